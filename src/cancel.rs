@@ -0,0 +1,64 @@
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// Token to cancel a job from another thread, like the cancel button of a UI.
+/// The clones share the same state, so cancelling any of them cancels all.
+///
+/// # Examples
+/// ```no_run
+/// use zip_archive::{Archiver, CancellationToken};
+///
+/// let token = CancellationToken::new();
+/// let mut archiver = Archiver::new();
+/// archiver.push("origin/dir1");
+/// archiver.set_destination("dest");
+/// archiver.set_cancellation_token(token.clone());
+///
+/// std::thread::spawn(move || token.cancel());
+/// archiver.archive().unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the jobs of the token.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Check whether the token is cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Get the error which stops the reading of an archive, if the token is cancelled.
+    pub(crate) fn check(&self) -> io::Result<()> {
+        match self.is_cancelled() {
+            true => Err(io::Error::new(io::ErrorKind::Interrupted, "Cancelled!")),
+            false => Ok(()),
+        }
+    }
+
+    /// Check whether the error is the one of [`check`](CancellationToken::check).
+    pub(crate) fn is_cancelled_error(&self, error: &io::Error) -> bool {
+        self.is_cancelled() && error.kind() == io::ErrorKind::Interrupted
+    }
+}
+
+/// Share the flag, so storing `true` to it cancels the token.
+impl From<Arc<AtomicBool>> for CancellationToken {
+    fn from(cancelled: Arc<AtomicBool>) -> Self {
+        CancellationToken { cancelled }
+    }
+}
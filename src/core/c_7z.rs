@@ -1,55 +1,444 @@
-use std::io;
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, File};
 use std::io::ErrorKind;
+use std::io::{self, Read};
 use std::path::{Path, PathBuf};
-use subprocess::Exec;
+use std::time::Duration;
+use subprocess::{Exec, ExitStatus, NullFile, Redirection};
 
-use crate::extra::get_7z_executable_path;
+use crate::extra::{get_7z_executable_path, get_file_list, unique_temp_name, walk_files};
+use crate::ignore::IgnoreFile;
+use crate::{CancellationToken, Format};
 
-use super::Compress;
+use super::{
+    entry_name, output_path, write_output, Compress, CompressOptions, EntryVisitor,
+    OverwritePolicy, SkipReason,
+};
+
+/// Get the command of the 7z executable at `program`, with the environment variables
+/// and the working directory of `options`.
+pub(super) fn command(program: &Path, options: &CompressOptions) -> Exec {
+    let mut exec = Exec::cmd(program);
+    if let Some(working_dir) = &options.working_dir {
+        exec = exec.cwd(working_dir);
+    }
+    for (key, value) in &options.env_7z {
+        exec = exec.env(key, value);
+    }
+    exec
+}
+
+/// Run the 7z executable and wait for it to exit.
+fn join(exec: Exec) -> Result<(), io::Error> {
+    match exec.join() {
+        Ok(_) => Ok(()),
+        Err(e) => Err(io::Error::new(
+            io::ErrorKind::BrokenPipe,
+            format!("Cannot execute subprocess!: {}", e),
+        )),
+    }
+}
+
+/// Check the 7z archive with the `t` command of the 7z executable.
+pub fn verify_7z(zip_path: &Path) -> Result<(), io::Error> {
+    let status = Exec::cmd(get_7z_executable_path()?)
+        .arg("t")
+        .arg(zip_path)
+        .stdout(NullFile)
+        .join()
+        .map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                format!("Cannot execute subprocess!: {}", e),
+            )
+        })?;
+    if !status.success() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "The 7z archive is broken!",
+        ));
+    }
+    Ok(())
+}
+
+/// Read each file of the 7z archive with `visit`.
+///
+/// The 7z executable cannot stream the entries, so the archive is extracted
+/// to a temporary directory first, which is removed afterward.
+/// So it needs the free space for the whole contents.
+/// The extraction stops once `cancel` is cancelled, like [`extract_7z_matching`].
+pub fn each_7z_entry(
+    zip_path: &Path,
+    cancel: &CancellationToken,
+    visit: &mut EntryVisitor,
+) -> io::Result<()> {
+    let temp_dir = env::temp_dir().join(unique_temp_name(
+        "zip_archive",
+        zip_path.file_name().unwrap_or_default(),
+    ));
+    let result = extract_7z(zip_path, &temp_dir, cancel).and_then(|_| {
+        walk_files(&temp_dir, |path| {
+            let name = path.strip_prefix(&temp_dir).unwrap_or(path);
+            visit(name, &mut File::open(path)?)
+        })
+    });
+    if temp_dir.exists() {
+        fs::remove_dir_all(&temp_dir)?;
+    }
+    result
+}
+
+/// Extract the 7z archive into `dir` with the `x` command of the 7z executable.
+fn extract_7z(zip_path: &Path, dir: &Path, cancel: &CancellationToken) -> io::Result<()> {
+    extract_7z_matching(zip_path, dir, &[], cancel)
+}
+
+/// Run the 7z executable and wait for it to exit,
+/// killing it with the error of [`CancellationToken::check`] once `cancel` is cancelled.
+fn join_cancellable(exec: Exec, cancel: &CancellationToken) -> io::Result<ExitStatus> {
+    let to_io_error = |e: subprocess::PopenError| {
+        io::Error::new(
+            io::ErrorKind::BrokenPipe,
+            format!("Cannot execute subprocess!: {}", e),
+        )
+    };
+    let mut popen = exec.popen().map_err(to_io_error)?;
+    loop {
+        if let Err(e) = cancel.check() {
+            popen.kill().ok();
+            popen.wait().map_err(to_io_error)?;
+            return Err(e);
+        }
+        if let Some(status) = popen
+            .wait_timeout(Duration::from_millis(50))
+            .map_err(to_io_error)?
+        {
+            return Ok(status);
+        }
+    }
+}
+
+/// Extract the files of the 7z archive matching the wildcards into `dir`,
+/// or all of them if there are no wildcards.
+/// The wildcards without `/` are matched against the file names in all directories.
+/// The 7z executable is killed once `cancel` is cancelled, leaving the files extracted so far.
+pub fn extract_7z_matching(
+    zip_path: &Path,
+    dir: &Path,
+    wildcards: &[&str],
+    cancel: &CancellationToken,
+) -> io::Result<()> {
+    let mut exec = Exec::cmd(get_7z_executable_path()?)
+        .arg("x")
+        .arg("-y")
+        .arg(format!("-o{}", dir.display()))
+        .arg(zip_path);
+    for wildcard in wildcards {
+        exec = match wildcard.contains('/') {
+            true => exec.arg(wildcard),
+            false => exec.arg(format!("-ir!{}", wildcard)),
+        };
+    }
+    let status = join_cancellable(exec.stdout(NullFile), cancel)?;
+    if !status.success() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Cannot extract the 7z archive!",
+        ));
+    }
+    Ok(())
+}
+
+/// Get the percentage of a token printed by the 7z executable with `-bsp1`, like `42%`.
+fn parse_percent(token: &str) -> Option<u8> {
+    token
+        .strip_suffix('%')?
+        .parse::<u8>()
+        .ok()
+        .filter(|p| *p <= 100)
+}
+
+/// Run the 7z executable, passing each new percentage it prints to `on_percent`,
+/// and wait for it to exit.
+/// The progress line is rewritten with backspaces, so the output is split into tokens by them
+/// as well as whitespaces. The tokens which are not percentages are ignored.
+fn join_with_progress<F: FnMut(u8)>(exec: Exec, mut on_percent: F) -> Result<(), io::Error> {
+    let to_io_error = |e| {
+        io::Error::new(
+            io::ErrorKind::BrokenPipe,
+            format!("Cannot execute subprocess!: {}", e),
+        )
+    };
+    let mut popen = exec
+        .stdout(Redirection::Pipe)
+        .popen()
+        .map_err(to_io_error)?;
+    if let Some(mut stdout) = popen.stdout.take() {
+        let mut buf = [0; 1024];
+        let mut pending = String::new();
+        let mut last = None;
+        let is_separator = |c: char| c.is_whitespace() || c == '\u{8}';
+        loop {
+            let n = stdout.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            pending.push_str(&String::from_utf8_lossy(&buf[..n]));
+            // The last token may be cut in the middle, so keep it for the next read.
+            let split = pending.rfind(is_separator).map_or(0, |i| i + 1);
+            for token in pending[..split].split(is_separator) {
+                if let Some(percent) = parse_percent(token) {
+                    if last != Some(percent) {
+                        last = Some(percent);
+                        on_percent(percent);
+                    }
+                }
+            }
+            pending.drain(..split);
+        }
+    }
+    popen.wait().map_err(to_io_error)?;
+    Ok(())
+}
+
+/// Compress the `files` into the 7z archive at `zip_path`, with the entry names relative to the entry base.
+/// The 7z executable runs in the entry base to get the relative names.
+pub fn compress_files(
+    files: &[PathBuf],
+    base: &Path,
+    zip_path: &Path,
+    options: &CompressOptions,
+) -> Result<(), io::Error> {
+    let compressor_path = get_7z_executable_path()?;
+    let zip_path = env::current_dir()?.join(zip_path);
+    let names = files
+        .iter()
+        .map(|file| entry_name(file, file, options))
+        .collect::<Result<Vec<_>, io::Error>>()?;
+
+    write_output(&zip_path, options, |path| {
+        let exec = command(&compressor_path, options)
+            .cwd(base)
+            .args(&file_list_arguments(path, &names, options));
+        join(exec)?;
+        if options.fsync {
+            File::open(path)?.sync_all()?;
+        }
+        Ok(())
+    })
+}
+
+/// Build the arguments of the 7z executable to archive the files of the entry `names` to `zip_path`,
+/// in the level of `options`.
+fn file_list_arguments(
+    zip_path: &Path,
+    names: &[PathBuf],
+    options: &CompressOptions,
+) -> Vec<OsString> {
+    let mut args: Vec<OsString> = vec![
+        "a".into(),
+        format!("-mx={}", options.level_7z).into(),
+        "-t7z".into(),
+        zip_path.into(),
+    ];
+    args.extend(names.iter().map(|name| name.into()));
+    args
+}
+
+/// Build the arguments of the 7z executable to archive the `origins` to `zip_path`.
+/// All of them are put in the single archive, each under its own name.
+///
+/// The origins are passed as the absolute paths, so 7z names the entries from the last component of each,
+/// without the `./` which some versions keep from a relative path.
+/// The archive path is made absolute too if 7z runs in the working directory.
+fn arguments(
+    zip_path: &Path,
+    origins: &[&Path],
+    options: &CompressOptions,
+) -> Result<Vec<String>, io::Error> {
+    let current_dir = env::current_dir()?;
+    let zip_path = match options.working_dir {
+        Some(_) => current_dir.join(zip_path),
+        None => zip_path.to_path_buf(),
+    };
+    let mut args = vec![
+        "a".to_string(),
+        format!("-mx={}", options.level_7z),
+        "-t7z".to_string(),
+        zip_path.to_str().unwrap().to_string(),
+    ];
+    for origin in origins {
+        match current_dir.join(origin).to_str() {
+            None => {
+                return Err(io::Error::new(
+                    ErrorKind::NotFound,
+                    "Cannot get the destination directory path!",
+                ))
+            }
+            Some(s) => args.push(s.to_string()),
+        }
+    }
+    for name in &options.excluded_dir_names {
+        args.push(format!("-xr!{}", name));
+    }
+    if options.progress_7z {
+        args.push("-bsp1".to_string());
+    }
+    Ok(args)
+}
+
+/// Get the path of the 7z archive of `origin` in `dest`.
+fn zip_path(origin: &Path, dest: &Path) -> PathBuf {
+    let mut zip_path = dest.join(match origin.file_name() {
+        Some(p) => p,
+        _ => origin.as_os_str(),
+    });
+    zip_path.set_extension("7z");
+    zip_path
+}
+
+/// Join the program and the arguments into a command line, redacting the password of `-p`.
+fn format_command_line(program: &Path, args: &[String]) -> String {
+    let mut command_line = program.display().to_string();
+    for arg in args {
+        command_line.push(' ');
+        if arg.starts_with("-p") && arg.len() > 2 {
+            command_line.push_str("-p***");
+        } else {
+            command_line.push_str(arg);
+        }
+    }
+    command_line
+}
 
 pub struct Compress7z;
 
-impl Compress for Compress7z {
-    fn compress<T: AsRef<Path>, O: AsRef<Path>>(origin: T, dest: O) -> Result<PathBuf, io::Error> {
-        let compressor_path = get_7z_executable_path()?;
+impl Compress7z {
+    /// Get the command line executed to archive `origin` into `dest`, with the password redacted.
+    pub fn command_line<T: AsRef<Path>, O: AsRef<Path>>(
+        origin: T,
+        dest: O,
+        options: &CompressOptions,
+    ) -> Result<String, io::Error> {
+        let path = output_path(&zip_path(origin.as_ref(), dest.as_ref()), options);
+        let args = arguments(&path, &[origin.as_ref()], options)?;
+        Ok(format_command_line(&get_7z_executable_path()?, &args))
+    }
 
-        let mut zip_path = dest.as_ref().join(&match origin.as_ref().file_name() {
-            Some(p) => p,
-            _ => origin.as_ref().as_os_str(),
-        });
-        zip_path.set_extension("7z");
+    /// Get the command line executed to archive all `origins` into the single archive at `zip_path`,
+    /// with the password redacted.
+    pub fn combined_command_line(
+        origins: &[PathBuf],
+        zip_path: &Path,
+        options: &CompressOptions,
+    ) -> Result<String, io::Error> {
+        let origins = origins.iter().map(PathBuf::as_path).collect::<Vec<_>>();
+        let args = arguments(&output_path(zip_path, options), &origins, options)?;
+        Ok(format_command_line(&get_7z_executable_path()?, &args))
+    }
 
-        if zip_path.is_file() {
+    /// Compress all `origins` into the single archive at `zip_path` with one execution of 7z,
+    /// each under its own name. So the solid blocks span the directories.
+    pub fn compress_combined(
+        origins: &[PathBuf],
+        zip_path: &Path,
+        options: &CompressOptions,
+    ) -> Result<PathBuf, io::Error> {
+        if zip_path.is_file() && !replace_existing(zip_path, origins, options)? {
+            return Ok(zip_path.to_path_buf());
+        }
+        let compressor_path = get_7z_executable_path()?;
+        if !options.excluded_dir_names.is_empty() {
+            // Record the directories excluded by 7z.
+            for origin in origins {
+                get_file_list(origin, options)?;
+            }
+        }
+        let origins = origins.iter().map(PathBuf::as_path).collect::<Vec<_>>();
+        write_output(zip_path, options, |path| {
+            join(command(&compressor_path, options).args(&arguments(path, &origins, options)?))?;
+            if options.fsync {
+                File::open(path)?.sync_all()?;
+            }
+            Ok(())
+        })?;
+        Ok(zip_path.to_path_buf())
+    }
+}
+
+/// Handle the existing archive at `zip_path` by the overwrite policy.
+/// Returns `false` if the `origins` are skipped, keeping the archive.
+pub(super) fn replace_existing(
+    zip_path: &Path,
+    origins: &[PathBuf],
+    options: &CompressOptions,
+) -> Result<bool, io::Error> {
+    match options.overwrite {
+        // 7z adds the files to the existing archive, so remove it to replace it.
+        OverwritePolicy::Overwrite => fs::remove_file(zip_path)?,
+        OverwritePolicy::Skip => {
+            for origin in origins {
+                options.skip(origin, SkipReason::ArchiveExists);
+            }
+            return Ok(false);
+        }
+        OverwritePolicy::Error => {
             return Err(io::Error::new(
                 ErrorKind::AlreadyExists,
                 "The 7z archive file already exists!",
-            ));
-        }
-
-        let exec = Exec::cmd(compressor_path).args(&vec![
-            "a",
-            "-mx=9",
-            "-t7z",
-            zip_path.to_str().unwrap(),
-            match PathBuf::from("./").join(origin).to_str() {
-                None => {
-                    return Err(io::Error::new(
-                        ErrorKind::NotFound,
-                        "Cannot get the destination directory path!",
-                    ))
-                }
-                Some(s) => s,
-            },
-        ]);
-        match exec.join() {
-            Ok(_) => (),
-            Err(e) => {
-                return Err(io::Error::new(
-                    io::ErrorKind::BrokenPipe,
-                    format!("Cannot execute subprocess!: {}", e),
-                ))
+            ))
+        }
+    }
+    Ok(true)
+}
+
+impl Compress for Compress7z {
+    fn compress<T: AsRef<Path>, O: AsRef<Path>>(
+        origin: T,
+        dest: O,
+        options: &CompressOptions,
+    ) -> Result<PathBuf, io::Error> {
+        let zip_path = zip_path(origin.as_ref(), dest.as_ref());
+
+        if zip_path.is_file()
+            && !replace_existing(&zip_path, &[origin.as_ref().to_path_buf()], options)?
+        {
+            return Ok(zip_path);
+        }
+
+        if options.skip_empty_files || IgnoreFile::for_root(origin.as_ref(), options)?.is_some() {
+            // 7z cannot read the ignore file or filter the files by their sizes,
+            // so pass it the files left after the filters.
+            let files = get_file_list(&origin, options)?;
+            let base = origin.as_ref().parent().unwrap_or_else(|| Path::new(""));
+            return super::compress_files(&files, base, &zip_path, &Format::_7z, options);
+        }
+
+        let compressor_path = get_7z_executable_path()?;
+
+        if !options.excluded_dir_names.is_empty() {
+            // Record the directories excluded by 7z.
+            get_file_list(&origin, options)?;
+        }
+
+        write_output(&zip_path, options, |path| {
+            let exec = command(&compressor_path, options).args(&arguments(
+                path,
+                &[origin.as_ref()],
+                options,
+            )?);
+            match &options.observer {
+                Some(observer) if options.progress_7z => join_with_progress(exec, |percent| {
+                    observer.on_percent(origin.as_ref(), percent)
+                })?,
+                _ => join(exec)?,
             }
-        };
+            if options.fsync {
+                File::open(path)?.sync_all()?;
+            }
+            Ok(())
+        })?;
         return Ok(zip_path);
     }
 }
@@ -59,6 +448,7 @@ mod test {
     use function_name::named;
 
     use crate::core::test_util::{cleanup, setup, Dir};
+    use crate::preflight::unavailable_reason;
 
     use super::*;
 
@@ -66,9 +456,258 @@ mod test {
     #[named]
     fn compress_to_7z_test() {
         let Dir { mut origin, dest } = setup(function_name!());
-        Compress7z::compress(origin.as_path(), dest.as_path()).unwrap();
+        Compress7z::compress(
+            origin.as_path(),
+            dest.as_path(),
+            &CompressOptions::default(),
+        )
+        .unwrap();
         origin.set_extension("7z");
         assert!(dest.join(origin).is_file());
         cleanup(function_name!());
     }
+
+    #[test]
+    fn combined_command_line_test() {
+        let origins = vec![PathBuf::from("origin/dir1"), PathBuf::from("origin/dir2")];
+        let command_line = Compress7z::combined_command_line(
+            &origins,
+            Path::new("dest/all.7z"),
+            &CompressOptions::default(),
+        )
+        .unwrap();
+        let current_dir = env::current_dir().unwrap();
+        assert!(command_line.ends_with(&format!(
+            " a -mx=9 -t7z dest/all.7z {} {}",
+            current_dir.join("origin/dir1").display(),
+            current_dir.join("origin/dir2").display()
+        )));
+    }
+
+    #[test]
+    fn arguments_test() {
+        let options = CompressOptions {
+            excluded_dir_names: vec!["node_modules".to_string(), ".git".to_string()],
+            ..Default::default()
+        };
+        let args = arguments(Path::new("dest/dir1.7z"), &[Path::new("dir1")], &options).unwrap();
+
+        assert_eq!(
+            args,
+            vec![
+                "a",
+                "-mx=9",
+                "-t7z",
+                "dest/dir1.7z",
+                env::current_dir().unwrap().join("dir1").to_str().unwrap(),
+                "-xr!node_modules",
+                "-xr!.git"
+            ]
+        );
+    }
+
+    #[test]
+    fn file_list_arguments_test() {
+        let options = CompressOptions {
+            level_7z: 1,
+            ..Default::default()
+        };
+        let args = file_list_arguments(
+            Path::new("dest/dir1.7z"),
+            &[PathBuf::from("dir1/file3.png")],
+            &options,
+        );
+        assert_eq!(
+            args,
+            ["a", "-mx=1", "-t7z", "dest/dir1.7z", "dir1/file3.png"]
+        );
+    }
+
+    #[test]
+    #[named]
+    fn compress_7z_ignore_file_level_test() {
+        if let Some(reason) = unavailable_reason(&Format::_7z) {
+            println!("Skipped: {}", reason);
+            return;
+        }
+        let Dir { origin, dest } = setup(function_name!());
+        let dir = origin.join("dir3");
+        fs::write(dir.join(".archiveignore"), "*.txt\n").unwrap();
+        let options = CompressOptions {
+            honor_ignore_files: true,
+            level_7z: 0,
+            ..Default::default()
+        };
+        let zip_path = Compress7z::compress(&dir, &dest, &options).unwrap();
+
+        // The level 0 stores the files without compression.
+        let listing = Exec::cmd(get_7z_executable_path().unwrap())
+            .args(&["l", "-slt"])
+            .arg(&zip_path)
+            .capture()
+            .unwrap()
+            .stdout_str();
+        assert!(!listing.contains("file7.txt"));
+        let methods = listing
+            .lines()
+            .skip_while(|line| !line.starts_with("----------"))
+            .filter_map(|line| line.strip_prefix("Method = "))
+            .filter(|method| !method.is_empty())
+            .collect::<Vec<_>>();
+        assert_eq!(methods, ["Copy"; 3]);
+        cleanup(function_name!());
+    }
+
+    #[test]
+    fn format_command_line_test() {
+        let args = ["a", "-mx=9", "-psecret", "-p", "dest/dir1.7z", "./dir1"].map(String::from);
+        assert_eq!(
+            format_command_line(Path::new("7zz"), &args),
+            "7zz a -mx=9 -p*** -p dest/dir1.7z ./dir1"
+        );
+    }
+
+    #[test]
+    #[named]
+    fn compress_7z_overwrite_test() {
+        if let Some(reason) = unavailable_reason(&Format::_7z) {
+            println!("Skipped: {}", reason);
+            return;
+        }
+        let Dir { origin, dest } = setup(function_name!());
+        let options = CompressOptions {
+            overwrite: OverwritePolicy::Overwrite,
+            ..Default::default()
+        };
+        let first = Compress7z::compress(origin.join("dir1"), &dest, &options).unwrap();
+        let second = Compress7z::compress(origin.join("dir1"), &dest, &options).unwrap();
+        assert_eq!(first, second);
+        assert!(second.is_file());
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn compress_7z_existing_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        fs::write(dest.join("dir1.7z"), "existing").unwrap();
+
+        let error = Compress7z::compress(origin.join("dir1"), &dest, &CompressOptions::default())
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+
+        let options = CompressOptions {
+            overwrite: OverwritePolicy::Skip,
+            ..Default::default()
+        };
+        let path = Compress7z::compress(origin.join("dir1"), &dest, &options).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "existing");
+        assert_eq!(
+            options.skipped.to_vec(),
+            vec![(origin.join("dir1"), SkipReason::ArchiveExists)]
+        );
+        cleanup(function_name!());
+    }
+
+    #[test]
+    fn parse_percent_test() {
+        assert_eq!(parse_percent("42%"), Some(42));
+        assert_eq!(parse_percent("100%"), Some(100));
+        assert_eq!(parse_percent("101%"), None);
+        assert_eq!(parse_percent("%"), None);
+        assert_eq!(parse_percent("dir1/file3.png"), None);
+    }
+
+    #[test]
+    #[named]
+    fn compress_7z_progress_test() {
+        use std::sync::{Arc, Mutex};
+
+        if let Some(reason) = unavailable_reason(&Format::_7z) {
+            println!("Skipped: {}", reason);
+            return;
+        }
+
+        struct Percents(Mutex<Vec<u8>>);
+        impl crate::ArchiveObserver for Arc<Percents> {
+            fn on_percent(&self, _dir: &Path, percent: u8) {
+                self.0.lock().unwrap().push(percent);
+            }
+        }
+
+        let Dir { origin, dest } = setup(function_name!());
+        let percents = Arc::new(Percents(Mutex::new(Vec::new())));
+        let options = CompressOptions {
+            progress_7z: true,
+            observer: Some(Arc::new(Arc::clone(&percents))),
+            ..Default::default()
+        };
+        Compress7z::compress(origin.join("dir3"), &dest, &options).unwrap();
+        assert!(!percents.0.lock().unwrap().is_empty());
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn command_env_test() {
+        let mut options = CompressOptions::default();
+        options
+            .env_7z
+            .insert("ZIP_ARCHIVE_TEST_ENV".to_string(), "honored".to_string());
+        let output = command(Path::new("sh"), &options)
+            .args(&["-c", "printf %s \"$ZIP_ARCHIVE_TEST_ENV\" \"$PATH\""])
+            .capture()
+            .unwrap();
+        assert!(output.success());
+        // The parent environment, like `PATH`, is still inherited.
+        assert!(output.stdout_str().starts_with("honored"));
+        assert!(output.stdout_str().len() > "honored".len());
+    }
+
+    #[test]
+    #[named]
+    fn compress_7z_entry_names_test() {
+        if let Some(reason) = unavailable_reason(&Format::_7z) {
+            println!("Skipped: {}", reason);
+            return;
+        }
+        let Dir { origin, dest } = setup(function_name!());
+        let zip_path =
+            Compress7z::compress(origin.join("dir1"), &dest, &CompressOptions::default()).unwrap();
+
+        // The technical listing has a `Path = ` line per entry after the separator.
+        let listing = Exec::cmd(get_7z_executable_path().unwrap())
+            .args(&["l", "-slt"])
+            .arg(&zip_path)
+            .capture()
+            .unwrap()
+            .stdout_str();
+        let mut names = listing
+            .lines()
+            .skip_while(|line| !line.starts_with("----------"))
+            .filter_map(|line| line.strip_prefix("Path = "))
+            .map(|name| name.replace('\\', "/"))
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["dir1", "dir1/file3.png", "dir1/file5.webp"]);
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn join_cancellable_test() {
+        let token = CancellationToken::new();
+        let cancel = token.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            cancel.cancel();
+        });
+        let start = std::time::Instant::now();
+        let error = join_cancellable(Exec::cmd("sleep").arg("10"), &token).unwrap_err();
+        assert!(token.is_cancelled_error(&error));
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let status = join_cancellable(Exec::cmd("true"), &CancellationToken::new()).unwrap();
+        assert!(status.success());
+    }
 }
@@ -1,23 +1,444 @@
-use std::io;
+use std::io::{self, Read};
 use std::{
-    fs::File,
-    io::ErrorKind,
+    collections::HashMap,
+    fs::{self, File},
+    io::{ErrorKind, Write},
     path::{Path, PathBuf},
+    sync::OnceLock,
 };
 
-use tar::Builder;
+use tar::{Archive, Builder, EntryType, GnuExtSparseHeader, Header};
 
-use super::Compress;
+use crate::digest::content_hash;
+use crate::extra::{is_empty_file, is_excluded_dir};
+use crate::ignore::IgnoreFile;
+use crate::sparse::{data_regions, Region, RegionReader};
+
+use super::{
+    entry_name, strip_entry_name, write_output, Compress, CompressOptions, EntryVisitor, SkipReason,
+};
+
+/// Entry names of the files appended to the tarball, by their size and content hash.
+type Appended = HashMap<(u64, Vec<u8>), PathBuf>;
+
+/// Names of the users and the groups by their IDs, read once from `/etc/passwd` and `/etc/group`.
+#[cfg(unix)]
+static USER_NAMES: OnceLock<HashMap<u32, String>> = OnceLock::new();
+#[cfg(unix)]
+static GROUP_NAMES: OnceLock<HashMap<u32, String>> = OnceLock::new();
+
+/// Get the names by the IDs in the file of the `name:password:id:...` lines, like `/etc/passwd`.
+#[cfg(unix)]
+fn id_names<'a>(path: &str, names: &'a OnceLock<HashMap<u32, String>>) -> &'a HashMap<u32, String> {
+    names.get_or_init(|| {
+        fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split(':');
+                let name = fields.next()?;
+                let id = fields.nth(1)?.parse().ok()?;
+                Some((id, name.to_string()))
+            })
+            .collect()
+    })
+}
+
+/// Record the uid and the gid of the file with the user and group names where they are found,
+/// if the ownership is preserved. Otherwise both IDs are 0 and the names are left empty.
+#[cfg(unix)]
+fn set_ownership(
+    header: &mut Header,
+    metadata: &fs::Metadata,
+    options: &CompressOptions,
+) -> io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    if !options.preserve_ownership {
+        header.set_uid(0);
+        header.set_gid(0);
+        return Ok(());
+    }
+    header.set_uid(metadata.uid() as u64);
+    header.set_gid(metadata.gid() as u64);
+    if let Some(name) = id_names("/etc/passwd", &USER_NAMES).get(&metadata.uid()) {
+        header.set_username(name)?;
+    }
+    if let Some(name) = id_names("/etc/group", &GROUP_NAMES).get(&metadata.gid()) {
+        header.set_groupname(name)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_ownership(
+    _header: &mut Header,
+    _metadata: &fs::Metadata,
+    _options: &CompressOptions,
+) -> io::Result<()> {
+    Ok(())
+}
+
+/// Get the GNU header of the file or the directory with its metadata.
+fn header_of(metadata: &fs::Metadata, options: &CompressOptions) -> io::Result<Header> {
+    let mut header = Header::new_gnu();
+    header.set_metadata(metadata);
+    set_ownership(&mut header, metadata, options)?;
+    Ok(header)
+}
+
+/// Append the `file` opened from `path` to the tarball under `name`, with its content.
+fn append_path<W: Write>(
+    builder: &mut Builder<W>,
+    name: &Path,
+    path: &Path,
+    file: File,
+    options: &CompressOptions,
+) -> io::Result<()> {
+    let mut header = header_of(&path.metadata()?, options)?;
+    if options.sparse {
+        if let Some(regions) = data_regions(&file)? {
+            return append_sparse(builder, header, name, &file, &regions);
+        }
+    }
+    builder.append_data(&mut header, name, file)
+}
+
+/// Append the sparse file to the tarball under `name` as a GNU sparse entry, with only its data regions.
+/// The regions which do not fit in the header are listed in the extended headers after it.
+fn append_sparse<W: Write>(
+    builder: &mut Builder<W>,
+    mut header: Header,
+    name: &Path,
+    file: &File,
+    regions: &[Region],
+) -> io::Result<()> {
+    header.set_entry_type(EntryType::GNUSparse);
+    header.set_size(regions.iter().map(|(_, len)| len).sum());
+    let gnu_header = header.as_gnu_mut().unwrap();
+    gnu_header.set_real_size(file.metadata()?.len());
+    for (sparse, (offset, len)) in gnu_header.sparse.iter_mut().zip(regions) {
+        sparse.set_offset(*offset);
+        sparse.set_length(*len);
+    }
+    let in_header = gnu_header.sparse.len();
+    gnu_header.set_is_extended(regions.len() > in_header);
+
+    let mut extended = Vec::new();
+    let mut rest = regions.iter().skip(in_header).peekable();
+    while rest.peek().is_some() {
+        let mut ext_header = GnuExtSparseHeader::new();
+        for (sparse, (offset, len)) in ext_header.sparse_mut().iter_mut().zip(rest.by_ref()) {
+            sparse.set_offset(*offset);
+            sparse.set_length(*len);
+        }
+        ext_header.set_is_extended(rest.peek().is_some());
+        extended.extend_from_slice(ext_header.as_bytes());
+    }
+    let data = extended.as_slice().chain(RegionReader::new(file, regions));
+    builder.append_data(&mut header, name, data)
+}
+
+/// Append the file to the tarball under `name`, and get whether it is appended.
+/// If dedup is enabled and a file with the same content is already appended,
+/// the file is stored as a hardlink to it.
+/// The file which cannot be opened is recorded as skipped instead.
+///
+/// The entries use the GNU headers, so the names and the link targets over 100 bytes
+/// are stored losslessly in the long-name entries instead of being truncated.
+fn append_file<W: Write>(
+    builder: &mut Builder<W>,
+    name: &Path,
+    path: &Path,
+    options: &CompressOptions,
+    appended: &mut Appended,
+) -> io::Result<bool> {
+    // The transformed content is buffered and appended as is, without dedup.
+    if options.content_transform.is_some() {
+        let content = match options
+            .read_file(path)
+            .and_then(|content| options.transform(path, content))
+        {
+            Some(content) => content,
+            None => return Ok(false),
+        };
+        let mut header = header_of(&path.metadata()?, options)?;
+        header.set_size(content.len() as u64);
+        builder.append_data(&mut header, name, content.as_slice())?;
+        return Ok(true);
+    }
+    let file = match options.open_file(path) {
+        Some(file) => file,
+        None => return Ok(false),
+    };
+    if !options.dedup {
+        append_path(builder, name, path, file, options)?;
+        return Ok(true);
+    }
+    let metadata = path.metadata()?;
+    let key = (metadata.len(), content_hash(path, options.dedup_hash)?);
+    match appended.get(&key) {
+        Some(target) => {
+            let mut header = header_of(&metadata, options)?;
+            header.set_entry_type(EntryType::Link);
+            header.set_size(0);
+            builder.append_link(&mut header, name, target)?;
+        }
+        None => {
+            append_path(builder, name, path, file, options)?;
+            appended.insert(key, name.to_path_buf());
+        }
+    }
+    Ok(true)
+}
+
+/// Append the directory and all its contents to the tarball under `name`,
+/// skipping the subdirectories whose name is excluded.
+/// If `name` is empty, the contents are appended at the root of the tarball.
+fn append_dir_filtered<W: Write>(
+    builder: &mut Builder<W>,
+    name: &Path,
+    dir: &Path,
+    options: &CompressOptions,
+    ignore: Option<&IgnoreFile>,
+    appended: &mut Appended,
+) -> io::Result<()> {
+    if let Some(stripped) = strip_entry_name(name, options) {
+        let mut header = header_of(&dir.metadata()?, options)?;
+        builder.append_data(&mut header, stripped, io::empty())?;
+    }
+    for entry in dir.read_dir()? {
+        let path = entry?.path();
+        let entry_name = name.join(path.file_name().unwrap());
+        if ignore.is_some_and(|ignore| ignore.is_ignore_file(&path)) {
+            continue;
+        }
+        if ignore.is_some_and(|ignore| ignore.is_ignored(&path, path.is_dir())) {
+            options.skip(&path, SkipReason::Excluded);
+        } else if path.is_dir() {
+            if is_excluded_dir(&path, &options.excluded_dir_names) {
+                options.skip(&path, SkipReason::Excluded);
+            } else {
+                append_dir_filtered(builder, &entry_name, &path, options, ignore, appended)?;
+            }
+        } else if options.skip_empty_files && is_empty_file(&path, options.follow_symlinks)? {
+            options.skip(&path, SkipReason::EmptyFile);
+        } else if let Some(stripped) = strip_entry_name(&entry_name, options) {
+            if append_file(builder, &stripped, &path, options, appended)? {
+                options.progress(&path, path.metadata()?.len());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write the tarball of `origin` to `writer` and finish it.
+/// Returns the writer after the end-of-archive blocks are written.
+pub fn write_tar<W: Write>(writer: W, origin: &Path, options: &CompressOptions) -> io::Result<W> {
+    let mut tar_builder = Builder::new(writer);
+    let root_name = if options.flat_tar {
+        PathBuf::new()
+    } else {
+        entry_name(origin, origin, options)?
+    };
+    let mut appended = Appended::new();
+    let ignore = IgnoreFile::for_root(origin, options)?;
+    append_dir_filtered(
+        &mut tar_builder,
+        &root_name,
+        origin,
+        options,
+        ignore.as_ref(),
+        &mut appended,
+    )?;
+    tar_builder.into_inner()
+}
+
+/// Write the tarball of the `files` to `tar_path`, with the entry names relative to the entry base.
+/// It is the intermediate tarball of the xz format, unlike [`write_tar_files_archive`].
+pub fn write_tar_files(
+    tar_path: &Path,
+    files: &[PathBuf],
+    options: &CompressOptions,
+) -> io::Result<()> {
+    tar_files(File::create(tar_path)?, files, options)?.flush()
+}
+
+/// Write the tarball of the `files` to `writer` and finish it, with the entry names relative to the entry base.
+pub fn tar_files<W: Write>(
+    writer: W,
+    files: &[PathBuf],
+    options: &CompressOptions,
+) -> io::Result<W> {
+    let mut tar_builder = Builder::new(writer);
+    let mut appended = Appended::new();
+    for file in files {
+        let name = match strip_entry_name(&entry_name(file, file, options)?, options) {
+            Some(name) => name,
+            None => continue,
+        };
+        if append_file(&mut tar_builder, &name, file, options, &mut appended)? {
+            options.progress(file, file.metadata()?.len());
+        }
+    }
+    tar_builder.into_inner()
+}
+
+/// Write the tarball of the `files` to `tar_path` as the final archive,
+/// with the entry names relative to the entry base.
+pub fn write_tar_files_archive(
+    tar_path: &Path,
+    files: &[PathBuf],
+    options: &CompressOptions,
+) -> io::Result<()> {
+    write_output(tar_path, options, |path| {
+        let tar_file = tar_files(File::create(path)?, files, options)?;
+        if options.fsync {
+            tar_file.sync_all()?;
+        }
+        Ok(())
+    })
+}
+
+/// Write the tarball of `origin` to `tar_path` as the final archive,
+/// unlike [`CompressTar`] which writes the intermediate tarball of the xz format.
+pub fn write_tar_archive(
+    tar_path: &Path,
+    origin: &Path,
+    options: &CompressOptions,
+) -> io::Result<()> {
+    write_output(tar_path, options, |path| {
+        let tar_file = write_tar(File::create(path)?, origin, options)?;
+        if options.fsync {
+            tar_file.sync_all()?;
+        }
+        Ok(())
+    })
+}
+
+/// Write the tarball of the `archives` to `tar_path`, each stored under its file name.
+pub fn write_bundle(
+    tar_path: &Path,
+    archives: &[PathBuf],
+    options: &CompressOptions,
+) -> io::Result<()> {
+    write_output(tar_path, options, |path| {
+        let mut tar_builder = Builder::new(File::create(path)?);
+        for archive in archives {
+            let name = archive.file_name().unwrap_or_default();
+            tar_builder.append_path_with_name(archive, name)?;
+        }
+        let tar_file = tar_builder.into_inner()?;
+        if options.fsync {
+            tar_file.sync_all()?;
+        }
+        Ok(())
+    })
+}
+
+/// Check that every entry of the tarball can be read back.
+pub fn verify_tar(tar_path: &Path) -> io::Result<()> {
+    let mut archive = Archive::new(File::open(tar_path)?);
+    for entry in archive.entries()? {
+        io::copy(&mut entry?, &mut io::sink())?;
+    }
+    Ok(())
+}
+
+/// Read each file of the tarball opened by `open` with `visit`.
+/// The sparse files are read with their holes filled, like the regular files.
+///
+/// The hardlinks, like the ones written by the dedup, are visited after the other files
+/// with the contents of their targets, read in a second pass over the tarball opened again.
+/// The directories, symlinks and other special entries are skipped.
+pub fn each_tar_entry<R, F>(mut open: F, visit: &mut EntryVisitor) -> io::Result<()>
+where
+    R: io::Read,
+    F: FnMut() -> io::Result<R>,
+{
+    // Names of the hardlinks by the names of their targets.
+    let mut links: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+    let mut archive = Archive::new(open()?);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.into_owned();
+        match entry.header().entry_type() {
+            EntryType::Regular | EntryType::Continuous | EntryType::GNUSparse => {
+                visit(&name, &mut entry)?
+            }
+            EntryType::Link => {
+                let target = entry.link_name()?.ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("The hardlink {} has no target!", name.display()),
+                    )
+                })?;
+                links.entry(target.into_owned()).or_default().push(name);
+            }
+            _ => {}
+        }
+    }
+    if links.is_empty() {
+        return Ok(());
+    }
+    let mut archive = Archive::new(open()?);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.into_owned();
+        if entry.header().entry_type() == EntryType::Link {
+            continue;
+        }
+        if let Some(names) = links.remove(&name) {
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content)?;
+            for name in names {
+                visit(&name, &mut content.as_slice())?;
+            }
+        }
+    }
+    match links.into_keys().next() {
+        Some(target) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "The target {} of a hardlink is not found!",
+                target.display()
+            ),
+        )),
+        None => Ok(()),
+    }
+}
 
 pub struct CompressTar;
 
-impl Compress for CompressTar {
-    fn compress<T: AsRef<Path>, O: AsRef<Path>>(origin: T, dest: O) -> Result<PathBuf, io::Error> {
-        let mut tar_path = dest.as_ref().join(&match origin.as_ref().file_name() {
+impl CompressTar {
+    /// Get the path of the tarball of `origin` in `dest`, like `dest/dir1.tar`.
+    pub fn tar_path<T: AsRef<Path>, O: AsRef<Path>>(origin: T, dest: O) -> PathBuf {
+        let mut tar_path = dest.as_ref().join(match origin.as_ref().file_name() {
             Some(p) => p,
             None => origin.as_ref().as_os_str(),
         });
         tar_path.set_extension("tar");
+        tar_path
+    }
+
+    /// Write the tarball of `origin` to `writer`, like an already open file or a pipe,
+    /// instead of a new file in the destination, and get the writer after the tarball is finished.
+    pub fn compress_to<T: AsRef<Path>, W: Write>(
+        origin: T,
+        writer: W,
+        options: &CompressOptions,
+    ) -> io::Result<W> {
+        write_tar(writer, origin.as_ref(), options)
+    }
+}
+
+impl Compress for CompressTar {
+    fn compress<T: AsRef<Path>, O: AsRef<Path>>(
+        origin: T,
+        dest: O,
+        options: &CompressOptions,
+    ) -> Result<PathBuf, io::Error> {
+        let tar_path = CompressTar::tar_path(&origin, dest);
 
         if tar_path.is_file() {
             return Err(io::Error::new(
@@ -27,8 +448,8 @@ impl Compress for CompressTar {
         }
 
         let tar_file = File::create(&tar_path)?;
-        let mut tar_builder = Builder::new(tar_file);
-        tar_builder.append_dir_all(origin.as_ref().file_name().unwrap(), origin.as_ref())?;
+        let mut tar_file = write_tar(tar_file, origin.as_ref(), options)?;
+        tar_file.flush()?;
 
         return Ok(tar_path);
     }
@@ -36,18 +457,270 @@ impl Compress for CompressTar {
 
 #[cfg(test)]
 mod tests {
+    use std::fs;
+
+    use std::sync::Arc;
+
     use function_name::named;
 
-    use crate::core::test_util::{cleanup, setup, Dir};
+    use crate::core::c_xz::CompressXz;
+    use crate::core::test_util::{cleanup, setup, tar_entries, tar_xz_entries, Dir};
 
     use super::*;
     #[test]
     #[named]
     fn make_tar_test() {
         let Dir { mut origin, dest } = setup(function_name!());
-        CompressTar::compress(origin.as_path(), dest.as_path()).unwrap();
+        CompressTar::compress(
+            origin.as_path(),
+            dest.as_path(),
+            &CompressOptions::default(),
+        )
+        .unwrap();
         origin.set_extension("tar");
         assert!(dest.join(origin).is_file());
         cleanup(function_name!());
     }
+
+    #[test]
+    #[named]
+    fn make_flat_tar_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let options = CompressOptions {
+            flat_tar: true,
+            ..Default::default()
+        };
+        let tar_path = CompressTar::compress(origin.join("dir1"), &dest, &options).unwrap();
+
+        let mut entries = tar_entries(tar_path);
+        entries.sort();
+        assert_eq!(entries, vec!["file3.png", "file5.webp"]);
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn make_tar_entry_base_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let options = CompressOptions {
+            entry_base: origin.parent().map(|p| p.to_path_buf()),
+            ..Default::default()
+        };
+        let tar_path = CompressTar::compress(origin.join("dir1"), &dest, &options).unwrap();
+
+        let mut entries = tar_entries(tar_path);
+        entries.sort();
+        let origin_name = origin.to_str().unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                format!("{}/dir1", origin_name),
+                format!("{}/dir1/file3.png", origin_name),
+                format!("{}/dir1/file5.webp", origin_name),
+            ]
+        );
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn write_tar_finish_test() {
+        let Dir { origin, dest: _ } = setup(function_name!());
+        let tar = write_tar(
+            Vec::new(),
+            &origin.join("dir1"),
+            &CompressOptions::default(),
+        )
+        .unwrap();
+
+        // A finished tarball ends with two zero-filled blocks.
+        assert_eq!(tar.len() % 512, 0);
+        assert!(tar[tar.len() - 1024..].iter().all(|&b| b == 0));
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn make_tar_dedup_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let dup = origin.join("dup");
+        fs::create_dir_all(dup.join("sub")).unwrap();
+        let content: Vec<u8> = (0..100_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        fs::write(dup.join("a.bin"), &content).unwrap();
+        fs::write(dup.join("b.bin"), &content).unwrap();
+        fs::write(dup.join("sub").join("c.bin"), &content).unwrap();
+        fs::write(dup.join("other.bin"), b"other").unwrap();
+
+        let plain_dest = dest.join("plain");
+        fs::create_dir(&plain_dest).unwrap();
+        let plain = CompressTar::compress(&dup, &plain_dest, &CompressOptions::default()).unwrap();
+        let options = CompressOptions {
+            dedup: true,
+            ..Default::default()
+        };
+        let dedup = CompressTar::compress(&dup, &dest, &options).unwrap();
+        assert!(fs::metadata(&dedup).unwrap().len() < fs::metadata(&plain).unwrap().len());
+
+        let unpacked = dest.join("unpacked");
+        Archive::new(File::open(&dedup).unwrap())
+            .unpack(&unpacked)
+            .unwrap();
+        for name in ["a.bin", "b.bin", "sub/c.bin"] {
+            assert_eq!(fs::read(unpacked.join("dup").join(name)).unwrap(), content);
+        }
+        assert_eq!(
+            fs::read(unpacked.join("dup").join("other.bin")).unwrap(),
+            b"other"
+        );
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn long_name_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let long = "a".repeat(60);
+        let deep = origin.join("dir1").join(&long).join(&long);
+        fs::create_dir_all(&deep).unwrap();
+        fs::write(deep.join(format!("{}.txt", long)), b"same").unwrap();
+        fs::write(deep.join(format!("{}.bak", long)), b"same").unwrap();
+        let options = CompressOptions {
+            dedup: true,
+            ..Default::default()
+        };
+
+        let tar_path = CompressTar::compress(origin.join("dir1"), &dest, &options).unwrap();
+        let xz_path = CompressXz::compress(&tar_path, &dest, &options).unwrap();
+
+        let prefix = format!("dir1/{}/{}", long, long);
+        let names = [
+            format!("{}/{}.bak", prefix, long),
+            format!("{}/{}.txt", prefix, long),
+        ];
+        for entries in [tar_entries(&tar_path), tar_xz_entries(&xz_path)] {
+            assert!(names.iter().all(|n| n.len() > 100 && entries.contains(n)));
+        }
+        let mut archive = Archive::new(File::open(&tar_path).unwrap());
+        let link = archive
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap())
+            .find(|e| e.header().entry_type() == EntryType::Link)
+            .unwrap();
+        let target = link
+            .link_name()
+            .unwrap()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(target.len() > 100 && names.contains(&target));
+
+        cleanup(function_name!());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[named]
+    fn preserve_ownership_test() {
+        use std::os::unix::fs::MetadataExt;
+
+        let Dir { origin, dest } = setup(function_name!());
+        let metadata = origin.join("dir1").join("file3.png").metadata().unwrap();
+        for preserve_ownership in [false, true] {
+            let options = CompressOptions {
+                preserve_ownership,
+                ..Default::default()
+            };
+            let tar_path = dest.join(format!("dir1_{}.tar", preserve_ownership));
+            write_tar_archive(&tar_path, &origin.join("dir1"), &options).unwrap();
+
+            let mut archive = Archive::new(File::open(&tar_path).unwrap());
+            for entry in archive.entries().unwrap() {
+                let entry = entry.unwrap();
+                let header = entry.header();
+                if !preserve_ownership {
+                    assert_eq!(header.uid().unwrap(), 0);
+                    assert_eq!(header.gid().unwrap(), 0);
+                    assert_eq!(header.username().unwrap(), Some(""));
+                    continue;
+                }
+                assert_eq!(header.uid().unwrap(), metadata.uid() as u64);
+                assert_eq!(header.gid().unwrap(), metadata.gid() as u64);
+                assert_eq!(
+                    header.username().unwrap(),
+                    id_names("/etc/passwd", &USER_NAMES)
+                        .get(&metadata.uid())
+                        .map(String::as_str)
+                        .or(Some(""))
+                );
+            }
+        }
+
+        cleanup(function_name!());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[named]
+    fn sparse_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let dir = origin.join("dir2");
+        let image = File::create(dir.join("disk.img")).unwrap();
+        image.set_len(64 << 20).unwrap();
+        std::os::unix::fs::FileExt::write_at(&image, b"data", 1 << 20).unwrap();
+        drop(image);
+
+        let options = CompressOptions {
+            sparse: true,
+            ..Default::default()
+        };
+        let tar_path = dest.join("dir2.tar");
+        write_tar_archive(&tar_path, &dir, &options).unwrap();
+        if data_regions(&File::open(dir.join("disk.img")).unwrap())
+            .unwrap()
+            .is_some()
+        {
+            assert!(tar_path.metadata().unwrap().len() < 1 << 20);
+        }
+
+        let unpacked = dest.join("unpacked");
+        Archive::new(File::open(&tar_path).unwrap())
+            .unpack(&unpacked)
+            .unwrap();
+        let content = fs::read(unpacked.join("dir2").join("disk.img")).unwrap();
+        assert_eq!(content.len(), 64 << 20);
+        assert_eq!(&content[1 << 20..(1 << 20) + 4], b"data");
+        assert_eq!(content.iter().filter(|b| **b != 0).count(), 4);
+        assert_eq!(
+            fs::read(unpacked.join("dir2").join("file6.webp")).unwrap(),
+            fs::read(dir.join("file6.webp")).unwrap()
+        );
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn write_tar_files_archive_atomic_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let files = [origin.join("dir3").join("file7.txt")];
+        let tar_path = dest.join("files.tar");
+        let final_path = tar_path.clone();
+        let options = CompressOptions {
+            atomic_output: true,
+            entry_base: Some(origin.clone()),
+            // The archive is written to the temporary file, so the final path does not exist yet.
+            content_transform: Some(Arc::new(move |_, content| match final_path.exists() {
+                true => Err(io::Error::other("written in place")),
+                false => Ok(content),
+            })),
+            ..Default::default()
+        };
+        write_tar_files_archive(&tar_path, &files, &options).unwrap();
+        assert!(options.skipped.to_vec().is_empty());
+        assert_eq!(tar_entries(&tar_path), vec!["dir3/file7.txt"]);
+        assert!(!dest.join("files.tar.tmp").exists());
+        cleanup(function_name!());
+    }
 }
@@ -0,0 +1,213 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use subprocess::{Exec, NullFile, Redirection};
+
+use crate::extra::get_7z_executable_path;
+
+use super::{
+    c_7z::{command, replace_existing},
+    c_tar::{each_tar_entry, tar_files, write_tar},
+    write_output, CompressOptions, EntryVisitor,
+};
+
+fn to_io_error(e: subprocess::PopenError) -> io::Error {
+    io::Error::new(
+        ErrorKind::BrokenPipe,
+        format!("Cannot execute subprocess!: {}", e),
+    )
+}
+
+/// Get the name of the tarball stored in the 7z archive at `zip_path`, like `dir1.tar` in `dir1.tar.7z`.
+fn tar_name(zip_path: &Path) -> String {
+    let name = zip_path.file_name().unwrap_or_default().to_string_lossy();
+    match name.strip_suffix(".7z") {
+        Some(tar_name) => tar_name.to_string(),
+        None => format!("{}.tar", name),
+    }
+}
+
+/// Compress the tarball which `write` writes to the stdin of `7z a -si` into the 7z archive at `zip_path`,
+/// without the intermediate tarball on the disk.
+fn pipe_to_7z<F>(zip_path: &Path, options: &CompressOptions, write: F) -> io::Result<()>
+where
+    F: FnOnce(File) -> io::Result<File>,
+{
+    // 7z adds the tarball to the existing archive, so replace it like a new tarball.
+    // The existing final archive is handled by the overwrite policy before.
+    if zip_path.is_file() {
+        fs::remove_file(zip_path)?;
+    }
+    let mut popen = command(&get_7z_executable_path()?, options)
+        .arg("a")
+        .arg(format!("-mx={}", options.level_7z))
+        .arg("-t7z")
+        .arg(format!("-si{}", tar_name(zip_path)))
+        .arg(env::current_dir()?.join(zip_path))
+        .stdin(Redirection::Pipe)
+        .stdout(NullFile)
+        .popen()
+        .map_err(to_io_error)?;
+    // The stdin is closed when `write` returns, so 7z sees the end of the tarball even if it fails.
+    let written = match popen.stdin.take() {
+        Some(stdin) => write(stdin).map(drop),
+        None => Ok(()),
+    };
+    let status = popen.wait().map_err(to_io_error)?;
+    written?;
+    if !status.success() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "Cannot compress the tarball from stdin! The 7z executable may not support -si.",
+        ));
+    }
+    Ok(())
+}
+
+/// Stream the tarball of `origin` into the 7z archive at `zip_path`.
+/// The existing archive is handled by the overwrite policy, like the 7z format.
+pub fn write_tar_7z(origin: &Path, zip_path: &Path, options: &CompressOptions) -> io::Result<()> {
+    if zip_path.is_file() && !replace_existing(zip_path, &[origin.to_path_buf()], options)? {
+        return Ok(());
+    }
+    write_output(zip_path, options, |path| {
+        pipe_to_7z(path, options, |stdin| write_tar(stdin, origin, options))?;
+        if options.fsync {
+            File::open(path)?.sync_all()?;
+        }
+        Ok(())
+    })
+}
+
+/// Stream the tarball of the `files` into the 7z archive at `zip_path`,
+/// with the entry names relative to the entry base.
+pub fn write_tar_files_7z(
+    files: &[PathBuf],
+    zip_path: &Path,
+    options: &CompressOptions,
+) -> io::Result<()> {
+    write_output(zip_path, options, |path| {
+        pipe_to_7z(path, options, |stdin| tar_files(stdin, files, options))?;
+        if options.fsync {
+            File::open(path)?.sync_all()?;
+        }
+        Ok(())
+    })
+}
+
+/// Read each file of the tarball in the 7z archive with `visit`,
+/// streamed from the stdout of `7z x -so`, which runs again for the hardlinks.
+/// The 7z executable is killed if the reading stops early.
+pub fn each_tar_7z_entry(zip_path: &Path, visit: &mut EntryVisitor) -> io::Result<()> {
+    let executable = get_7z_executable_path()?;
+    let mut children = Vec::new();
+    let result = each_tar_entry(
+        || {
+            let mut popen = Exec::cmd(&executable)
+                .arg("x")
+                .arg("-so")
+                .arg(zip_path)
+                .stdout(Redirection::Pipe)
+                .stderr(NullFile)
+                .popen()
+                .map_err(to_io_error)?;
+            let stdout = popen.stdout.take();
+            children.push(popen);
+            stdout.ok_or_else(|| io::Error::new(ErrorKind::BrokenPipe, "No stdout of 7z!"))
+        },
+        visit,
+    );
+    for mut child in children {
+        if result.is_err() {
+            child.kill().ok();
+        }
+        child.wait().map_err(to_io_error)?;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use function_name::named;
+
+    use crate::core::test_util::{cleanup, setup, Dir};
+    use crate::core::{OverwritePolicy, SkipReason};
+    use crate::preflight::unavailable_reason;
+    use crate::Format;
+
+    use super::*;
+
+    #[test]
+    #[named]
+    fn write_tar_7z_test() {
+        if let Some(reason) = unavailable_reason(&Format::Tar7z) {
+            println!("Skipped: {}", reason);
+            return;
+        }
+        let Dir { origin, dest } = setup(function_name!());
+        let zip_path = dest.join("dir1.tar.7z");
+        write_tar_7z(&origin.join("dir1"), &zip_path, &CompressOptions::default()).unwrap();
+        assert_eq!(tar_name(&zip_path), "dir1.tar");
+
+        let mut names = Vec::new();
+        each_tar_7z_entry(&zip_path, &mut |name, _| {
+            names.push(name.to_path_buf());
+            Ok(())
+        })
+        .unwrap();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                PathBuf::from("dir1/file3.png"),
+                PathBuf::from("dir1/file5.webp")
+            ]
+        );
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn write_tar_7z_existing_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let origin = origin.join("dir1");
+        let zip_path = dest.join("dir1.tar.7z");
+        fs::write(&zip_path, "existing").unwrap();
+
+        let error = write_tar_7z(&origin, &zip_path, &CompressOptions::default()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+        let options = CompressOptions {
+            overwrite: OverwritePolicy::Skip,
+            ..Default::default()
+        };
+        write_tar_7z(&origin, &zip_path, &options).unwrap();
+        assert_eq!(fs::read_to_string(&zip_path).unwrap(), "existing");
+        assert_eq!(
+            options.skipped.to_vec(),
+            vec![(origin, SkipReason::ArchiveExists)]
+        );
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn write_tar_files_7z_atomic_test() {
+        if let Some(reason) = unavailable_reason(&Format::Tar7z) {
+            println!("Skipped: {}", reason);
+            return;
+        }
+        let Dir { origin, dest } = setup(function_name!());
+        let files = [origin.join("dir1").join("file3.png")];
+        let zip_path = dest.join("files.tar.7z");
+        let options = CompressOptions {
+            atomic_output: true,
+            entry_base: Some(origin.clone()),
+            ..Default::default()
+        };
+        write_tar_files_7z(&files, &zip_path, &options).unwrap();
+        assert!(zip_path.is_file());
+        assert!(!dest.join("files.tar.7z.tmp").exists());
+        cleanup(function_name!());
+    }
+}
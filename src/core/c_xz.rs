@@ -6,9 +6,17 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use xz2::write::XzEncoder;
+use tar::Archive;
+use xz2::{
+    read::XzDecoder,
+    stream::{Check, MtStreamBuilder, Stream},
+    write::XzEncoder,
+};
 
-use super::Compress;
+use super::{
+    c_tar::{each_tar_entry, write_tar},
+    reserve_memory, write_output, Compress, CompressOptions, EntryVisitor,
+};
 
 fn append_ext(ext: impl AsRef<OsStr>, path: PathBuf) -> PathBuf {
     let mut os_string: OsString = path.into();
@@ -17,27 +25,104 @@ fn append_ext(ext: impl AsRef<OsStr>, path: PathBuf) -> PathBuf {
     os_string.into()
 }
 
+/// Flag of the xz preset which selects the extreme variant of the level.
+const PRESET_EXTREME: u32 = 1 << 31;
+
+/// Get the encoder stream of the level and the threads of `options`.
+/// The multithreaded stream splits the input into blocks, so even a single large file is compressed in parallel.
+fn encoder_stream(options: &CompressOptions) -> io::Result<Stream> {
+    let mut preset = options.xz_level;
+    if options.xz_extreme {
+        preset |= PRESET_EXTREME;
+    }
+    let stream = if options.xz_threads > 1 {
+        let mut builder = MtStreamBuilder::new();
+        builder
+            .threads(options.xz_threads)
+            .preset(preset)
+            .check(Check::Crc64);
+        if let Some(block_size) = options.xz_block_size {
+            builder.block_size(block_size);
+        }
+        builder.encoder()?
+    } else {
+        Stream::new_easy_encoder(preset, Check::Crc64)?
+    };
+    Ok(stream)
+}
+
+/// Compress the file at `origin` into `xz_path`.
+pub fn write_xz(origin: &Path, xz_path: &Path, options: &CompressOptions) -> io::Result<()> {
+    let mut origin_file = File::open(origin)?;
+    write_output(xz_path, options, |path| {
+        let dest_file = File::create(path)?;
+
+        let mut encoder = XzEncoder::new_stream(dest_file, encoder_stream(options)?);
+        let _permit = reserve_memory(options, origin_file.metadata()?.len());
+        let mut content = Vec::new();
+        origin_file.read_to_end(&mut content)?;
+        encoder.write_all(&content)?;
+        let dest_file = encoder.finish()?;
+        if options.fsync {
+            dest_file.sync_all()?;
+        }
+        Ok(())
+    })
+}
+
+/// Check that the xz archive can be read back.
+/// If it is a `.tar.xz`, every entry of the tarball in it is read.
+pub fn verify_xz(xz_path: &Path) -> io::Result<()> {
+    let mut decoder = XzDecoder::new(File::open(xz_path)?);
+    if !xz_path.to_string_lossy().ends_with(".tar.xz") {
+        io::copy(&mut decoder, &mut io::sink())?;
+        return Ok(());
+    }
+    let mut archive = Archive::new(decoder);
+    for entry in archive.entries()? {
+        io::copy(&mut entry?, &mut io::sink())?;
+    }
+    Ok(())
+}
+
+/// Read each file of the tarball in the xz archive with `visit`.
+pub fn each_xz_entry(xz_path: &Path, visit: &mut EntryVisitor) -> io::Result<()> {
+    each_tar_entry(|| Ok(XzDecoder::new(File::open(xz_path)?)), visit)
+}
+
 pub struct CompressXz;
 
+impl CompressXz {
+    /// Write the xz compressed tarball of the directory `origin` to `writer`, like an already open file or a pipe,
+    /// and get the writer after the stream is finished.
+    /// Unlike [`compress`](Compress::compress), which compresses an existing tarball,
+    /// the tarball is streamed into the encoder without the intermediate file.
+    pub fn compress_to<T: AsRef<Path>, W: Write>(
+        origin: T,
+        writer: W,
+        options: &CompressOptions,
+    ) -> io::Result<W> {
+        let encoder = XzEncoder::new_stream(writer, encoder_stream(options)?);
+        write_tar(encoder, origin.as_ref(), options)?.finish()
+    }
+}
+
 impl Compress for CompressXz {
-    fn compress<T: AsRef<Path>, O: AsRef<Path>>(origin: T, dest: O) -> Result<PathBuf, io::Error> {
+    fn compress<T: AsRef<Path>, O: AsRef<Path>>(
+        origin: T,
+        dest: O,
+        options: &CompressOptions,
+    ) -> Result<PathBuf, io::Error> {
         if !origin.as_ref().is_file() {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 "The origin is not a file!",
             ));
         }
-        let mut origin_file = File::open(&origin)?;
         let mut dest = dest.as_ref().join(&origin.as_ref().file_name().unwrap());
 
         dest = append_ext("xz", dest);
-        let dest_file = File::create(&dest)?;
-
-        let mut encoder = XzEncoder::new(dest_file, 9);
-        let mut content = Vec::new();
-        origin_file.read_to_end(&mut content)?;
-        encoder.write_all(&content)?;
-        encoder.finish()?;
+        write_xz(origin.as_ref(), &dest, options)?;
         return Ok(dest);
     }
 }
@@ -45,6 +130,7 @@ impl Compress for CompressXz {
 #[cfg(test)]
 mod tests {
     use function_name::named;
+    use std::fs;
 
     use super::super::c_tar::CompressTar;
     use super::*;
@@ -54,11 +140,49 @@ mod tests {
     #[named]
     fn compress_xz_test() {
         let Dir { origin, dest } = setup(function_name!());
-        let tar_path = CompressTar::compress(&origin, &dest).unwrap();
-        CompressXz::compress(&tar_path, dest).unwrap();
+        let options = CompressOptions::default();
+        let tar_path = CompressTar::compress(&origin, &dest, &options).unwrap();
+        CompressXz::compress(&tar_path, dest, &options).unwrap();
 
         assert!(tar_path.is_file());
         assert!(Path::new(&format!("{}.xz", &tar_path.to_str().unwrap())).is_file());
         cleanup(function_name!());
     }
+
+    #[test]
+    #[ignore = "compresses 64 MiB twice to compare the times"]
+    #[named]
+    fn xz_block_size_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let file = origin.join("large.bin");
+        let mut state = 1u32;
+        let content = (0..64 << 20)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                b"abcdefgh"[(state >> 28) as usize % 8]
+            })
+            .collect::<Vec<_>>();
+        fs::write(&file, &content).unwrap();
+
+        let mut elapsed = Vec::new();
+        for (threads, name) in [(1, "single.xz"), (4, "multi.xz")] {
+            let options = CompressOptions {
+                xz_level: 6,
+                xz_threads: threads,
+                xz_block_size: Some(4 << 20),
+                ..Default::default()
+            };
+            let start = std::time::Instant::now();
+            write_xz(&file, &dest.join(name), &options).unwrap();
+            elapsed.push(start.elapsed());
+
+            let mut decoded = Vec::new();
+            XzDecoder::new(File::open(dest.join(name)).unwrap())
+                .read_to_end(&mut decoded)
+                .unwrap();
+            assert!(decoded == content);
+        }
+        assert!(elapsed[1] < elapsed[0]);
+        cleanup(function_name!());
+    }
 }
@@ -1,47 +1,485 @@
 use std::{
-    fs::File,
-    io::{self, Read, Write},
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Read, Seek, Write},
     path::{Path, PathBuf},
 };
 
-use zip::{write::FileOptions, ZipWriter};
+use time::OffsetDateTime;
+use zip::{write::FileOptions, DateTime, ZipArchive, ZipWriter};
 
-use crate::extra::get_file_list;
+use crate::extra::{get_file_list, walk_filtered_files};
 
-use super::Compress;
+use super::{
+    entry_name, reserve_memory, strip_entry_name, write_output, CollisionPolicy, Compress,
+    CompressOptions, EntryOrder, EntryVisitor,
+};
+
+/// Header ID of the zip extra field which stores the extended attributes.
+const XATTR_EXTRA_FIELD_ID: u16 = 0x7861;
+
+/// Get the extended attributes of the file as the data of a zip extra field.
+///
+/// The field starts with the header ID and the size of the data, followed by each attribute
+/// as the name length, the name, the value length and the value.
+/// All numbers are 2 bytes little endian.
+/// The attributes which do not fit in the field are left out.
+/// If the file has no attributes, the returned data is empty.
+#[cfg(unix)]
+fn get_xattr_extra_field<T: AsRef<Path>>(path: T) -> Result<Vec<u8>, io::Error> {
+    let mut data = Vec::new();
+    for name in xattr::list(&path)? {
+        let value = match xattr::get(&path, &name)? {
+            Some(v) => v,
+            None => continue,
+        };
+        let name = name.as_encoded_bytes();
+        let size = 4 + name.len() + value.len();
+        if 4 + data.len() + size > u16::MAX as usize {
+            continue;
+        }
+        data.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        data.extend_from_slice(name);
+        data.extend_from_slice(&(value.len() as u16).to_le_bytes());
+        data.extend_from_slice(&value);
+    }
+    if data.is_empty() {
+        return Ok(data);
+    }
+    let mut field = Vec::with_capacity(4 + data.len());
+    field.extend_from_slice(&XATTR_EXTRA_FIELD_ID.to_le_bytes());
+    field.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    field.append(&mut data);
+    Ok(field)
+}
+
+#[cfg(not(unix))]
+fn get_xattr_extra_field<T: AsRef<Path>>(_path: T) -> Result<Vec<u8>, io::Error> {
+    Ok(Vec::new())
+}
+
+/// Largest number of entries of the classic zip format, without Zip64.
+const CLASSIC_MAX_ENTRIES: usize = u16::MAX as usize;
+
+/// Largest size of a file and an archive of the classic zip format, without Zip64.
+const CLASSIC_MAX_SIZE: u64 = u32::MAX as u64;
+
+/// Get the error that `what` exceeds a limit of the classic zip format, while Zip64 is disabled.
+fn zip64_error(what: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{} needs Zip64, but it is disabled!", what),
+    )
+}
+
+/// Count a new entry of the archive, failing if it exceeds the classic zip format without Zip64.
+fn count_entry(entries: &mut usize, options: &CompressOptions) -> io::Result<()> {
+    *entries += 1;
+    if !options.zip64 && *entries > CLASSIC_MAX_ENTRIES {
+        return Err(zip64_error(format!(
+            "An archive of more than {} entries",
+            CLASSIC_MAX_ENTRIES
+        )));
+    }
+    Ok(())
+}
+
+/// Get the base options of every zip entry.
+fn base_file_options(options: &CompressOptions) -> FileOptions {
+    options.zip_file_options.unwrap_or_else(|| {
+        FileOptions::default().compression_method(zip::CompressionMethod::Deflated)
+    })
+}
+
+/// Layer the permissions and the size of the file on the base options,
+/// and store the file without compression if its extension is in the stored extensions.
+fn file_options_for(
+    base: FileOptions,
+    file: &Path,
+    options: &CompressOptions,
+) -> Result<FileOptions, io::Error> {
+    let metadata = file.metadata()?;
+    let mut file_options = base.large_file(options.zip64 && metadata.len() >= CLASSIC_MAX_SIZE);
+    let stored = file.extension().is_some_and(|ext| {
+        options
+            .stored_extensions
+            .iter()
+            .any(|stored| ext.eq_ignore_ascii_case(stored.trim_start_matches('.')))
+    });
+    if stored {
+        file_options = file_options.compression_method(zip::CompressionMethod::Stored);
+    }
+    #[cfg(unix)]
+    let file_options = {
+        use std::os::unix::fs::PermissionsExt;
+        file_options.unix_permissions(metadata.permissions().mode())
+    };
+    Ok(file_options)
+}
+
+/// Get the options of the directory entry of `dir`, with its permissions and modification time.
+fn dir_options(base: FileOptions, dir: &Path) -> Result<FileOptions, io::Error> {
+    let metadata = dir.metadata()?;
+    let mut dir_options = base;
+    if let Ok(modified) = DateTime::try_from(OffsetDateTime::from(metadata.modified()?)) {
+        dir_options = dir_options.last_modified_time(modified);
+    }
+    #[cfg(unix)]
+    let dir_options = {
+        use std::os::unix::fs::PermissionsExt;
+        dir_options.unix_permissions(metadata.permissions().mode())
+    };
+    Ok(dir_options)
+}
+
+/// Join the components of the entry name with forward slashes, as the zip format requires,
+/// regardless of the path separator of the platform.
+fn zip_entry_name(name: &Path) -> String {
+    name.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Write the zip archive of the `files` in `origin` to `zip_path`.
+pub fn write_zip(
+    zip_path: &Path,
+    origin: &Path,
+    files: &[PathBuf],
+    options: &CompressOptions,
+) -> Result<(), io::Error> {
+    write_zip_walk(zip_path, origin, options, |visit| {
+        files.iter().try_for_each(|file| visit(file))
+    })
+}
+
+/// Write the zip archive of the files in `origin` which `walk` passes to its visitor, to `zip_path`.
+/// Each file is added as soon as it is visited, so the whole file list is never held in memory.
+/// The incomplete archive is removed on an error, like the size of 4 GiB or more found
+/// after it is finished without Zip64.
+fn write_zip_walk<W>(
+    zip_path: &Path,
+    origin: &Path,
+    options: &CompressOptions,
+    walk: W,
+) -> Result<(), io::Error>
+where
+    W: Fn(&mut dyn FnMut(&Path) -> io::Result<()>) -> io::Result<()>,
+{
+    write_output(zip_path, options, |path| {
+        let zip_file = match zip_to(File::create(path)?, origin, options, walk) {
+            Ok(zip_file) => zip_file,
+            Err(e) => {
+                fs::remove_file(path)?;
+                return Err(e);
+            }
+        };
+        if options.fsync {
+            zip_file.sync_all()?;
+        }
+        Ok(())
+    })
+}
+
+/// Write the zip archive of the files in `origin` which `walk` passes to its visitor, to `writer`,
+/// and get the writer after the archive is finished.
+fn zip_to<S, W>(writer: S, origin: &Path, options: &CompressOptions, walk: W) -> io::Result<S>
+where
+    S: Write + Seek,
+    W: Fn(&mut dyn FnMut(&Path) -> io::Result<()>) -> io::Result<()>,
+{
+    let mut zip_writer = ZipWriter::new(writer);
+    let base_options = base_file_options(options);
+    let mut entries = 0;
+
+    if options.preserve_root_dir {
+        if let Some(name) = strip_entry_name(&entry_name(origin, origin, options)?, options) {
+            count_entry(&mut entries, options)?;
+            zip_writer.add_directory(zip_entry_name(&name), dir_options(base_options, origin)?)?;
+        }
+    }
+
+    walk(&mut |file| {
+        let name = match strip_entry_name(&entry_name(file, origin, options)?, options) {
+            Some(name) => zip_entry_name(&name),
+            None => return Ok(()),
+        };
+        count_entry(&mut entries, options)?;
+        if !options.follow_symlinks && file.symlink_metadata()?.file_type().is_symlink() {
+            let target = fs::read_link(file)?;
+            return Ok(zip_writer.add_symlink(name, target.to_string_lossy(), base_options)?);
+        }
+        let mut file_options = file_options_for(base_options, file, options)?;
+        let _permit = reserve_memory(options, file.metadata()?.len());
+        let content = match options
+            .read_file(file)
+            .and_then(|content| options.transform(file, content))
+        {
+            Some(content) => content,
+            None => return Ok(()),
+        };
+        // The transformed content may grow over the limit, so check its own size.
+        if content.len() as u64 >= CLASSIC_MAX_SIZE {
+            if !options.zip64 {
+                return Err(zip64_error(format!("{} of 4 GiB or more", file.display())));
+            }
+            file_options = file_options.large_file(true);
+        }
+        let extra_field = if options.preserve_xattrs {
+            get_xattr_extra_field(file)?
+        } else {
+            Vec::new()
+        };
+        if extra_field.is_empty() {
+            zip_writer.start_file(name, file_options)?;
+        } else {
+            zip_writer.start_file_with_extra_data(name, file_options)?;
+            zip_writer.write_all(&extra_field)?;
+            zip_writer.end_extra_data()?;
+        }
+        zip_writer.write_all(&content)?;
+        options.progress(file, content.len() as u64);
+        Ok(())
+    })?;
+
+    let mut writer = zip_writer.finish()?;
+    if !options.zip64 && writer.stream_position()? > CLASSIC_MAX_SIZE {
+        return Err(zip64_error("An archive of 4 GiB or more".to_string()));
+    }
+    Ok(writer)
+}
+
+/// Check that every entry of the zip archive can be read back with the right CRC.
+pub fn verify_zip(zip_path: &Path) -> Result<(), io::Error> {
+    let mut archive = ZipArchive::new(File::open(zip_path)?)?;
+    for i in 0..archive.len() {
+        io::copy(&mut archive.by_index(i)?, &mut io::sink())?;
+    }
+    Ok(())
+}
+
+/// Read each file of the zip archive with `visit`. The directory entries are skipped.
+pub fn each_zip_entry(zip_path: &Path, visit: &mut EntryVisitor) -> io::Result<()> {
+    let mut archive = ZipArchive::new(File::open(zip_path)?)?;
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        if file.is_dir() {
+            continue;
+        }
+        let name = file.enclosed_name().map(Path::to_path_buf).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsafe entry name {}!", file.name()),
+            )
+        })?;
+        visit(&name, &mut file)?;
+    }
+    Ok(())
+}
 
-fn get_content_vec<T: AsRef<Path>>(path: T) -> Result<Vec<u8>, io::Error> {
-    let mut file = File::open(path)?;
-    let mut content = Vec::new();
-    file.read_to_end(&mut content)?;
-    Ok(content)
+/// Get the name of the entry renamed with the number, like `dir/file (2).txt`.
+fn numbered_name(name: &str, number: usize) -> String {
+    let file_start = name.rfind('/').map_or(0, |i| i + 1);
+    // The number goes before all extensions, like `a (2).tar.gz`, but not before the dot of a hidden file.
+    match name[file_start..].get(1..).and_then(|file| file.find('.')) {
+        Some(dot) => {
+            let dot = file_start + 1 + dot;
+            format!("{} ({}){}", &name[..dot], number, &name[dot..])
+        }
+        None => format!("{} ({})", name, number),
+    }
+}
+
+/// Merge the entries of the zip archives at `inputs` into a new zip archive at `output`, in their order,
+/// copying the compressed data as it is without decompressing it.
+/// The entries of the same name are handled by `collision`.
+///
+/// The output is written to a temporary file next to it and renamed at the end,
+/// so it can be one of the inputs, and is left as it is on an error.
+pub fn merge_zips(inputs: &[PathBuf], output: &Path, collision: CollisionPolicy) -> io::Result<()> {
+    let mut archives = inputs
+        .iter()
+        .map(|input| Ok(ZipArchive::new(File::open(input)?)?))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    // The input and the index of each entry to copy, with its name in the output.
+    let mut entries: Vec<(usize, usize, String)> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for (input, archive) in archives.iter_mut().enumerate() {
+        for index in 0..archive.len() {
+            let name = archive.by_index_raw(index)?.name().to_string();
+            let position = match positions.get(&name) {
+                Some(&position) => position,
+                None => {
+                    positions.insert(name.clone(), entries.len());
+                    entries.push((input, index, name));
+                    continue;
+                }
+            };
+            if name.ends_with('/') {
+                continue;
+            }
+            match collision {
+                CollisionPolicy::KeepFirst => (),
+                CollisionPolicy::KeepLast => entries[position] = (input, index, name),
+                CollisionPolicy::Rename => {
+                    let renamed = (2..)
+                        .map(|number| numbered_name(&name, number))
+                        .find(|renamed| !positions.contains_key(renamed))
+                        .unwrap();
+                    positions.insert(renamed.clone(), entries.len());
+                    entries.push((input, index, renamed));
+                }
+                CollisionPolicy::Error => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!(
+                            "The entry {} of {} is already merged!",
+                            name,
+                            inputs[input].display()
+                        ),
+                    ))
+                }
+            }
+        }
+    }
+
+    let options = CompressOptions {
+        atomic_output: true,
+        ..Default::default()
+    };
+    // The inputs are closed when the closure returns, before the output replaces any of them.
+    write_output(output, &options, move |temp_path| {
+        let mut zip_writer = ZipWriter::new(File::create(temp_path)?);
+        for (input, index, name) in entries {
+            let file = archives[input].by_index_raw(index)?;
+            if file.name() == name {
+                zip_writer.raw_copy_file(file)?;
+            } else {
+                zip_writer.raw_copy_file_rename(file, name)?;
+            }
+        }
+        zip_writer.finish()?;
+        Ok(())
+    })
 }
 
 pub struct CompressZip;
 
+impl CompressZip {
+    /// Compress `origin` into the independent zip archives of
+    /// [`files_per_archive`](CompressOptions::files_per_archive) files each,
+    /// named like `dir1.part001.zip`, and get their paths.
+    /// If it is `None`, all files are put in a single part.
+    pub fn compress_parts<T: AsRef<Path>, O: AsRef<Path>>(
+        origin: T,
+        dest: O,
+        options: &CompressOptions,
+    ) -> Result<Vec<PathBuf>, io::Error> {
+        let files = get_file_list(&origin, options)?;
+        let mut parts: Vec<&[PathBuf]> = files
+            .chunks(options.files_per_archive.unwrap_or(usize::MAX).max(1))
+            .collect();
+        if parts.is_empty() {
+            parts.push(&[]);
+        }
+
+        let mut part_paths = Vec::new();
+        for (i, part) in parts.into_iter().enumerate() {
+            let mut part_name = origin.as_ref().file_name().unwrap().to_os_string();
+            part_name.push(format!(".part{:03}.zip", i + 1));
+            let part_path = dest.as_ref().join(part_name);
+            write_zip(&part_path, origin.as_ref(), part, options)?;
+            part_paths.push(part_path);
+        }
+        Ok(part_paths)
+    }
+
+    /// Compress `origin` into the zip archive written to `writer`, like an already open file,
+    /// instead of a new file in the destination, and get the writer after the archive is finished.
+    /// The zip format needs to seek back to the entries, so `writer` must be seekable.
+    /// It is written directly, without the temp file of [`atomic_output`](CompressOptions::atomic_output),
+    /// so the archive in `writer` is left incomplete on an error.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use std::fs::OpenOptions;
+    /// use zip_archive::{CompressOptions, CompressZip};
+    ///
+    /// let file = OpenOptions::new().write(true).create_new(true).open("dest/dir1.zip").unwrap();
+    /// let file = CompressZip::compress_to("origin/dir1", file, &CompressOptions::default()).unwrap();
+    /// file.sync_all().unwrap();
+    /// ```
+    pub fn compress_to<T: AsRef<Path>, W: Write + Seek>(
+        origin: T,
+        writer: W,
+        options: &CompressOptions,
+    ) -> io::Result<W> {
+        let origin = origin.as_ref();
+        if options.entry_order != EntryOrder::Filesystem {
+            let files = get_file_list(origin, options)?;
+            return zip_to(writer, origin, options, |visit| {
+                files.iter().try_for_each(|file| visit(file))
+            });
+        }
+        zip_to(writer, origin, options, |visit| {
+            walk_filtered_files(origin, options, visit)
+        })
+    }
+
+    /// Compress the data read from `reader` into the zip archive at `zip_path`,
+    /// as a single entry named `entry_name`.
+    /// The data is streamed into the archive without a temporary file.
+    pub fn compress_reader<R: Read, P: AsRef<Path>>(
+        mut reader: R,
+        entry_name: &str,
+        zip_path: P,
+        options: &CompressOptions,
+    ) -> Result<PathBuf, io::Error> {
+        write_output(zip_path.as_ref(), options, |path| {
+            let mut zip_writer = ZipWriter::new(File::create(path)?);
+            // The size of the data is unknown until it is read, so prepare for a large one.
+            let file_options = base_file_options(options).large_file(options.zip64);
+            zip_writer.start_file(entry_name, file_options)?;
+            io::copy(&mut reader, &mut zip_writer)?;
+
+            let zip_file = zip_writer.finish()?;
+            if options.fsync {
+                zip_file.sync_all()?;
+            }
+            Ok(())
+        })?;
+        Ok(zip_path.as_ref().to_path_buf())
+    }
+}
+
 impl Compress for CompressZip {
-    fn compress<T: AsRef<Path>, O: AsRef<Path>>(origin: T, dest: O) -> Result<PathBuf, io::Error> {
+    /// Compress `origin` into a zip archive.
+    /// If [`files_per_archive`](CompressOptions::files_per_archive) is set,
+    /// it is split like [`compress_parts`](CompressZip::compress_parts)
+    /// and the path of the first part is returned.
+    fn compress<T: AsRef<Path>, O: AsRef<Path>>(
+        origin: T,
+        dest: O,
+        options: &CompressOptions,
+    ) -> Result<PathBuf, io::Error> {
+        if options.files_per_archive.is_some() {
+            return Ok(CompressZip::compress_parts(origin, dest, options)?.remove(0));
+        }
+
         let mut zip_file_name =
             PathBuf::from(dest.as_ref().join(&origin.as_ref().file_name().unwrap()));
         zip_file_name.set_extension("zip");
-        let zip_file = File::create(&zip_file_name)?;
-
-        let mut zip_writer = ZipWriter::new(zip_file);
-        let options = FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
 
-        for file in get_file_list(&origin)? {
-            let content = get_content_vec(&file)?;
-            zip_writer.start_file(
-                file.strip_prefix(&origin.as_ref().parent().unwrap())
-                    .unwrap()
-                    .to_str()
-                    .unwrap(),
-                options,
-            )?;
-            zip_writer.write_all(&content)?;
+        // The files are streamed in the filesystem order, or listed to be sorted.
+        if options.entry_order != EntryOrder::Filesystem {
+            let files = get_file_list(&origin, options)?;
+            write_zip(&zip_file_name, origin.as_ref(), &files, options)?;
+            return Ok(zip_file_name);
         }
-
-        zip_writer.finish()?;
+        write_zip_walk(&zip_file_name, origin.as_ref(), options, |visit| {
+            walk_filtered_files(&origin, options, visit)
+        })?;
 
         return Ok(zip_file_name);
     }
@@ -53,12 +491,12 @@ mod tests {
     use super::*;
     use function_name::named;
     use std::{
-        fs::File,
+        fs::{self, File},
         io::{Read, Write},
     };
     use zip::write::FileOptions;
 
-    use crate::core::test_util::{cleanup, setup, Dir};
+    use crate::core::test_util::{cleanup, setup, zip_entries, Dir};
 
     #[named]
     fn lib_test() {
@@ -99,9 +537,451 @@ mod tests {
     #[named]
     fn compress_zip_test() {
         let Dir { mut origin, dest } = setup(function_name!());
-        CompressZip::compress(&origin, &dest).unwrap();
+        CompressZip::compress(&origin, &dest, &CompressOptions::default()).unwrap();
         origin.set_extension("zip");
         assert!(dest.join(origin).is_file());
         cleanup(function_name!())
     }
+
+    #[test]
+    #[named]
+    fn compress_zip_atomic_output_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let options = CompressOptions {
+            atomic_output: true,
+            ..Default::default()
+        };
+
+        CompressZip::compress(origin.join("dir1"), &dest, &options).unwrap();
+        assert!(dest.join("dir1.zip").is_file());
+        assert!(!dest.join("dir1.zip.tmp").exists());
+
+        // Fails to list the files of the missing directory after creating the archive file.
+        assert!(CompressZip::compress(origin.join("missing"), &dest, &options).is_err());
+        assert!(!dest.join("missing.zip").exists());
+        assert!(!dest.join("missing.zip.tmp").exists());
+
+        // Fails to move the written archive onto a directory of its name.
+        fs::create_dir_all(dest.join("dir3.zip").join("file")).unwrap();
+        assert!(CompressZip::compress(origin.join("dir3"), &dest, &options).is_err());
+        assert!(dest.join("dir3.zip").is_dir());
+        assert!(!dest.join("dir3.zip.tmp").exists());
+        cleanup(function_name!())
+    }
+
+    #[test]
+    #[named]
+    fn compress_zip_atomic_output_cross_fs_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        // tmpfs is usually another filesystem than the destination.
+        let scratch = match Path::new("/dev/shm").is_dir() {
+            true => Path::new("/dev/shm").join(function_name!()),
+            false => std::env::temp_dir().join(function_name!()),
+        };
+        fs::create_dir_all(&scratch).unwrap();
+        let options = CompressOptions {
+            atomic_output: true,
+            temp_dir: Some(scratch.clone()),
+            ..Default::default()
+        };
+
+        CompressZip::compress(origin.join("dir1"), &dest, &options).unwrap();
+        assert_eq!(zip_entries(dest.join("dir1.zip")).len(), 2);
+        assert!(!dest.join("dir1.zip.tmp").exists());
+        assert_eq!(scratch.read_dir().unwrap().count(), 0);
+
+        fs::remove_dir_all(&scratch).unwrap();
+        cleanup(function_name!())
+    }
+
+    #[test]
+    #[named]
+    fn compress_zip_entry_base_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let mut options = CompressOptions {
+            entry_base: Some(origin.join("dir1")),
+            ..Default::default()
+        };
+
+        let zip_path = CompressZip::compress(origin.join("dir1"), &dest, &options).unwrap();
+        let mut entries = zip_entries(&zip_path);
+        entries.sort();
+        assert_eq!(entries, vec!["file3.png", "file5.webp"]);
+
+        options.entry_base = Some(PathBuf::from("other"));
+        fs::remove_file(zip_path).unwrap();
+        let error = CompressZip::compress(origin.join("dir1"), &dest, &options).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        cleanup(function_name!())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[named]
+    fn compress_zip_xattrs_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let file = origin.join("dir1").join("file3.png");
+        if xattr::set(&file, "user.zip_archive_test", b"tag value").is_err() {
+            // The file system does not support extended attributes.
+            cleanup(function_name!());
+            return;
+        }
+        let options = CompressOptions {
+            preserve_xattrs: true,
+            ..Default::default()
+        };
+        let zip_path = CompressZip::compress(origin.join("dir1"), &dest, &options).unwrap();
+
+        let mut archive = zip::ZipArchive::new(File::open(zip_path).unwrap()).unwrap();
+        let extra_data = archive
+            .by_name("dir1/file3.png")
+            .unwrap()
+            .extra_data()
+            .to_vec();
+        let name = b"user.zip_archive_test";
+        let mut expected = Vec::new();
+        expected.extend_from_slice(&XATTR_EXTRA_FIELD_ID.to_le_bytes());
+        expected.extend_from_slice(&(2 + name.len() as u16 + 2 + 9).to_le_bytes());
+        expected.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        expected.extend_from_slice(name);
+        expected.extend_from_slice(&9u16.to_le_bytes());
+        expected.extend_from_slice(b"tag value");
+        assert_eq!(extra_data, expected);
+
+        let entry = archive.by_name("dir1/file5.webp").unwrap();
+        assert!(entry.extra_data().is_empty());
+        cleanup(function_name!())
+    }
+
+    #[test]
+    #[named]
+    fn compress_zip_parts_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let many = origin.join("many");
+        fs::create_dir(&many).unwrap();
+        for i in 0..25 {
+            fs::write(many.join(format!("file{}.txt", i)), i.to_string()).unwrap();
+        }
+        let options = CompressOptions {
+            files_per_archive: Some(10),
+            ..Default::default()
+        };
+
+        let parts = CompressZip::compress_parts(&many, &dest, &options).unwrap();
+        assert_eq!(
+            parts,
+            vec![
+                dest.join("many.part001.zip"),
+                dest.join("many.part002.zip"),
+                dest.join("many.part003.zip")
+            ]
+        );
+        let entry_counts: Vec<usize> = parts.iter().map(|p| zip_entries(p).len()).collect();
+        assert_eq!(entry_counts, vec![10, 10, 5]);
+        assert!(!dest.join("many.zip").exists());
+        cleanup(function_name!())
+    }
+
+    #[test]
+    #[named]
+    fn compress_reader_test() {
+        let Dir { origin: _, dest } = setup(function_name!());
+        let data = b"CREATE TABLE dump (id INTEGER);".repeat(100);
+
+        let zip_path = CompressZip::compress_reader(
+            data.as_slice(),
+            "dump.sql",
+            dest.join("dump.zip"),
+            &CompressOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(zip_path, dest.join("dump.zip"));
+
+        let mut archive = zip::ZipArchive::new(File::open(zip_path).unwrap()).unwrap();
+        let mut content = Vec::new();
+        archive
+            .by_name("dump.sql")
+            .unwrap()
+            .read_to_end(&mut content)
+            .unwrap();
+        assert_eq!(content, data);
+        assert_eq!(archive.len(), 1);
+        cleanup(function_name!())
+    }
+
+    #[test]
+    #[named]
+    fn zip_entry_name_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let name = Path::new("dir1").join("sub").join("file.txt");
+        assert_eq!(zip_entry_name(&name), "dir1/sub/file.txt");
+
+        fs::create_dir(origin.join("dir1").join("sub")).unwrap();
+        fs::write(origin.join("dir1").join("sub").join("file.txt"), "").unwrap();
+        let zip_path =
+            CompressZip::compress(origin.join("dir1"), &dest, &CompressOptions::default()).unwrap();
+        let entries = zip_entries(zip_path);
+        assert!(entries.contains(&"dir1/sub/file.txt".to_string()));
+        assert!(entries.iter().all(|e| !e.contains('\\')));
+        cleanup(function_name!())
+    }
+
+    #[test]
+    #[named]
+    fn compress_zip_file_options_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let options = CompressOptions {
+            zip_file_options: Some(
+                FileOptions::default().compression_method(zip::CompressionMethod::Stored),
+            ),
+            ..Default::default()
+        };
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let file = origin.join("dir1").join("file3.png");
+            fs::set_permissions(file, fs::Permissions::from_mode(0o600)).unwrap();
+        }
+
+        let zip_path = CompressZip::compress(origin.join("dir1"), &dest, &options).unwrap();
+        let mut archive = zip::ZipArchive::new(File::open(zip_path).unwrap()).unwrap();
+        for i in 0..archive.len() {
+            let entry = archive.by_index(i).unwrap();
+            assert_eq!(entry.compression(), zip::CompressionMethod::Stored);
+        }
+        #[cfg(unix)]
+        assert_eq!(
+            archive
+                .by_name("dir1/file3.png")
+                .unwrap()
+                .unix_mode()
+                .unwrap()
+                & 0o777,
+            0o600
+        );
+        cleanup(function_name!())
+    }
+
+    #[test]
+    #[named]
+    fn compress_zip_stored_extensions_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let options = CompressOptions {
+            stored_extensions: vec!["PNG".to_string(), ".webp".to_string()],
+            ..Default::default()
+        };
+
+        let zip_path = CompressZip::compress(origin.join("dir3"), &dest, &options).unwrap();
+        let mut archive = zip::ZipArchive::new(File::open(zip_path).unwrap()).unwrap();
+        let method = |archive: &mut zip::ZipArchive<File>, name| {
+            archive.by_name(name).unwrap().compression()
+        };
+        assert_eq!(
+            method(&mut archive, "dir3/file1.png"),
+            zip::CompressionMethod::Stored
+        );
+        assert_eq!(
+            method(&mut archive, "dir3/file7.txt"),
+            zip::CompressionMethod::Deflated
+        );
+        cleanup(function_name!())
+    }
+
+    #[test]
+    #[named]
+    fn compress_zip_zstd_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let options = CompressOptions {
+            zip_file_options: Some(
+                FileOptions::default()
+                    .compression_method(zip::CompressionMethod::Zstd)
+                    .compression_level(Some(19)),
+            ),
+            ..Default::default()
+        };
+
+        let zip_path = CompressZip::compress(origin.join("dir3"), &dest, &options).unwrap();
+        let mut archive = zip::ZipArchive::new(File::open(zip_path).unwrap()).unwrap();
+        let mut entry = archive.by_name("dir3/file7.txt").unwrap();
+        assert_eq!(entry.compression(), zip::CompressionMethod::Zstd);
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content).unwrap();
+        assert_eq!(
+            content,
+            fs::read(origin.join("dir3").join("file7.txt")).unwrap()
+        );
+        cleanup(function_name!())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[named]
+    fn compress_zip_symlink_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let dir = origin.join("dir3");
+        std::os::unix::fs::symlink("file7.txt", dir.join("link.txt")).unwrap();
+        let options = CompressOptions {
+            follow_symlinks: false,
+            ..Default::default()
+        };
+        let zip_path = CompressZip::compress(&dir, &dest, &options).unwrap();
+
+        // Extract the symlink entry like unzip on Unix.
+        let extracted = dest.join("extracted");
+        fs::create_dir(&extracted).unwrap();
+        let mut archive = ZipArchive::new(File::open(zip_path).unwrap()).unwrap();
+        let mut entry = archive.by_name("dir3/link.txt").unwrap();
+        assert_eq!(entry.unix_mode().unwrap() & 0o170000, 0o120000);
+        let mut target = String::new();
+        entry.read_to_string(&mut target).unwrap();
+        std::os::unix::fs::symlink(&target, extracted.join("link.txt")).unwrap();
+
+        assert_eq!(
+            fs::read_link(extracted.join("link.txt")).unwrap(),
+            fs::read_link(dir.join("link.txt")).unwrap()
+        );
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn compress_zip64_entries_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let many = origin.join("many");
+        fs::create_dir(&many).unwrap();
+        for i in 0..=CLASSIC_MAX_ENTRIES {
+            File::create(many.join(i.to_string())).unwrap();
+        }
+        let options = CompressOptions {
+            zip_file_options: Some(
+                FileOptions::default().compression_method(zip::CompressionMethod::Stored),
+            ),
+            ..Default::default()
+        };
+
+        let zip_path = CompressZip::compress(&many, &dest, &options).unwrap();
+        let archive = ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+        assert_eq!(archive.len(), CLASSIC_MAX_ENTRIES + 1);
+        verify_zip(&zip_path).unwrap();
+        fs::remove_file(&zip_path).unwrap();
+
+        let options = CompressOptions {
+            zip64: false,
+            ..options
+        };
+        let error = CompressZip::compress(&many, &dest, &options).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("needs Zip64"));
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn compress_to_open_file_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let options = CompressOptions::default();
+
+        let zip_path = dest.join("handle.zip");
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&zip_path)
+            .unwrap();
+        let file = CompressZip::compress_to(origin.join("dir1"), file, &options).unwrap();
+        file.sync_all().unwrap();
+        let mut entries = zip_entries(&zip_path);
+        entries.sort();
+        assert_eq!(entries, vec!["dir1/file3.png", "dir1/file5.webp"]);
+
+        let mut tar = Vec::new();
+        crate::CompressTar::compress_to(origin.join("dir2"), &mut tar, &options).unwrap();
+        let xz = crate::CompressXz::compress_to(origin.join("dir2"), Vec::new(), &options).unwrap();
+        let mut unpacked = Vec::new();
+        xz2::read::XzDecoder::new(xz.as_slice())
+            .read_to_end(&mut unpacked)
+            .unwrap();
+        assert_eq!(unpacked, tar);
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn write_zip_error_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let origin = origin.join("dir1");
+        let zip_path = dest.join("dir1.zip");
+        let result = write_zip_walk(&zip_path, &origin, &CompressOptions::default(), |visit| {
+            visit(&origin.join("file3.png"))?;
+            Err(io::Error::other("stop"))
+        });
+        assert!(result.is_err());
+        assert!(!zip_path.exists());
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn merge_zips_test() {
+        let Dir { origin: _, dest } = setup(function_name!());
+        let inputs = [("a.zip", "first"), ("b.zip", "second")].map(|(name, content)| {
+            let path = dest.join(name);
+            let mut zip = ZipWriter::new(File::create(&path).unwrap());
+            zip.add_directory("docs/", FileOptions::default()).unwrap();
+            for entry in ["docs/notes.txt", &format!("docs/{}.txt", content)] {
+                zip.start_file(entry, FileOptions::default()).unwrap();
+                zip.write_all(content.as_bytes()).unwrap();
+            }
+            zip.finish().unwrap();
+            path
+        });
+        let merged = dest.join("merged.zip");
+        let read = |name: &str| {
+            let mut content = String::new();
+            let mut archive = ZipArchive::new(File::open(&merged).unwrap()).unwrap();
+            archive
+                .by_name(name)
+                .unwrap()
+                .read_to_string(&mut content)
+                .unwrap();
+            content
+        };
+
+        merge_zips(&inputs, &merged, CollisionPolicy::Rename).unwrap();
+        let mut entries = zip_entries(&merged);
+        entries.sort();
+        assert_eq!(
+            entries,
+            vec![
+                "docs/",
+                "docs/first.txt",
+                "docs/notes (2).txt",
+                "docs/notes.txt",
+                "docs/second.txt"
+            ]
+        );
+        assert_eq!(read("docs/notes (2).txt"), "second");
+
+        merge_zips(&inputs, &merged, CollisionPolicy::KeepFirst).unwrap();
+        assert_eq!(zip_entries(&merged).len(), 4);
+        assert_eq!(read("docs/notes.txt"), "first");
+        merge_zips(&inputs, &merged, CollisionPolicy::KeepLast).unwrap();
+        assert_eq!(read("docs/notes.txt"), "second");
+        assert!(merge_zips(&inputs, &merged, CollisionPolicy::Error).is_err());
+        assert_eq!(read("docs/notes.txt"), "second");
+        assert!(!dest.join("merged.zip.tmp").exists());
+
+        // The output can be one of the inputs.
+        let inputs = [merged.clone(), inputs[0].clone()];
+        merge_zips(&inputs, &merged, CollisionPolicy::KeepFirst).unwrap();
+        assert_eq!(zip_entries(&merged).len(), 4);
+        assert_eq!(read("docs/notes.txt"), "second");
+        assert_eq!(read("docs/first.txt"), "first");
+        assert_eq!(numbered_name("a.tar.gz", 3), "a (3).tar.gz");
+        assert_eq!(numbered_name("dir.d/.hidden", 2), "dir.d/.hidden (2)");
+
+        cleanup(function_name!());
+    }
 }
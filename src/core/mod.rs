@@ -1,15 +1,890 @@
 use std::{
-    io,
+    collections::{HashMap, HashSet},
+    fmt::{self, Display},
+    fs, io,
     path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex},
+};
+
+use zip::write::FileOptions;
+
+use crate::{
+    digest::HashAlgorithm,
+    extra::{get_file_list, input_stats},
+    resume::ResumeLog,
+    ArchiveObserver, CancellationToken, Format, JobProgress,
 };
 
 pub mod c_7z;
 pub mod c_tar;
+pub mod c_tar_7z;
 pub mod c_xz;
 pub mod c_zip;
 
+/// The reason why a file or a directory is skipped from the archive.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SkipReason {
+    /// The directory is excluded by its name.
+    Excluded,
+    /// The archive of the directory already exists.
+    ArchiveExists,
+    /// The content transform failed on the file, with the error message.
+    TransformFailed(String),
+    /// No files of the directory are left after the filters.
+    NoFiles,
+    /// The directory is completed by a previous run, in the resume mode.
+    Completed,
+    /// The file has no bytes, and the empty files are skipped.
+    EmptyFile,
+    /// The file cannot be opened or read, like without the permission, with the error message.
+    Unreadable(String),
+}
+
+impl Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::Excluded => write!(f, "excluded"),
+            SkipReason::ArchiveExists => write!(f, "archive exists"),
+            SkipReason::TransformFailed(e) => write!(f, "transform failed: {}", e),
+            SkipReason::NoFiles => write!(f, "no files matched filters"),
+            SkipReason::Completed => write!(f, "already completed"),
+            SkipReason::EmptyFile => write!(f, "empty file"),
+            SkipReason::Unreadable(e) => write!(f, "unreadable: {}", e),
+        }
+    }
+}
+
+/// What to do when the archive already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OverwritePolicy {
+    /// Replace the existing archive.
+    Overwrite,
+    /// Keep the existing archive and skip the directory.
+    Skip,
+    /// Fail to archive the directory.
+    #[default]
+    Error,
+}
+
+/// What to do when the entries of the merged archives have the same name.
+/// The directory entries of the same name are always merged into the first one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CollisionPolicy {
+    /// Keep the entry of the first archive.
+    KeepFirst,
+    /// Keep the entry of the last archive, at the position of the first one.
+    KeepLast,
+    /// Keep all entries, renaming the later ones like `file (2).txt`.
+    Rename,
+    /// Fail to merge the archives.
+    #[default]
+    Error,
+}
+
+/// Order of the entries in the zip archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EntryOrder {
+    /// The order the files are found in the filesystem, without sorting.
+    #[default]
+    Filesystem,
+    /// By the path, ascending. The archive of the same files is always in the same order.
+    NameAsc,
+    /// By the size, the largest first.
+    SizeDesc,
+    /// By the modification time, the oldest first.
+    MtimeAsc,
+}
+
+impl EntryOrder {
+    /// Sort the files in the order. The files of the same key keep their order by the path.
+    pub fn sort(&self, files: &mut [PathBuf]) {
+        match self {
+            EntryOrder::Filesystem => (),
+            EntryOrder::NameAsc => files.sort(),
+            EntryOrder::SizeDesc => {
+                files.sort();
+                files.sort_by_cached_key(|file| {
+                    std::cmp::Reverse(file.metadata().map_or(0, |m| m.len()))
+                });
+            }
+            EntryOrder::MtimeAsc => {
+                files.sort();
+                files.sort_by_cached_key(|file| file.metadata().and_then(|m| m.modified()).ok());
+            }
+        }
+    }
+}
+
+/// What to do when no files of a directory are left after the filters.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum EmptyPolicy {
+    /// Skip the directory without writing an archive.
+    #[default]
+    Skip,
+    /// Write an empty archive.
+    Create,
+}
+
+/// List of skipped files shared by all compressors.
+#[derive(Clone, Default)]
+pub struct SkipList(Arc<Mutex<Vec<(PathBuf, SkipReason)>>>);
+
+impl SkipList {
+    pub fn push<P: AsRef<Path>>(&self, path: P, reason: SkipReason) {
+        self.0
+            .lock()
+            .unwrap()
+            .push((path.as_ref().to_path_buf(), reason));
+    }
+
+    pub fn to_vec(&self) -> Vec<(PathBuf, SkipReason)> {
+        self.0.lock().unwrap().clone()
+    }
+
+    pub fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
+
+/// Numbers of the files of a directory left after the filters, measured in a single walk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct InputStats {
+    /// Number of the files.
+    pub files: u64,
+    /// Total size of the files in bytes.
+    pub size: u64,
+    /// Number of the already compressed files, judged by their extensions.
+    pub incompressible_files: u64,
+}
+
+impl InputStats {
+    /// Get the share of the already compressed files among the files, from 0 to 1.
+    pub fn incompressible_share(&self) -> f64 {
+        match self.files {
+            0 => 0.0,
+            files => self.incompressible_files as f64 / files as f64,
+        }
+    }
+}
+
+/// Stats of the directories shared by all compressors,
+/// so each directory is walked once for all features which need its size.
+#[derive(Clone, Default)]
+pub struct InputStatsCache {
+    stats: Arc<Mutex<HashMap<PathBuf, InputStats>>>,
+    #[cfg(test)]
+    walks: Arc<std::sync::atomic::AtomicUsize>,
+}
+
+impl InputStatsCache {
+    /// Get the stats of the directory, walking it only if it is not measured yet.
+    pub fn get_or_measure(&self, dir: &Path, options: &CompressOptions) -> io::Result<InputStats> {
+        if let Some(stats) = self.stats.lock().unwrap().get(dir) {
+            return Ok(*stats);
+        }
+        #[cfg(test)]
+        self.walks
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let stats = input_stats(dir, options)?;
+        self.stats.lock().unwrap().insert(dir.to_path_buf(), stats);
+        Ok(stats)
+    }
+
+    /// Get the stats of all measured directories.
+    pub fn to_map(&self) -> HashMap<PathBuf, InputStats> {
+        self.stats.lock().unwrap().clone()
+    }
+
+    pub fn clear(&self) {
+        self.stats.lock().unwrap().clear();
+    }
+
+    #[cfg(test)]
+    pub fn walks(&self) -> usize {
+        self.walks.load(std::sync::atomic::Ordering::Relaxed)
+    }
+}
+
+/// Intermediate and temporary files being written by all compressors of a run,
+/// like the tarballs of [`Format::Xz`] and the temporary files of the atomic output,
+/// so the ones left by an interrupted run can be removed.
+#[derive(Clone, Default)]
+pub struct ScratchFiles {
+    paths: Arc<Mutex<HashSet<PathBuf>>>,
+}
+
+impl ScratchFiles {
+    /// Track the file before it is written.
+    pub fn register(&self, path: &Path) {
+        self.paths.lock().unwrap().insert(path.to_path_buf());
+    }
+
+    /// Stop tracking the file once it is removed or moved to the final path.
+    pub fn release(&self, path: &Path) {
+        self.paths.lock().unwrap().remove(path);
+    }
+
+    /// Remove the files still tracked, and get how many are removed.
+    pub fn remove_all(&self) -> usize {
+        let paths = std::mem::take(&mut *self.paths.lock().unwrap());
+        paths
+            .iter()
+            .filter(|path| fs::remove_file(path).is_ok())
+            .count()
+    }
+
+    /// Get a guard which removes the files still tracked when it is dropped,
+    /// even while a panic unwinds.
+    pub fn guard(&self) -> ScratchGuard {
+        ScratchGuard {
+            scratch: self.clone(),
+        }
+    }
+}
+
+/// Removes the intermediate files left by an interrupted run when it is dropped.
+pub struct ScratchGuard {
+    scratch: ScratchFiles,
+}
+
+impl Drop for ScratchGuard {
+    fn drop(&mut self) {
+        self.scratch.remove_all();
+    }
+}
+
+/// Counting semaphore of the bytes which all compressors may hold in memory at once.
+#[derive(Clone)]
+pub struct MemoryBudget {
+    total: u64,
+    available: Arc<(Mutex<u64>, Condvar)>,
+}
+
+impl MemoryBudget {
+    pub fn new(total: u64) -> Self {
+        MemoryBudget {
+            total,
+            available: Arc::new((Mutex::new(total), Condvar::new())),
+        }
+    }
+
+    /// Block until `bytes` are available and take them until the permit is dropped.
+    /// A request over the total takes the whole budget, so it runs alone instead of waiting forever.
+    pub fn acquire(&self, bytes: u64) -> MemoryPermit {
+        let bytes = bytes.min(self.total);
+        let (lock, condvar) = &*self.available;
+        let mut available = lock.lock().unwrap();
+        while *available < bytes {
+            available = condvar.wait(available).unwrap();
+        }
+        *available -= bytes;
+        MemoryPermit {
+            budget: self.clone(),
+            bytes,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn available(&self) -> u64 {
+        *self.available.0.lock().unwrap()
+    }
+}
+
+/// Bytes taken from a [`MemoryBudget`], which are given back on drop.
+pub struct MemoryPermit {
+    budget: MemoryBudget,
+    bytes: u64,
+}
+
+impl Drop for MemoryPermit {
+    fn drop(&mut self) {
+        let (lock, condvar) = &*self.budget.available;
+        *lock.lock().unwrap() += self.bytes;
+        condvar.notify_all();
+    }
+}
+
+/// Counting semaphore of the slots, like of the directories which may be archived at once in a format.
+#[derive(Clone)]
+pub struct Semaphore {
+    available: Arc<(Mutex<usize>, Condvar)>,
+}
+
+impl Semaphore {
+    /// Make the semaphore of `slots` slots, at least one.
+    pub fn new(slots: usize) -> Self {
+        Semaphore {
+            available: Arc::new((Mutex::new(slots.max(1)), Condvar::new())),
+        }
+    }
+
+    /// Block until a slot is free and take it until the permit is dropped.
+    pub fn acquire(&self) -> SemaphorePermit {
+        let (lock, condvar) = &*self.available;
+        let mut available = lock.lock().unwrap();
+        while *available == 0 {
+            available = condvar.wait(available).unwrap();
+        }
+        *available -= 1;
+        SemaphorePermit {
+            semaphore: self.clone(),
+        }
+    }
+}
+
+/// Slot taken from a [`Semaphore`], which is given back on drop.
+pub struct SemaphorePermit {
+    semaphore: Semaphore,
+}
+
+impl Drop for SemaphorePermit {
+    fn drop(&mut self) {
+        let (lock, condvar) = &*self.semaphore.available;
+        *lock.lock().unwrap() += 1;
+        condvar.notify_one();
+    }
+}
+
+/// Take `bytes` from the memory budget of `options`, if any.
+pub fn reserve_memory(options: &CompressOptions, bytes: u64) -> Option<MemoryPermit> {
+    options
+        .memory_budget
+        .as_ref()
+        .map(|budget| budget.acquire(bytes))
+}
+
+/// Function which transforms the content of each file before it is archived.
+pub type ContentTransform = Arc<dyn Fn(&Path, Vec<u8>) -> io::Result<Vec<u8>> + Send + Sync>;
+
+/// Options shared by all compressors.
+///
+/// New options may be added, so start from [`CompressOptions::default`] and set the fields on it.
+#[derive(Clone)]
+#[non_exhaustive]
+pub struct CompressOptions {
+    /// Sync the archive file to the disk before returning.
+    pub fsync: bool,
+    /// Names of the subdirectories to skip at any depth.
+    pub excluded_dir_names: Vec<String>,
+    /// Exclude the files and the subdirectories matching the patterns of the ignore file
+    /// at the root of each archived directory.
+    pub honor_ignore_files: bool,
+    /// Name of the ignore file, `.archiveignore` by default.
+    pub ignore_file_name: String,
+    /// Leave the files with no bytes out of the archives, instead of adding them as empty entries.
+    pub skip_empty_files: bool,
+    /// Directory to write the intermediate tarball. If `None`, the destination is used.
+    pub temp_dir: Option<PathBuf>,
+    /// Directory which the relative source paths are resolved against, and which the 7z executable runs in.
+    /// If `None`, the current directory of the process is used.
+    pub working_dir: Option<PathBuf>,
+    /// Keep the intermediate tarball of the xz format instead of deleting it.
+    pub keep_intermediate_tar: bool,
+    /// Put the contents of the directory at the root of the tarball,
+    /// without wrapping them in the directory itself.
+    pub flat_tar: bool,
+    /// Where the skipped files are recorded.
+    pub(crate) skipped: SkipList,
+    /// Write the archive to a temporary file and rename it to the archive path on success.
+    pub atomic_output: bool,
+    /// Compression level of xz, from 0 to 9.
+    pub xz_level: u32,
+    /// Use the extreme variant of the xz level.
+    pub xz_extreme: bool,
+    /// Number of threads compressing each xz archive.
+    pub xz_threads: u32,
+    /// Size of the blocks which the multithreaded xz compression splits the input into.
+    /// If `None`, liblzma picks 3 times the dictionary size of the level.
+    pub xz_block_size: Option<u64>,
+    /// Compression level of 7z, from 0 to 9.
+    pub level_7z: u32,
+    /// Send the advisory warnings, like 7z at the level 9 on the already compressed files.
+    pub advisory_warnings: bool,
+    /// Directory which the entry names are relative to.
+    /// If `None`, the parent of the archived directory is used.
+    pub entry_base: Option<PathBuf>,
+    /// Store the files which the symlinks point to, instead of the symlinks themselves in the zip archive.
+    pub follow_symlinks: bool,
+    /// Store the extended attributes of each file in the extra field of its zip entry.
+    pub preserve_xattrs: bool,
+    /// Record the uid, the gid and their names of each entry in the tarball.
+    pub preserve_ownership: bool,
+    /// Order of the files of a directory when they are listed, like the entries of the zip archive.
+    pub entry_order: EntryOrder,
+    /// Split the zip archive into independent parts of this many files each.
+    pub files_per_archive: Option<usize>,
+    /// Use the Zip64 extensions of the zip format where the archive exceeds the classic limits,
+    /// like more than 65535 entries or a file of 4 GiB or more.
+    /// If disabled, exceeding them is an error instead of an archive the old readers cannot open.
+    pub zip64: bool,
+    /// Base options of every zip entry. If `None`, the entries are deflated.
+    pub zip_file_options: Option<FileOptions>,
+    /// Extensions of the files stored without compression in the zip archive.
+    pub stored_extensions: Vec<String>,
+    /// Store the files with the same content as the hardlinks to the first one in the tarball.
+    pub dedup: bool,
+    /// Store only the data regions of the sparse files in the tarball, where the holes can be found.
+    pub sparse: bool,
+    /// Hash algorithm comparing the contents of the files for the dedup.
+    pub dedup_hash: HashAlgorithm,
+    /// Hash algorithm of the checksums of the archives.
+    pub checksum_hash: HashAlgorithm,
+    /// Bytes of the files which may be read into memory at once by all compressors.
+    pub(crate) memory_budget: Option<MemoryBudget>,
+    /// Stats of the directories measured once per job, if they are cached.
+    pub(crate) input_stats_cache: Option<InputStatsCache>,
+    /// Intermediate files being written, removed if the run is interrupted.
+    pub(crate) scratch: ScratchFiles,
+    /// Number of the directories which may be archived at once in each format, as the counting semaphores.
+    pub(crate) format_concurrency: Vec<(Format, Semaphore)>,
+    /// Receives the progress and the skipped files.
+    pub observer: Option<Arc<dyn ArchiveObserver>>,
+    /// Counts the bytes of the files added to the archives, to estimate the time remaining.
+    pub job_progress: Option<JobProgress>,
+    /// Number of the leading components dropped from each entry name.
+    pub strip_components: u32,
+    /// What to do when the 7z archive already exists.
+    pub overwrite: OverwritePolicy,
+    /// Add the entry of the archived directory itself to the zip archive, with its mode and modification time.
+    pub preserve_root_dir: bool,
+    /// Transforms the content of each file before it is archived.
+    pub content_transform: Option<ContentTransform>,
+    /// Read the percentages printed by the 7z executable and pass them to the observer.
+    pub progress_7z: bool,
+    /// Compress each pushed file into its own archive.
+    pub per_file: bool,
+    /// Environment variables set for the 7z executable, on top of the inherited ones.
+    pub env_7z: HashMap<String, String>,
+    /// What to do with the directories which have no files after the filters.
+    pub empty_policy: EmptyPolicy,
+    /// Record of the completed directories, in the resume mode.
+    pub(crate) resume: Option<ResumeLog>,
+}
+
+impl Default for CompressOptions {
+    fn default() -> Self {
+        CompressOptions {
+            fsync: true,
+            excluded_dir_names: Vec::new(),
+            honor_ignore_files: false,
+            ignore_file_name: ".archiveignore".to_string(),
+            skip_empty_files: false,
+            temp_dir: None,
+            working_dir: None,
+            keep_intermediate_tar: false,
+            flat_tar: false,
+            skipped: SkipList::default(),
+            atomic_output: false,
+            xz_level: 9,
+            xz_extreme: false,
+            xz_threads: 1,
+            xz_block_size: None,
+            level_7z: 9,
+            advisory_warnings: false,
+            entry_base: None,
+            follow_symlinks: true,
+            preserve_xattrs: false,
+            preserve_ownership: false,
+            entry_order: EntryOrder::Filesystem,
+            files_per_archive: None,
+            zip64: true,
+            zip_file_options: None,
+            stored_extensions: Vec::new(),
+            dedup: false,
+            sparse: false,
+            dedup_hash: HashAlgorithm::Sha256,
+            checksum_hash: HashAlgorithm::Sha256,
+            memory_budget: None,
+            input_stats_cache: None,
+            scratch: ScratchFiles::default(),
+            format_concurrency: Vec::new(),
+            observer: None,
+            job_progress: None,
+            strip_components: 0,
+            overwrite: OverwritePolicy::default(),
+            preserve_root_dir: false,
+            content_transform: None,
+            progress_7z: false,
+            per_file: false,
+            env_7z: HashMap::new(),
+            empty_policy: EmptyPolicy::default(),
+            resume: None,
+        }
+    }
+}
+
+impl CompressOptions {
+    /// Get the stats of the directory from the cache if it is enabled, or by walking it.
+    pub fn input_stats(&self, dir: &Path) -> io::Result<InputStats> {
+        match &self.input_stats_cache {
+            Some(cache) => cache.get_or_measure(dir, self),
+            None => input_stats(dir, self),
+        }
+    }
+
+    /// Block until a directory may be archived in the format, if its concurrency is limited,
+    /// and take the slot until the permit is dropped.
+    pub fn format_permit(&self, format: &Format) -> Option<SemaphorePermit> {
+        self.format_concurrency
+            .iter()
+            .find(|(f, _)| f == format)
+            .map(|(_, slots)| slots.acquire())
+    }
+
+    /// Record the skipped file and pass it to the observer, if any.
+    pub fn skip<P: AsRef<Path>>(&self, path: P, reason: SkipReason) {
+        if let Some(observer) = &self.observer {
+            observer.on_skip(path.as_ref(), &reason);
+        }
+        self.skipped.push(path, reason);
+    }
+
+    /// Open the file to read its content.
+    /// Returns `None` if it cannot be opened, and then the file is recorded as skipped.
+    pub fn open_file(&self, path: &Path) -> Option<fs::File> {
+        match fs::File::open(path) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                self.skip(path, SkipReason::Unreadable(e.to_string()));
+                None
+            }
+        }
+    }
+
+    /// Read the whole content of the file.
+    /// Returns `None` if it cannot be read, and then the file is recorded as skipped.
+    pub fn read_file(&self, path: &Path) -> Option<Vec<u8>> {
+        match fs::read(path) {
+            Ok(content) => Some(content),
+            Err(e) => {
+                self.skip(path, SkipReason::Unreadable(e.to_string()));
+                None
+            }
+        }
+    }
+
+    /// Apply the content transform to the content of the file, if any.
+    /// Returns `None` if the transform fails, and then the file is recorded as skipped.
+    pub fn transform(&self, path: &Path, content: Vec<u8>) -> Option<Vec<u8>> {
+        let transform = match &self.content_transform {
+            Some(transform) => transform,
+            None => return Some(content),
+        };
+        match transform(path, content) {
+            Ok(content) => Some(content),
+            Err(e) => {
+                self.skip(path, SkipReason::TransformFailed(e.to_string()));
+                None
+            }
+        }
+    }
+
+    /// Pass the file added to an archive to the observer and the job progress, if any.
+    pub fn progress(&self, file: &Path, bytes: u64) {
+        if let Some(job_progress) = &self.job_progress {
+            job_progress.add(bytes);
+        }
+        if let Some(observer) = &self.observer {
+            observer.on_progress(file, bytes);
+        }
+    }
+}
+
+/// Get the name of the entry for `path` in the archive of `origin`,
+/// which is relative to the entry base of `options`.
+pub fn entry_name(path: &Path, origin: &Path, options: &CompressOptions) -> io::Result<PathBuf> {
+    let base = match &options.entry_base {
+        Some(base) => base.as_path(),
+        None => origin.parent().unwrap_or_else(|| Path::new("")),
+    };
+    match path.strip_prefix(base) {
+        Ok(name) => Ok(name.to_path_buf()),
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} is not under the entry base {}!",
+                path.display(),
+                base.display()
+            ),
+        )),
+    }
+}
+
+/// Drop the leading [`strip_components`](CompressOptions::strip_components) components of the entry name.
+/// Returns `None` if no component is left, and then the entry is left out of the archive.
+pub fn strip_entry_name(name: &Path, options: &CompressOptions) -> Option<PathBuf> {
+    let stripped = name
+        .components()
+        .skip(options.strip_components as usize)
+        .collect::<PathBuf>();
+    if stripped.as_os_str().is_empty() {
+        None
+    } else {
+        Some(stripped)
+    }
+}
+
+/// Get the path which [`write_output`] writes the archive file of `path` to.
+/// It is the temporary path if atomic output is enabled,
+/// in the [`temp_dir`](CompressOptions::temp_dir) if it is set, or next to `path`.
+pub fn output_path(path: &Path, options: &CompressOptions) -> PathBuf {
+    if !options.atomic_output {
+        return path.to_path_buf();
+    }
+    let temp_path = temp_path(path);
+    match (&options.temp_dir, temp_path.file_name()) {
+        (Some(temp_dir), Some(name)) => temp_dir.join(name),
+        _ => temp_path,
+    }
+}
+
+/// Get the temporary path next to `path`, like `dir1.zip.tmp`.
+fn temp_path(path: &Path) -> PathBuf {
+    let mut temp_path = path.as_os_str().to_os_string();
+    temp_path.push(".tmp");
+    PathBuf::from(temp_path)
+}
+
+/// Check whether the two existing paths are on the same filesystem,
+/// by the device IDs on Unix and the volumes, like `C:`, on Windows.
+pub fn same_filesystem(a: &Path, b: &Path) -> io::Result<bool> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Ok(fs::metadata(a)?.dev() == fs::metadata(b)?.dev())
+    }
+    #[cfg(windows)]
+    {
+        let volume = |path: &Path| -> io::Result<_> {
+            Ok(fs::canonicalize(path)?
+                .components()
+                .next()
+                .map(|c| c.as_os_str().to_ascii_uppercase()))
+        };
+        Ok(volume(a)? == volume(b)?)
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = (a, b);
+        Ok(true)
+    }
+}
+
+/// Move the file `from` to `to`, replacing it.
+///
+/// Renaming does not work across filesystems, so then the file is copied
+/// to a temporary file next to `to` first and renamed within the filesystem of `to`.
+/// So `to` is never seen incomplete either way.
+pub fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    let to_dir = match to.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if same_filesystem(from, to_dir)? {
+        match fs::rename(from, to) {
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => (),
+            result => return result,
+        }
+    }
+    let temp_path = temp_path(to);
+    let copied = fs::copy(from, &temp_path)
+        .and_then(|_| fs::File::open(&temp_path)?.sync_all())
+        .and_then(|_| fs::rename(&temp_path, to));
+    if let Err(e) = copied {
+        if temp_path.exists() {
+            fs::remove_file(&temp_path)?;
+        }
+        return Err(e);
+    }
+    fs::remove_file(from)
+}
+
+/// Write the archive file of `path` with `write`.
+///
+/// If atomic output is enabled, `write` gets a temporary path from [`output_path`],
+/// and the temporary file is moved to `path` only if `write` succeeds,
+/// safely even if the temp directory is on another filesystem.
+/// The temporary file is removed if either of them fails.
+pub fn write_output<F>(path: &Path, options: &CompressOptions, write: F) -> io::Result<()>
+where
+    F: FnOnce(&Path) -> io::Result<()>,
+{
+    if !options.atomic_output {
+        return write(path);
+    }
+    let temp_path = output_path(path, options);
+    options.scratch.register(&temp_path);
+
+    let result = write(&temp_path).and_then(|_| move_file(&temp_path, path));
+    let removed = match result.is_err() && temp_path.exists() {
+        true => fs::remove_file(&temp_path),
+        false => Ok(()),
+    };
+    options.scratch.release(&temp_path);
+    removed?;
+    result
+}
+
+/// Compress the `files` into a single archive at `archive_path` in the format,
+/// with the entry names relative to `base`.
+/// The tar-based formats write an intermediate tarball to the temp directory
+/// or next to the archive, which is deleted afterward.
+pub fn compress_files(
+    files: &[PathBuf],
+    base: &Path,
+    archive_path: &Path,
+    format: &Format,
+    options: &CompressOptions,
+) -> io::Result<PathBuf> {
+    let options = CompressOptions {
+        entry_base: Some(base.to_path_buf()),
+        ..options.clone()
+    };
+    match format {
+        Format::Zip => c_zip::write_zip(archive_path, base, files, &options)?,
+        Format::Xz => {
+            let mut tar_name = archive_path.file_name().unwrap_or_default().to_os_string();
+            tar_name.push(".tmp.tar");
+            let tar_dir = match &options.temp_dir {
+                Some(dir) => dir.as_path(),
+                None => archive_path.parent().unwrap_or_else(|| Path::new("")),
+            };
+            let tar_path = tar_dir.join(tar_name);
+            options.scratch.register(&tar_path);
+            let result = c_tar::write_tar_files(&tar_path, files, &options)
+                .and_then(|_| c_xz::write_xz(&tar_path, archive_path, &options));
+            if tar_path.exists() {
+                fs::remove_file(&tar_path)?;
+            }
+            options.scratch.release(&tar_path);
+            result?
+        }
+        Format::_7z => c_7z::compress_files(files, base, archive_path, &options)?,
+        Format::Tar => c_tar::write_tar_files_archive(archive_path, files, &options)?,
+        Format::Tar7z => c_tar_7z::write_tar_files_7z(files, archive_path, &options)?,
+    }
+    Ok(archive_path.to_path_buf())
+}
+
+/// Compress all `dirs` into a single archive at `archive_path` in the format,
+/// each under its own name, like `dir1/file1.png` and `dir2/file6.webp`.
+/// [`Format::_7z`] passes all of them to one execution of 7z,
+/// and the others put the files of all of them in the archive.
+pub fn compress_combined(
+    dirs: &[PathBuf],
+    archive_path: &Path,
+    format: &Format,
+    options: &CompressOptions,
+) -> io::Result<PathBuf> {
+    // 7z cannot filter the files by their sizes, so pass it the files left after the filters.
+    if *format == Format::_7z && !options.skip_empty_files {
+        return c_7z::Compress7z::compress_combined(dirs, archive_path, options);
+    }
+    let base = common_base(dirs);
+    let mut files = Vec::new();
+    for dir in dirs {
+        files.extend(get_file_list(dir, options)?);
+    }
+    compress_files(&files, &base, archive_path, format, options)
+}
+
+/// Get the deepest directory containing all `dirs`, which the entry names are made relative to.
+pub fn common_base(dirs: &[PathBuf]) -> PathBuf {
+    let mut base = dirs
+        .first()
+        .and_then(|dir| dir.parent())
+        .unwrap_or_else(|| Path::new(""))
+        .to_path_buf();
+    for dir in dirs {
+        while !dir.starts_with(&base) && base.pop() {}
+    }
+    base
+}
+
+/// Compress the single file into its own archive in `dest`, named like `a.txt.xz`,
+/// with the entry named after the file.
+/// The xz format compresses the file as is, without a tarball.
+pub fn compress_file(
+    file: &Path,
+    dest: &Path,
+    format: &Format,
+    options: &CompressOptions,
+) -> io::Result<PathBuf> {
+    let mut archive_name = file.file_name().unwrap_or_default().to_os_string();
+    archive_name.push(match format {
+        Format::Zip => ".zip",
+        Format::Xz => ".xz",
+        Format::_7z => ".7z",
+        Format::Tar => ".tar",
+        Format::Tar7z => ".tar.7z",
+    });
+    let archive_path = dest.join(archive_name);
+    match format {
+        Format::Xz => c_xz::write_xz(file, &archive_path, options)?,
+        _ => {
+            let base = file.parent().unwrap_or_else(|| Path::new(""));
+            compress_files(&[file.to_path_buf()], base, &archive_path, format, options)?;
+        }
+    }
+    Ok(archive_path)
+}
+
+/// Check that the archive in the format can be read back.
+pub fn verify_archive(archive_path: &Path, format: &Format) -> io::Result<()> {
+    match format {
+        Format::Zip => c_zip::verify_zip(archive_path),
+        Format::Xz => c_xz::verify_xz(archive_path),
+        Format::_7z => c_7z::verify_7z(archive_path),
+        Format::Tar => c_tar::verify_tar(archive_path),
+        Format::Tar7z => c_7z::verify_7z(archive_path),
+    }
+}
+
+/// Visitor of the archive entries, given the name and the content of each file.
+pub type EntryVisitor<'a> = dyn FnMut(&Path, &mut dyn io::Read) -> io::Result<()> + 'a;
+
+/// Read each file of the archive in the format with `visit`, in the order they are stored.
+/// The 7z executable extracting the 7z archive is killed once `cancel` is cancelled,
+/// while the other formats leave checking it between the entries to `visit`.
+pub fn each_entry(
+    archive_path: &Path,
+    format: &Format,
+    cancel: &CancellationToken,
+    visit: &mut EntryVisitor,
+) -> io::Result<()> {
+    match format {
+        Format::Zip => c_zip::each_zip_entry(archive_path, visit),
+        Format::Xz => c_xz::each_xz_entry(archive_path, visit),
+        Format::_7z => c_7z::each_7z_entry(archive_path, cancel, visit),
+        Format::Tar => c_tar::each_tar_entry(|| fs::File::open(archive_path), visit),
+        Format::Tar7z => c_tar_7z::each_tar_7z_entry(archive_path, visit),
+    }
+}
+
+/// Backend of a format, which compresses `origin` into an archive in `dest` with the options
+/// and returns its path. The options carry every knob, like the levels and the filters,
+/// so a backend can be used standalone, without the [`Archiver`](crate::Archiver) and its threads.
+///
+/// [`CompressTar`](c_tar::CompressTar) writes the intermediate tarball of a directory,
+/// which [`CompressXz`](c_xz::CompressXz) compresses into a `.tar.xz`.
+///
+/// # Examples
+/// ```no_run
+/// use zip_archive::{Compress, CompressOptions, CompressTar, CompressXz, CompressZip};
+///
+/// let mut options = CompressOptions::default();
+/// options.xz_level = 3;
+/// options.excluded_dir_names = vec!["cache".to_string()];
+/// let zip_path = CompressZip::compress("origin/dir1", "dest", &options).unwrap();
+/// let tar_path = CompressTar::compress("origin/dir1", "dest", &options).unwrap();
+/// let xz_path = CompressXz::compress(&tar_path, "dest", &options).unwrap();
+/// ```
 pub trait Compress {
-    fn compress<T: AsRef<Path>, O: AsRef<Path>>(origin: T, dest: O) -> Result<PathBuf, io::Error>;
+    fn compress<T: AsRef<Path>, O: AsRef<Path>>(
+        origin: T,
+        dest: O,
+        options: &CompressOptions,
+    ) -> Result<PathBuf, io::Error>;
 }
 
 #[cfg(test)]
@@ -17,9 +892,12 @@ pub mod test_util {
     use crate::extra::get_dir_list;
     use fs_extra::dir;
     use fs_extra::dir::CopyOptions;
+    use tar::Archive;
+    use xz2::read::XzDecoder;
+    use zip::ZipArchive;
 
-    use std::fs;
-    use std::path::PathBuf;
+    use std::fs::{self, File};
+    use std::path::{Path, PathBuf};
 
     pub struct Dir {
         pub origin: PathBuf,
@@ -52,6 +930,32 @@ pub mod test_util {
         }
     }
 
+    /// Get the entry names of the zip archive.
+    pub fn zip_entries<P: AsRef<Path>>(path: P) -> Vec<String> {
+        let archive = ZipArchive::new(File::open(path).unwrap()).unwrap();
+        archive.file_names().map(|n| n.to_string()).collect()
+    }
+
+    /// Get the entry names of the tarball.
+    pub fn tar_entries<P: AsRef<Path>>(path: P) -> Vec<String> {
+        let mut archive = Archive::new(File::open(path).unwrap());
+        archive
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_str().unwrap().to_string())
+            .collect()
+    }
+
+    /// Get the entry names of the xz compressed tarball.
+    pub fn tar_xz_entries<P: AsRef<Path>>(path: P) -> Vec<String> {
+        let mut archive = Archive::new(XzDecoder::new(File::open(path).unwrap()));
+        archive
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_str().unwrap().to_string())
+            .collect()
+    }
+
     pub fn cleanup(test_name: &str) {
         let test_origin = PathBuf::from(format!("test_origin_{}", test_name));
         let test_dest = PathBuf::from(format!("test_dest_{}", test_name));
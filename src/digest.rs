@@ -0,0 +1,262 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256, Sha512};
+
+/// Hash algorithm of the checksums and the dedup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashAlgorithm {
+    /// SHA-256, the cryptographic hash of the `sha256sum` tool.
+    #[default]
+    Sha256,
+    /// SHA-512, the cryptographic hash of the `sha512sum` tool, faster than SHA-256 on 64-bit CPUs.
+    Sha512,
+    /// CRC-32, the fast non-cryptographic checksum of the zip format.
+    /// It is much faster, but only 32 bits, so the different contents may collide
+    /// and it is not suited for the dedup of a large tree.
+    Crc32,
+}
+
+/// Incremental state of a [`HashAlgorithm`].
+#[derive(Clone)]
+pub(crate) enum Hasher {
+    Sha256(Sha256),
+    Sha512(Sha512),
+    Crc32(crc32fast::Hasher),
+}
+
+impl Hasher {
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            HashAlgorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+            HashAlgorithm::Crc32 => Hasher::Crc32(crc32fast::Hasher::new()),
+        }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(bytes),
+            Hasher::Sha512(hasher) => hasher.update(bytes),
+            Hasher::Crc32(hasher) => hasher.update(bytes),
+        }
+    }
+
+    /// Get the digest of the bytes, big endian for CRC-32.
+    pub fn finalize(self) -> Vec<u8> {
+        match self {
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha512(hasher) => hasher.finalize().to_vec(),
+            Hasher::Crc32(hasher) => hasher.finalize().to_be_bytes().to_vec(),
+        }
+    }
+}
+
+/// Get the hash of the content of the file in the algorithm.
+pub(crate) fn content_hash(path: &Path, algorithm: HashAlgorithm) -> io::Result<Vec<u8>> {
+    let mut hasher = Hasher::new(algorithm);
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize())
+}
+
+/// Get the line of the checksums file for the file named `name`, like `sha256sum` prints.
+/// A name with a backslash or a newline is escaped, and the line starts with a backslash.
+fn checksum_line(digest: &[u8], name: &str) -> String {
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    match name.contains(['\\', '\n']) {
+        true => format!(
+            "\\{}  {}\n",
+            hex,
+            name.replace('\\', "\\\\").replace('\n', "\\n")
+        ),
+        false => format!("{}  {}\n", hex, name),
+    }
+}
+
+/// Write the checksums of the `files` to the file at `path` in the format of `sha256sum`,
+/// with the names relative to `base`, so `sha256sum -c` checks them in `base`.
+pub(crate) fn write_checksum_file(
+    path: &Path,
+    files: &[PathBuf],
+    base: &Path,
+    algorithm: HashAlgorithm,
+) -> io::Result<()> {
+    let mut content = String::new();
+    for file in files {
+        let name = file
+            .strip_prefix(base)
+            .unwrap_or(file)
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        content.push_str(&checksum_line(&content_hash(file, algorithm)?, &name));
+    }
+    fs::write(path, content)
+}
+
+impl Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writer which forwards the bytes to the inner writer and computes their digest on the fly,
+/// so an archive and its checksum are made in a single pass. It is SHA-256 by default.
+///
+/// ```
+/// use std::io::Write;
+/// use zip_archive::DigestWriter;
+///
+/// let mut writer = DigestWriter::new(Vec::new());
+/// writer.write_all(b"archive").unwrap();
+/// let (bytes, digest) = writer.finish();
+/// assert_eq!(bytes, b"archive");
+/// assert_eq!(digest.len(), 64);
+/// ```
+pub struct DigestWriter<W: Write> {
+    inner: W,
+    hasher: Hasher,
+}
+
+impl<W: Write> DigestWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self::with_algorithm(inner, HashAlgorithm::Sha256)
+    }
+
+    /// Create the writer computing the digest in the algorithm.
+    pub fn with_algorithm(inner: W, algorithm: HashAlgorithm) -> Self {
+        DigestWriter {
+            inner,
+            hasher: Hasher::new(algorithm),
+        }
+    }
+
+    /// Get the inner writer and the lowercase hex digest of the bytes written so far.
+    pub fn finish(self) -> (W, String) {
+        let digest = self
+            .hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        (self.inner, digest)
+    }
+}
+
+impl<W: Write> Write for DigestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Hash only the bytes the inner writer accepted.
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use function_name::named;
+    use std::fs::File;
+    use tar::Builder;
+
+    use crate::core::test_util::{cleanup, setup, Dir};
+    use crate::Archiver;
+
+    #[test]
+    #[named]
+    fn digest_writer_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let tar_path = dest.join("dir1.tar");
+
+        let mut builder = Builder::new(DigestWriter::new(File::create(&tar_path).unwrap()));
+        builder.append_dir_all("dir1", origin.join("dir1")).unwrap();
+        let (_, digest) = builder.into_inner().unwrap().finish();
+
+        let expected = Sha256::digest(std::fs::read(&tar_path).unwrap())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        assert_eq!(digest, expected);
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn hash_algorithm_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let tar_path = dest.join("dir1.tar");
+        let hex = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>()
+        };
+
+        for algorithm in [
+            HashAlgorithm::Sha256,
+            HashAlgorithm::Sha512,
+            HashAlgorithm::Crc32,
+        ] {
+            let writer = DigestWriter::with_algorithm(File::create(&tar_path).unwrap(), algorithm);
+            let mut builder = Builder::new(writer);
+            builder.append_dir_all("dir1", origin.join("dir1")).unwrap();
+            let (_, digest) = builder.into_inner().unwrap().finish();
+
+            let bytes = std::fs::read(&tar_path).unwrap();
+            let expected = match algorithm {
+                HashAlgorithm::Sha256 => hex(&Sha256::digest(&bytes)),
+                HashAlgorithm::Sha512 => hex(&Sha512::digest(&bytes)),
+                HashAlgorithm::Crc32 => format!("{:08x}", crc32fast::hash(&bytes)),
+            };
+            assert_eq!(digest, expected);
+        }
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn checksum_file_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let mut archiver = Archiver::new();
+        archiver.push(origin.join("dir3"));
+        archiver.push(origin.join("dir1"));
+        archiver.set_destination(&dest);
+        archiver.set_checksum_file(Some("SHA256SUMS"));
+        archiver.archive().unwrap();
+
+        let expected: String = ["dir1.zip", "dir3.zip"]
+            .iter()
+            .map(|name| {
+                let digest = Sha256::digest(fs::read(dest.join(name)).unwrap());
+                let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+                format!("{}  {}\n", hex, name)
+            })
+            .collect();
+        assert_eq!(
+            fs::read_to_string(dest.join("SHA256SUMS")).unwrap(),
+            expected
+        );
+        assert_eq!(checksum_line(&[0xab], "a\\b"), "\\ab  a\\\\b\n");
+
+        // Verify it with the tool where it is available.
+        if let Ok(output) = std::process::Command::new("sha256sum")
+            .args(["-c", "SHA256SUMS"])
+            .current_dir(&dest)
+            .output()
+        {
+            assert!(output.status.success(), "{:?}", output);
+        }
+        cleanup(function_name!());
+    }
+}
@@ -0,0 +1,45 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// The error returned by [`Archiver`](crate::Archiver) and the archiving functions.
+#[derive(Debug)]
+pub enum ArchiveError {
+    /// The destination directory is not set.
+    DestinationNotSet,
+    /// There are no directories to archive.
+    QueueEmpty,
+    /// The number of threads is zero or over the maximum.
+    InvalidThreadCount(u32),
+    /// An I/O error, like failing to create the destination directory.
+    Io(io::Error),
+    /// An option is out of range or incompatible with the others, with the description.
+    InvalidOption(String),
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveError::DestinationNotSet => write!(f, "Destination directory is not set"),
+            ArchiveError::QueueEmpty => write!(f, "The queue is empty"),
+            ArchiveError::InvalidThreadCount(n) => write!(f, "Invalid thread count: {}", n),
+            ArchiveError::Io(e) => write!(f, "{}", e),
+            ArchiveError::InvalidOption(e) => write!(f, "Invalid option: {}", e),
+        }
+    }
+}
+
+impl Error for ArchiveError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ArchiveError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ArchiveError {
+    fn from(e: io::Error) -> Self {
+        ArchiveError::Io(e)
+    }
+}
@@ -1,14 +1,19 @@
+use std::collections::VecDeque;
 use std::env::consts::OS;
+use std::ffi::OsStr;
 use std::io;
 use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::Sender;
 
+use crate::core::{CompressOptions, InputStats, SkipReason};
+use crate::ignore::IgnoreFile;
+use crate::preset::INCOMPRESSIBLE_EXTENSIONS;
+
+/// Send the message, ignoring the error of the dropped receiver, since no one reads the message.
 pub fn send_message<T: ToString>(sender: &Sender<T>, message: T) {
-    match sender.send(message) {
-        Ok(_) => (),
-        Err(e) => println!("Message passing error!: {}", e),
-    }
+    sender.send(message).ok();
 }
 
 pub fn try_send_message<T: ToString>(sender: &Option<Sender<T>>, message: T) {
@@ -89,37 +94,270 @@ pub fn get_dir_list_with_depth<O: AsRef<Path>>(root: O, depth: u32) -> io::Resul
     return Ok(result);
 }
 
+/// Get a list of the leaf directories under the root directory, which have no subdirectories.
+/// The root directory itself is the only leaf if it has no subdirectories.
+pub fn get_leaf_dir_list<O: AsRef<Path>>(root: O) -> io::Result<Vec<PathBuf>> {
+    let dir_list = get_dir_list(&root)?;
+    if dir_list.is_empty() {
+        return Ok(vec![root.as_ref().to_path_buf()]);
+    }
+    let mut result = Vec::new();
+    for dir in dir_list {
+        result.append(&mut get_leaf_dir_list(dir)?);
+    }
+    Ok(result)
+}
+
+/// Which directories under the root are archived by [`Archiver::archive_tree`](crate::Archiver::archive_tree).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreeDepth {
+    /// The directories at the depth, like [`get_dir_list_with_depth`].
+    Depth(u32),
+    /// The leaf directories, like [`get_leaf_dir_list`].
+    Leaves,
+}
+
+impl TreeDepth {
+    /// Get the directories of the depth under `root`.
+    pub fn dir_list<O: AsRef<Path>>(&self, root: O) -> io::Result<Vec<PathBuf>> {
+        match self {
+            TreeDepth::Depth(depth) => get_dir_list_with_depth(root, *depth),
+            TreeDepth::Leaves => get_leaf_dir_list(root),
+        }
+    }
+}
+
+/// Get the name of a temporary directory for `name`, unique to each call in the process,
+/// like `zip_archive_1234_5_dir1.7z`, so the concurrent calls never share it.
+pub fn unique_temp_name(prefix: &str, name: &OsStr) -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    format!(
+        "{}_{}_{}_{}",
+        prefix,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed),
+        name.to_string_lossy()
+    )
+}
+
 /// Find all files in the root directory in recursive way.
 /// The hidden files are also include, except the .DS_Store files in Mac.
-pub fn get_file_list<O: AsRef<Path>>(root: O) -> io::Result<Vec<PathBuf>> {
-    let mut file_list: Vec<PathBuf> = Vec::new();
-    let mut file_queue: Vec<PathBuf> = root
-        .as_ref()
-        .read_dir()?
-        .map(|entry| entry.unwrap().path())
-        .collect();
-    let mut i = 0;
-    loop {
-        if i >= file_queue.len() {
-            break;
+/// The subdirectories whose name is excluded in `options` are skipped at any depth,
+/// and recorded to the skipped list of `options`.
+/// The files are sorted in the [`entry_order`](CompressOptions::entry_order) of `options`.
+pub fn get_file_list<O: AsRef<Path>>(
+    root: O,
+    options: &CompressOptions,
+) -> io::Result<Vec<PathBuf>> {
+    let mut file_list = Vec::new();
+    walk_filtered_files(root, options, |path| {
+        file_list.push(path.to_path_buf());
+        Ok(())
+    })?;
+    options.entry_order.sort(&mut file_list);
+    Ok(file_list)
+}
+
+/// Check whether any file of the root directory is left after the filters of `options`,
+/// without recording the excluded directories. The walk stops at the first file.
+pub fn has_files<O: AsRef<Path>>(root: O, options: &CompressOptions) -> io::Result<bool> {
+    find_first_file(root.as_ref(), options, |_| {})
+}
+
+/// Walk the files of the root directory until the first one, which is passed to `visit`.
+fn find_first_file(
+    root: &Path,
+    options: &CompressOptions,
+    mut visit: impl FnMut(&Path),
+) -> io::Result<bool> {
+    let mut found = false;
+    let result = walk(
+        root,
+        &options.excluded_dir_names,
+        IgnoreFile::for_root(root, options)?.as_ref(),
+        options.follow_symlinks,
+        options.skip_empty_files,
+        &|_, _| {},
+        &mut |path| {
+            visit(path);
+            found = true;
+            Err(io::Error::new(ErrorKind::Interrupted, "found a file"))
+        },
+    );
+    match result {
+        Err(e) if found && e.kind() == ErrorKind::Interrupted => Ok(true),
+        result => result.map(|_| false),
+    }
+}
+
+/// Get the total size of the files of the root directory, or of the root itself if it is a file,
+/// without recording the excluded directories.
+pub fn input_size<O: AsRef<Path>>(root: O, options: &CompressOptions) -> io::Result<u64> {
+    Ok(input_stats(root, options)?.size)
+}
+
+/// Count and measure the files of the root directory, or the root itself if it is a file,
+/// in a single walk without recording the excluded directories.
+pub fn input_stats<O: AsRef<Path>>(root: O, options: &CompressOptions) -> io::Result<InputStats> {
+    let root = root.as_ref();
+    let mut stats = InputStats::default();
+    let mut count = |path: &Path| {
+        stats.files += 1;
+        stats.size += path.metadata()?.len();
+        let ext = path.extension().unwrap_or_default();
+        if INCOMPRESSIBLE_EXTENSIONS
+            .iter()
+            .any(|e| ext.eq_ignore_ascii_case(e))
+        {
+            stats.incompressible_files += 1;
         }
-        if file_queue[i].is_dir() {
-            for component in file_queue[i].read_dir()? {
-                file_queue.push(component.unwrap().path());
+        Ok(())
+    };
+    if root.is_file() {
+        count(root)?;
+    } else {
+        walk(
+            root,
+            &options.excluded_dir_names,
+            IgnoreFile::for_root(root, options)?.as_ref(),
+            options.follow_symlinks,
+            options.skip_empty_files,
+            &|_, _| {},
+            &mut count,
+        )?;
+    }
+    Ok(stats)
+}
+
+/// Pass all files in the root directory to `visit` as they are found, in recursive way,
+/// instead of collecting them into a list first.
+/// The hidden files are also visited, except the .DS_Store files in Mac.
+/// The walk stops at the first error, including the one returned by `visit`.
+///
+/// # Examples
+/// ```
+/// use zip_archive::walk_files;
+///
+/// let mut size = 0;
+/// walk_files("original_images", |path| {
+///     size += path.metadata()?.len();
+///     Ok(())
+/// })
+/// .unwrap();
+/// ```
+pub fn walk_files<O, F>(root: O, mut visit: F) -> io::Result<()>
+where
+    O: AsRef<Path>,
+    F: FnMut(&Path) -> io::Result<()>,
+{
+    walk(
+        root.as_ref(),
+        &[],
+        None,
+        true,
+        false,
+        &|_, _| {},
+        &mut visit,
+    )
+}
+
+/// Walk the files like [`walk_files`], skipping the subdirectories whose name is excluded in `options`,
+/// the files and subdirectories matched by the ignore file if it is honored,
+/// and the empty files if they are skipped, recording them to the skipped list of `options`.
+pub fn walk_filtered_files<O, F>(root: O, options: &CompressOptions, mut visit: F) -> io::Result<()>
+where
+    O: AsRef<Path>,
+    F: FnMut(&Path) -> io::Result<()>,
+{
+    walk(
+        root.as_ref(),
+        &options.excluded_dir_names,
+        IgnoreFile::for_root(root.as_ref(), options)?.as_ref(),
+        options.follow_symlinks,
+        options.skip_empty_files,
+        &|path, reason| options.skip(path, reason),
+        &mut visit,
+    )
+}
+
+/// Visit the files of each directory before going into its subdirectories,
+/// holding only the directories yet to be read in memory.
+/// If `follow_symlinks` is false, the symlinks are visited as files, even if they point to directories.
+/// The paths matched by `ignore` are passed to `on_skip` like the excluded directories,
+/// and so are the empty files if `skip_empty_files` is true.
+fn walk(
+    root: &Path,
+    excluded_dir_names: &[String],
+    ignore: Option<&IgnoreFile>,
+    follow_symlinks: bool,
+    skip_empty_files: bool,
+    on_skip: &dyn Fn(&Path, SkipReason),
+    visit: &mut dyn FnMut(&Path) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut dirs = VecDeque::from([root.to_path_buf()]);
+    while let Some(dir) = dirs.pop_front() {
+        for entry in dir.read_dir()? {
+            let entry = entry?;
+            let path = entry.path();
+            let is_dir = if follow_symlinks {
+                path.is_dir()
+            } else {
+                entry.file_type()?.is_dir()
+            };
+            if ignore.is_some_and(|ignore| ignore.is_ignore_file(&path)) {
+                continue;
+            }
+            if ignore.is_some_and(|ignore| ignore.is_ignored(&path, is_dir)) {
+                on_skip(&path, SkipReason::Excluded);
+            } else if is_dir {
+                if is_excluded_dir(&path, excluded_dir_names) {
+                    on_skip(&path, SkipReason::Excluded);
+                } else {
+                    dirs.push_back(path);
+                }
+            } else if path.file_name() == Some(OsStr::new(".DS_Store")) {
+                continue;
+            } else if skip_empty_files && is_empty_file(&path, follow_symlinks)? {
+                on_skip(&path, SkipReason::EmptyFile);
+            } else {
+                visit(&path)?;
             }
-        } else if file_queue[i]
-            .file_name()
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .ne(".DS_Store")
-        {
-            file_list.push(file_queue[i].to_path_buf());
         }
-        i += 1;
     }
+    Ok(())
+}
 
-    Ok(file_list)
+/// Check whether the file has no bytes.
+/// If `follow_symlinks` is false, a symlink is never empty, since it is archived as the link itself.
+pub fn is_empty_file(path: &Path, follow_symlinks: bool) -> io::Result<bool> {
+    let metadata = match follow_symlinks {
+        true => path.metadata()?,
+        false => path.symlink_metadata()?,
+    };
+    Ok(metadata.is_file() && metadata.len() == 0)
+}
+
+/// Check whether the name of the directory is in `excluded_dir_names`.
+pub fn is_excluded_dir<P: AsRef<Path>>(dir: P, excluded_dir_names: &[String]) -> bool {
+    match dir.as_ref().file_name().and_then(|n| n.to_str()) {
+        Some(name) => excluded_dir_names.iter().any(|e| e == name),
+        None => false,
+    }
+}
+
+/// Format the number of bytes in a human-readable unit, like `12.4 MiB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
 }
 
 pub fn get_7z_executable_path() -> Result<PathBuf, io::Error> {
@@ -144,6 +382,15 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn format_size_test() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1024), "1.0 KiB");
+        assert_eq!(format_size(13_002_342), "12.4 MiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+
     #[test]
     fn get_7z_executable_path_test() {
         // The test will be passed if there is a 7z executable file in the root directory of the current project.
@@ -177,7 +424,7 @@ mod tests {
 
     #[test]
     fn get_file_list_test() {
-        let file_list = get_file_list("original_images").unwrap();
+        let file_list = get_file_list("original_images", &CompressOptions::default()).unwrap();
         let mut file_list: Vec<&str> = file_list.iter().map(|p| p.to_str().unwrap()).collect();
         file_list.sort();
 
@@ -194,4 +441,81 @@ mod tests {
 
         assert_eq!(file_list, expected_file_list);
     }
+
+    #[test]
+    fn get_file_list_excluded_dir_test() {
+        fs::create_dir_all("exclude_test/node_modules/package").unwrap();
+        fs::create_dir_all("exclude_test/src").unwrap();
+        fs::write("exclude_test/node_modules/package/index.js", "").unwrap();
+        fs::write("exclude_test/src/main.js", "").unwrap();
+
+        let options = CompressOptions {
+            excluded_dir_names: vec!["node_modules".to_string()],
+            ..Default::default()
+        };
+        let file_list = get_file_list("exclude_test", &options).unwrap();
+
+        assert_eq!(file_list, vec![PathBuf::from("exclude_test/src/main.js")]);
+        assert_eq!(
+            options.skipped.to_vec(),
+            vec![(
+                PathBuf::from("exclude_test/node_modules"),
+                SkipReason::Excluded
+            )]
+        );
+
+        fs::remove_dir_all("exclude_test").unwrap();
+    }
+
+    #[test]
+    fn walk_files_test() {
+        let mut visited = Vec::new();
+        walk_files("original_images", |path| {
+            visited.push(path.to_str().unwrap().to_string());
+            Ok(())
+        })
+        .unwrap();
+        visited.sort();
+        assert_eq!(
+            visited,
+            vec![
+                "original_images/dir1/file3.png",
+                "original_images/dir1/file5.webp",
+                "original_images/dir2/file6.webp",
+                "original_images/dir3/file1.png",
+                "original_images/dir3/file2.jpg",
+                "original_images/dir3/file4.jpg",
+                "original_images/dir3/file7.txt",
+            ]
+        );
+
+        let mut count = 0;
+        let result = walk_files("original_images", |_| {
+            count += 1;
+            Err(io::Error::new(ErrorKind::Interrupted, "stop"))
+        });
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::Interrupted);
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn has_files_test() {
+        let mut count = 0;
+        let options = CompressOptions::default();
+        assert!(find_first_file(Path::new("original_images"), &options, |_| count += 1).unwrap());
+        assert_eq!(count, 1);
+
+        fs::create_dir_all("has_files_test/empty").unwrap();
+        assert!(!has_files("has_files_test", &options).unwrap());
+        fs::remove_dir_all("has_files_test").unwrap();
+    }
+
+    #[test]
+    fn unique_temp_name_test() {
+        let first = unique_temp_name("zip_archive", OsStr::new("x.7z"));
+        let second = unique_temp_name("zip_archive", OsStr::new("x.7z"));
+        assert_ne!(first, second);
+        assert!(first.starts_with(&format!("zip_archive_{}_", std::process::id())));
+        assert!(first.ends_with("_x.7z"));
+    }
 }
@@ -0,0 +1,480 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    path::{Component, Path},
+};
+
+use crate::{
+    core::{c_7z::extract_7z_matching, each_entry},
+    extra::{unique_temp_name, walk_files},
+    ignore::{check_glob, glob_match},
+    CancellationToken, Format,
+};
+
+/// How far the extraction got, like when it is cancelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ExtractionReport {
+    /// Number of the files visited or written.
+    pub entries: usize,
+    /// Whether the extraction is stopped by the cancellation token.
+    /// Then the files visited or written before it are left as they are.
+    pub cancelled: bool,
+}
+
+impl ExtractionReport {
+    /// Make the report of the extraction which ends with `result`,
+    /// turning the error of the cancellation into a cancelled report.
+    fn finish(
+        entries: usize,
+        result: io::Result<()>,
+        cancel: &CancellationToken,
+    ) -> io::Result<Self> {
+        match result {
+            Ok(()) => Ok(ExtractionReport {
+                entries,
+                cancelled: false,
+            }),
+            Err(e) if cancel.is_cancelled_error(&e) => Ok(ExtractionReport {
+                entries,
+                cancelled: true,
+            }),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Get the format of the archive from its file name.
+fn archive_format(archive_path: &Path) -> io::Result<Format> {
+    let name = archive_path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+    let format = match name.rsplit('.').next() {
+        Some("zip") => Format::Zip,
+        Some("xz") => Format::Xz,
+        Some("7z") if name.ends_with(".tar.7z") => Format::Tar7z,
+        Some("7z") => Format::_7z,
+        Some("tar") => Format::Tar,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unknown archive format of {}!", archive_path.display()),
+            ))
+        }
+    };
+    Ok(format)
+}
+
+/// Read each file of the archive with `visit`, given its entry name and a reader of its content,
+/// without writing it to the disk. The files are visited in the order they are stored,
+/// and the directory entries are skipped.
+/// The format is detected from the file name, like `dir1.zip` or `dir1.tar.xz`.
+///
+/// The zip and tar based archives are streamed, but the 7z archive is extracted
+/// to a temporary directory first by the 7z executable, which needs the free space for its contents.
+///
+/// # Examples
+/// ```no_run
+/// use std::io::Read;
+/// use zip_archive::extract_each;
+///
+/// extract_each("dest/dir1.zip", |name, reader| {
+///     let mut content = Vec::new();
+///     reader.read_to_end(&mut content)?;
+///     println!("{}: {} bytes", name.display(), content.len());
+///     Ok(())
+/// })
+/// .unwrap();
+/// ```
+pub fn extract_each<A, F>(archive_path: A, visit: F) -> io::Result<()>
+where
+    A: AsRef<Path>,
+    F: FnMut(&Path, &mut dyn Read) -> io::Result<()>,
+{
+    extract_each_cancellable(archive_path, &CancellationToken::new(), visit).map(|_| ())
+}
+
+/// Read each file of the archive with `visit` like [`extract_each`],
+/// stopping before the next file once `cancel` is cancelled.
+/// The 7z executable extracting the 7z archive is killed then.
+///
+/// # Examples
+/// ```no_run
+/// use zip_archive::{extract_each_cancellable, CancellationToken};
+///
+/// let token = CancellationToken::new();
+/// let report = extract_each_cancellable("dest/dir1.zip", &token, |name, _| {
+///     println!("{}", name.display());
+///     Ok(())
+/// })
+/// .unwrap();
+/// println!("{} files, cancelled: {}", report.entries, report.cancelled);
+/// ```
+pub fn extract_each_cancellable<A, F>(
+    archive_path: A,
+    cancel: &CancellationToken,
+    mut visit: F,
+) -> io::Result<ExtractionReport>
+where
+    A: AsRef<Path>,
+    F: FnMut(&Path, &mut dyn Read) -> io::Result<()>,
+{
+    let archive_path = archive_path.as_ref();
+    let format = archive_format(archive_path)?;
+    let mut entries = 0;
+    let result = cancel.check().and_then(|_| {
+        each_entry(archive_path, &format, cancel, &mut |name, reader| {
+            cancel.check()?;
+            visit(name, reader)?;
+            entries += 1;
+            Ok(())
+        })
+    });
+    ExtractionReport::finish(entries, result, cancel)
+}
+
+/// Check whether the entry name matches any of the glob patterns.
+/// A pattern with `/` is matched against the whole name, and the others against the file name.
+fn is_matching(name: &Path, patterns: &[&str]) -> bool {
+    let path = name
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    let file_name = name.file_name().unwrap_or_default().to_string_lossy();
+    patterns.iter().any(|pattern| match pattern.contains('/') {
+        true => glob_match(pattern.as_bytes(), path.as_bytes()),
+        false => glob_match(pattern.as_bytes(), file_name.as_bytes()),
+    })
+}
+
+/// Extract only the files of the archive whose entry names match any of the glob patterns into `dest`,
+/// keeping their paths in the archive. The other files are never written.
+/// The format is detected from the file name like [`extract_each`].
+///
+/// In the patterns, `*` matches any characters but `/`, `?` matches one of them,
+/// and `**` matches any characters including `/`, as a whole path component.
+/// A pattern with `/` is matched against the whole entry name, like `dir1/**/*.json`,
+/// and the others against the file name in any directory, like `*.json`.
+///
+/// The zip and tar based archives are read entry by entry, and only the matching ones are written.
+/// The 7z archive is filtered by the 7z executable, whose wildcards may differ slightly.
+///
+/// # Examples
+/// ```no_run
+/// use zip_archive::extract_matching;
+///
+/// extract_matching("dest/dir1.zip", "extracted", &["*.json", "config/**"]).unwrap();
+/// ```
+pub fn extract_matching<A, D>(archive_path: A, dest: D, patterns: &[&str]) -> io::Result<()>
+where
+    A: AsRef<Path>,
+    D: AsRef<Path>,
+{
+    extract_matching_cancellable(archive_path, dest, patterns, &CancellationToken::new())
+        .map(|_| ())
+}
+
+/// Extract the files of the archive matching the patterns into `dest` like [`extract_matching`],
+/// stopping before the next file once `cancel` is cancelled, and leaving the files written so far.
+///
+/// The 7z archive is extracted by the 7z executable to a temporary directory in `dest` first,
+/// and then its files are moved into `dest`. The 7z executable is killed once `cancel` is cancelled.
+pub fn extract_matching_cancellable<A, D>(
+    archive_path: A,
+    dest: D,
+    patterns: &[&str],
+    cancel: &CancellationToken,
+) -> io::Result<ExtractionReport>
+where
+    A: AsRef<Path>,
+    D: AsRef<Path>,
+{
+    let (archive_path, dest) = (archive_path.as_ref(), dest.as_ref());
+    for pattern in patterns {
+        if let Err(reason) = check_glob(pattern) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid pattern {:?}: {}", pattern, reason),
+            ));
+        }
+    }
+    let format = archive_format(archive_path)?;
+    let mut entries = 0;
+    if format == Format::_7z {
+        if patterns.is_empty() {
+            return Ok(ExtractionReport::default());
+        }
+        let temp_dir = dest.join(unique_temp_name(
+            ".zip_archive",
+            archive_path.file_name().unwrap_or_default(),
+        ));
+        let result = cancel
+            .check()
+            .and_then(|_| extract_7z_matching(archive_path, &temp_dir, patterns, cancel))
+            .and_then(|_| {
+                walk_files(&temp_dir, |path| {
+                    cancel.check()?;
+                    let target = dest.join(path.strip_prefix(&temp_dir).unwrap_or(path));
+                    if let Some(parent) = target.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    fs::rename(path, target)?;
+                    entries += 1;
+                    Ok(())
+                })
+            });
+        if temp_dir.exists() {
+            fs::remove_dir_all(&temp_dir)?;
+        }
+        return ExtractionReport::finish(entries, result, cancel);
+    }
+    let result = cancel.check().and_then(|_| {
+        each_entry(archive_path, &format, cancel, &mut |name, reader| {
+            if !is_matching(name, patterns) {
+                return Ok(());
+            }
+            cancel.check()?;
+            if name
+                .components()
+                .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unsafe entry name {}!", name.display()),
+                ));
+            }
+            let path = dest.join(name);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            io::copy(reader, &mut File::create(path)?)?;
+            entries += 1;
+            Ok(())
+        })
+    });
+    ExtractionReport::finish(entries, result, cancel)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        fs,
+        io::{Seek, SeekFrom, Write},
+        path::PathBuf,
+    };
+
+    use function_name::named;
+    use tar::{Archive, EntryType};
+
+    use super::*;
+    use crate::core::test_util::{cleanup, setup, Dir};
+    use crate::sparse::data_regions;
+    use crate::Archiver;
+
+    /// Size of the sparse file made by [`dedup_sparse_archives`].
+    const SPARSE_SIZE: u64 = 8 << 20;
+
+    /// Add a copy of `file6.webp` and a sparse file to `dir2`, and archive it with the dedup and the sparse files
+    /// in the tar based formats. Returns the paths of the archives.
+    fn dedup_sparse_archives(origin: &Path, dest: &Path) -> Vec<PathBuf> {
+        let dir = origin.join("dir2");
+        fs::copy(dir.join("file6.webp"), dir.join("copy.webp")).unwrap();
+        let mut image = File::create(dir.join("disk.img")).unwrap();
+        image.set_len(SPARSE_SIZE).unwrap();
+        image.seek(SeekFrom::Start(1 << 20)).unwrap();
+        image.write_all(b"data").unwrap();
+        drop(image);
+
+        let mut archives = Vec::new();
+        for format in [Format::Tar, Format::Xz] {
+            // The xz format removes its intermediate tarball of the same name.
+            let dest = dest.join(format!("dedup_sparse_{:?}", format));
+            let mut archiver = Archiver::new();
+            archiver.push(&dir);
+            archiver.set_destination(&dest);
+            archiver.set_format(format.clone());
+            archiver.set_dedup(true);
+            archiver.set_sparse(true);
+            archiver.archive().unwrap();
+            archives.push(dest.join(format!("dir2{}", format.extension())));
+        }
+
+        // The tarball has the hardlink of the copy, and the sparse file if the holes can be found.
+        let types: Vec<_> = Archive::new(File::open(&archives[0]).unwrap())
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().header().entry_type())
+            .collect();
+        assert!(types.contains(&EntryType::Link));
+        if data_regions(&File::open(dir.join("disk.img")).unwrap())
+            .unwrap()
+            .is_some()
+        {
+            assert!(types.contains(&EntryType::GNUSparse));
+        }
+        archives
+    }
+
+    /// Check the content of the sparse file made by [`dedup_sparse_archives`].
+    fn assert_sparse_content(content: &[u8]) {
+        assert_eq!(content.len() as u64, SPARSE_SIZE);
+        assert_eq!(&content[1 << 20..(1 << 20) + 4], b"data");
+        assert_eq!(content.iter().filter(|b| **b != 0).count(), 4);
+    }
+
+    #[test]
+    #[named]
+    fn extract_each_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let expected = fs::read(origin.join("dir3").join("file7.txt")).unwrap();
+        for format in [Format::Zip, Format::Xz, Format::Tar] {
+            let mut archiver = Archiver::new();
+            archiver.push(origin.join("dir3"));
+            archiver.set_destination(dest.join(format.extension()));
+            archiver.set_format(format.clone());
+            archiver.archive().unwrap();
+
+            let archive_path = dest
+                .join(format.extension())
+                .join(format!("dir3{}", format.extension()));
+            let mut contents: HashMap<PathBuf, Vec<u8>> = HashMap::new();
+            extract_each(&archive_path, |name, reader| {
+                let mut content = Vec::new();
+                reader.read_to_end(&mut content)?;
+                contents.insert(name.to_path_buf(), content);
+                Ok(())
+            })
+            .unwrap();
+
+            assert_eq!(contents.len(), 4, "{:?}", format);
+            let (_, content) = contents
+                .iter()
+                .find(|(name, _)| name.ends_with("file7.txt"))
+                .unwrap();
+            assert_eq!(content, &expected);
+        }
+        assert!(extract_each(dest.join("dir3.rar"), |_, _| Ok(())).is_err());
+
+        // The hardlinks and the sparse files are not lost.
+        let expected = fs::read(origin.join("dir2").join("file6.webp")).unwrap();
+        for archive_path in dedup_sparse_archives(&origin, &dest) {
+            let mut contents: HashMap<PathBuf, Vec<u8>> = HashMap::new();
+            extract_each(&archive_path, |name, reader| {
+                let mut content = Vec::new();
+                reader.read_to_end(&mut content)?;
+                contents.insert(name.to_path_buf(), content);
+                Ok(())
+            })
+            .unwrap();
+            assert_eq!(contents.len(), 3, "{:?}", archive_path);
+            assert_eq!(contents[Path::new("dir2/file6.webp")], expected);
+            assert_eq!(contents[Path::new("dir2/copy.webp")], expected);
+            assert_sparse_content(&contents[Path::new("dir2/disk.img")]);
+        }
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn extract_matching_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        for format in [Format::Zip, Format::Tar, Format::Xz] {
+            // The xz format removes its intermediate tarball of the same name.
+            let archives = dest.join(format!("archives_{}", format.to_string()));
+            let mut archiver = Archiver::new();
+            archiver.push(origin.join("dir3"));
+            archiver.set_destination(&archives);
+            archiver.set_format(format.clone());
+            archiver.archive().unwrap();
+
+            let archive_path = archives.join(format!("dir3{}", format.extension()));
+            let extracted = dest.join(format.to_string());
+            extract_matching(&archive_path, &extracted, &["*.jpg", "dir3/file7.*"]).unwrap();
+            let mut names: Vec<_> = fs::read_dir(extracted.join("dir3"))
+                .unwrap()
+                .map(|e| e.unwrap().file_name())
+                .collect();
+            names.sort();
+            assert_eq!(names, ["file2.jpg", "file4.jpg", "file7.txt"]);
+            assert_eq!(
+                fs::read(extracted.join("dir3").join("file2.jpg")).unwrap(),
+                fs::read(origin.join("dir3").join("file2.jpg")).unwrap()
+            );
+        }
+        let archive_path = dest.join("archives_zip").join("dir3.zip");
+        assert!(extract_matching(archive_path, &dest, &["a**"]).is_err());
+
+        // The hardlinks and the sparse files are written like the regular files.
+        let expected = fs::read(origin.join("dir2").join("file6.webp")).unwrap();
+        for (i, archive_path) in dedup_sparse_archives(&origin, &dest).iter().enumerate() {
+            let extracted = dest.join(format!("dedup_sparse{}", i));
+            extract_matching(archive_path, &extracted, &["copy.*", "*.img"]).unwrap();
+            let dir = extracted.join("dir2");
+            assert!(!dir.join("file6.webp").exists());
+            assert_eq!(fs::read(dir.join("copy.webp")).unwrap(), expected);
+            assert_sparse_content(&fs::read(dir.join("disk.img")).unwrap());
+        }
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn extract_cancellable_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        for format in Archiver::supported_formats() {
+            let archives = dest.join(format!("archives_{}", format.to_string()));
+            let mut archiver = Archiver::new();
+            archiver.push(origin.join("dir3"));
+            archiver.set_destination(&archives);
+            archiver.set_format(format.clone());
+            archiver.archive().unwrap();
+            let archive_path = archives.join(format!("dir3{}", format.extension()));
+
+            // Cancelled after the second file, the rest of the files are not visited.
+            let token = CancellationToken::new();
+            let mut names = Vec::new();
+            let report = extract_each_cancellable(&archive_path, &token, |name, _| {
+                names.push(name.to_path_buf());
+                if names.len() == 2 {
+                    token.cancel();
+                }
+                Ok(())
+            })
+            .unwrap();
+            let expected = ExtractionReport {
+                entries: 2,
+                cancelled: true,
+            };
+            assert_eq!(report, expected, "{:?}", format);
+            assert_eq!(names.len(), 2);
+
+            // Cancelled before the start, nothing is written.
+            let extracted = dest.join(format.to_string());
+            let report =
+                extract_matching_cancellable(&archive_path, &extracted, &["*"], &token).unwrap();
+            assert_eq!(report.entries, 0);
+            assert!(report.cancelled);
+            assert!(!extracted.join("dir3").exists());
+
+            let report = extract_matching_cancellable(
+                &archive_path,
+                &extracted,
+                &["*"],
+                &CancellationToken::new(),
+            )
+            .unwrap();
+            let expected = ExtractionReport {
+                entries: 4,
+                cancelled: false,
+            };
+            assert_eq!(report, expected, "{:?}", format);
+            assert_eq!(fs::read_dir(&extracted).unwrap().count(), 1);
+        }
+        cleanup(function_name!());
+    }
+}
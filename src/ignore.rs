@@ -0,0 +1,191 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use crate::core::CompressOptions;
+
+/// A line of the ignore file.
+#[derive(Debug)]
+struct Pattern {
+    glob: String,
+    /// Matched against the path from the root, instead of the name at any depth.
+    anchored: bool,
+    /// Matches only the directories.
+    dir_only: bool,
+}
+
+/// Patterns of the ignore file at the root of a directory, like `.gitignore`,
+/// which exclude the matching files and subdirectories from the archive of the directory.
+///
+/// See [`Archiver::set_honor_ignore_files`](crate::Archiver::set_honor_ignore_files) for the patterns.
+/// The blank lines and the lines starting with `#` are ignored.
+#[derive(Debug)]
+pub struct IgnoreFile {
+    root: PathBuf,
+    path: PathBuf,
+    patterns: Vec<Pattern>,
+}
+
+impl IgnoreFile {
+    /// Read the ignore file at the root of the directory,
+    /// or get `None` if it is not honored by `options` or the directory has none.
+    pub fn for_root(root: &Path, options: &CompressOptions) -> io::Result<Option<IgnoreFile>> {
+        if !options.honor_ignore_files {
+            return Ok(None);
+        }
+        let path = root.join(&options.ignore_file_name);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let patterns = fs::read_to_string(&path)?
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(number, line)| {
+                let dir_only = line.ends_with('/');
+                let line = line.trim_end_matches('/');
+                let glob = line.trim_start_matches('/');
+                if let Err(reason) = check_glob(glob) {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid pattern {:?} at line {} of {}: {}",
+                            line,
+                            number,
+                            path.display(),
+                            reason
+                        ),
+                    ));
+                }
+                Ok(Pattern {
+                    glob: glob.to_string(),
+                    anchored: line.contains('/'),
+                    dir_only,
+                })
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(Some(IgnoreFile {
+            root: root.to_path_buf(),
+            path,
+            patterns,
+        }))
+    }
+
+    /// Check whether the path is the ignore file itself, which is left out of the archive.
+    pub fn is_ignore_file(&self, path: &Path) -> bool {
+        path == self.path
+    }
+
+    /// Check whether the file or the directory under the root is excluded by the patterns.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let relative = match path.strip_prefix(&self.root) {
+            Ok(relative) => relative,
+            Err(_) => return false,
+        };
+        let relative = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        self.patterns.iter().any(|pattern| {
+            (is_dir || !pattern.dir_only)
+                && match pattern.anchored {
+                    true => glob_match(pattern.glob.as_bytes(), relative.as_bytes()),
+                    false => glob_match(pattern.glob.as_bytes(), name.as_bytes()),
+                }
+        })
+    }
+}
+
+/// Check that the glob pattern can be matched, or get why it cannot.
+pub(crate) fn check_glob(glob: &str) -> Result<(), &'static str> {
+    if glob.is_empty() {
+        return Err("it matches nothing");
+    }
+    for component in glob.split('/') {
+        if component.contains("**") && component != "**" {
+            return Err("`**` must be a whole path component");
+        }
+    }
+    Ok(())
+}
+
+/// Match the text against the glob pattern of `*`, `?` and `**`.
+pub(crate) fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => {
+            // `**/` also matches no directory at all.
+            if rest
+                .strip_prefix(b"/")
+                .is_some_and(|after| glob_match(after, text))
+            {
+                return true;
+            }
+            (0..=text.len()).any(|i| glob_match(rest, &text[i..]))
+        }
+        [b'*', rest @ ..] => (0..=text.len())
+            .take_while(|&i| i == 0 || text[i - 1] != b'/')
+            .any(|i| glob_match(rest, &text[i..])),
+        [b'?', rest @ ..] => match text {
+            [c, text @ ..] if *c != b'/' => glob_match(rest, text),
+            _ => false,
+        },
+        [p, rest @ ..] => match text {
+            [c, text @ ..] if c == p => glob_match(rest, text),
+            _ => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use function_name::named;
+
+    use super::*;
+    use crate::core::test_util::{cleanup, setup, tar_xz_entries, zip_entries, Dir};
+    use crate::{Archiver, Format, SkipReason};
+
+    #[test]
+    #[named]
+    fn archive_ignore_file_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let dir = origin.join("dir3");
+        fs::create_dir(dir.join("cache")).unwrap();
+        fs::write(dir.join("cache").join("a.png"), "cached").unwrap();
+        fs::write(
+            dir.join(".archiveignore"),
+            "# media\n*.jpg\n\n/file7.txt\ncache/\n",
+        )
+        .unwrap();
+        assert!(glob_match(b"**/*.png", b"a/b/c.png"));
+        assert!(glob_match(b"**/c.png", b"c.png"));
+        assert!(!glob_match(b"*.png", b"a/c.png"));
+
+        for format in [Format::Zip, Format::Xz] {
+            let mut archiver = Archiver::new();
+            archiver.push(&dir);
+            archiver.set_destination(&dest);
+            archiver.set_format(format.clone());
+            archiver.set_honor_ignore_files(true);
+            archiver.archive().unwrap();
+
+            let mut entries = match format {
+                Format::Zip => zip_entries(dest.join("dir3.zip")),
+                _ => tar_xz_entries(dest.join("dir3.tar.xz")),
+            };
+            entries.retain(|e| !e.ends_with('/') && e != "dir3");
+            assert_eq!(entries, vec!["dir3/file1.png"]);
+            assert!(archiver
+                .skipped_files()
+                .contains(&(dir.join("cache"), SkipReason::Excluded)));
+        }
+        cleanup(function_name!());
+    }
+}
@@ -10,6 +10,7 @@
 //! | [xz](https://en.wikipedia.org/wiki/XZ) | Using [xz2] crate. |
 //! | [7z](https://www.7-zip.org) | See [Requirements](#requirements-for-7z-format) section. |
 //! | [zip] | Using [zip] crate. |
+//! | [tar] | Uncompressed, for benchmarking. Using [tar] crate. |
 //!
 //!
 //! # Examples
@@ -85,22 +86,135 @@
 //! 1. Download [7-Zip console version executable](https://www.7-zip.org/download.html) for macOS.
 //! 2. Place 7zz executable to home directory.
 
+mod cancel;
 mod core;
+mod digest;
+mod error;
 mod extra;
+mod extract;
+mod ignore;
+mod observer;
+mod options;
+mod post_command;
+mod preflight;
+mod preset;
 mod process;
+mod progress;
+mod recommend;
+mod report;
+mod results;
+mod resume;
+mod sparse;
+mod template;
 
+use crate::core::{
+    c_tar::write_bundle, common_base, compress_combined, compress_files, InputStatsCache,
+    MemoryBudget, ScratchFiles, Semaphore, SkipList,
+};
 use crossbeam_queue::SegQueue;
 use extra::try_send_message;
-use process::get_compressor;
-use std::error::Error;
+use ignore::IgnoreFile;
+use process::{
+    get_pipeline, Compressors, Item, Message, MessageFormatter, Outcome, Reporter, ResultCallback,
+    Source, WorkQueue,
+};
+use std::collections::HashMap;
+use std::fmt;
 use std::fs::create_dir_all;
-use std::path::{Path, PathBuf};
-use std::sync::mpsc::Sender;
-use std::sync::Arc;
-use std::{io, thread};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender};
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use digest::write_checksum_file;
+use post_command::PostCommand;
+use resume::ResumeLog;
+use template::NameTemplate;
 
-pub use extra::{get_dir_list, get_dir_list_with_depth};
-pub use process::Format;
+pub use cancel::CancellationToken;
+pub use crate::core::{
+    c_7z::Compress7z, c_tar::CompressTar, c_xz::CompressXz, c_zip::CompressZip, CollisionPolicy,
+    Compress, CompressOptions, EmptyPolicy, EntryOrder, InputStats, OverwritePolicy, SkipReason,
+};
+pub use digest::{DigestWriter, HashAlgorithm};
+pub use error::ArchiveError;
+pub use extract::{
+    extract_each, extract_each_cancellable, extract_matching, extract_matching_cancellable,
+    ExtractionReport,
+};
+pub use extra::{get_dir_list, get_dir_list_with_depth, get_leaf_dir_list, walk_files, TreeDepth};
+pub use observer::ArchiveObserver;
+pub use options::{XzOptions, XzOptionsBuilder, ZipOptions, ZipOptionsBuilder};
+pub use preflight::PreflightReport;
+pub use preset::Preset;
+pub use process::{ArchiveEvent, Format, MessageCategory};
+pub use progress::JobProgress;
+pub use recommend::{recommend_format, Objective};
+pub use report::{ArchivedDir, CompressionReport};
+pub use results::ArchiveResults;
+pub use zip::write::FileOptions;
+pub use zip::CompressionMethod;
+
+/// Maximum number of worker threads per the available parallelism of the machine.
+const MAX_THREADS_PER_PARALLELISM: usize = 4;
+/// Total size of the input over which auto-tuning lowers the xz level.
+const AUTO_TUNE_LARGE_INPUT_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+/// The xz level used by auto-tuning for the large input.
+const AUTO_TUNE_LARGE_INPUT_XZ_LEVEL: u32 = 6;
+
+/// Compress the `files` into a single archive at `dest` in the format,
+/// with the entry names relative to `base`.
+///
+/// It is for the callers who find the files to archive by themselves.
+/// All files must be under `base`.
+/// [`Format::Xz`] writes an intermediate tarball next to `dest`, which is deleted afterward.
+///
+/// # Examples
+/// ```no_run
+/// use std::path::{Path, PathBuf};
+/// use zip_archive::{compress_files_to, Format};
+///
+/// let files = vec![
+///     PathBuf::from("origin/dir1/file1.png"),
+///     PathBuf::from("origin/dir2/file2.png"),
+/// ];
+/// compress_files_to(&files, Path::new("origin"), Path::new("dest/files.zip"), Format::Zip).unwrap();
+/// ```
+pub fn compress_files_to(
+    files: &[PathBuf],
+    base: &Path,
+    dest: &Path,
+    format: Format,
+) -> Result<PathBuf, ArchiveError> {
+    let options = CompressOptions::default();
+    Ok(compress_files(files, base, dest, &format, &options)?)
+}
+
+/// Merge the zip archives at `inputs` into a single zip archive at `output`, like to consolidate the backups.
+///
+/// The entries are copied in the order of the archives without being decompressed and compressed again,
+/// so it is as fast as copying the files.
+/// The entries of the same name in several archives are handled by `collision`,
+/// while the directory entries of the same name are merged into one.
+/// The output may be one of the inputs, since it is replaced only when the merged archive is complete.
+///
+/// # Examples
+/// ```no_run
+/// use std::path::{Path, PathBuf};
+/// use zip_archive::{merge_zips, CollisionPolicy};
+///
+/// let inputs = vec![PathBuf::from("dest/dir1.zip"), PathBuf::from("dest/dir2.zip")];
+/// merge_zips(&inputs, Path::new("dest/all.zip"), CollisionPolicy::Rename).unwrap();
+/// ```
+pub fn merge_zips(
+    inputs: &[PathBuf],
+    output: &Path,
+    collision: CollisionPolicy,
+) -> Result<(), ArchiveError> {
+    Ok(core::c_zip::merge_zips(inputs, output, collision)?)
+}
 
 /// Archiver struct.
 ///
@@ -110,8 +224,29 @@ pub struct Archiver {
     dest: Option<PathBuf>,
     thread_count: u32,
     sender: Option<Sender<String>>,
-    queue: Option<SegQueue<PathBuf>>,
+    queue: Option<SegQueue<Item>>,
+    source: Mutex<Option<Source>>,
     format: Format,
+    options: CompressOptions,
+    verbose: bool,
+    auto_tune: bool,
+    allow_empty_queue: bool,
+    io_thread_count: Option<u32>,
+    compress_thread_count: Option<u32>,
+    heartbeat_interval: Option<Duration>,
+    cancellation_token: Option<CancellationToken>,
+    delete_source: bool,
+    message_formatter: Option<MessageFormatter>,
+    message_categories: MessageCategory,
+    name_template: Option<NameTemplate>,
+    post_command: Option<String>,
+    fail_on_post_command: bool,
+    resume: bool,
+    ordered: bool,
+    bundle_tar: Option<PathBuf>,
+    delete_bundled: bool,
+    checksum_file: Option<PathBuf>,
+    spawned_thread_count: Mutex<Option<u32>>,
 }
 
 impl Archiver {
@@ -123,7 +258,28 @@ impl Archiver {
             thread_count: 1,
             sender: None,
             queue: None,
+            source: Mutex::new(None),
             format: Format::Zip,
+            options: CompressOptions::default(),
+            verbose: false,
+            auto_tune: false,
+            allow_empty_queue: false,
+            io_thread_count: None,
+            compress_thread_count: None,
+            heartbeat_interval: None,
+            cancellation_token: None,
+            delete_source: false,
+            message_formatter: None,
+            message_categories: MessageCategory::ALL,
+            name_template: None,
+            post_command: None,
+            fail_on_post_command: false,
+            resume: false,
+            ordered: false,
+            bundle_tar: None,
+            delete_bundled: false,
+            checksum_file: None,
+            spawned_thread_count: Mutex::new(None),
         }
     }
 
@@ -135,360 +291,3805 @@ impl Archiver {
     }
 
     /// Set for the number of threads.
+    ///
+    /// The number of threads spawned by [`archive`](Archiver::archive) is clamped
+    /// to the number of directories in the queue,
+    /// and to 4 times the available parallelism of the machine.
     pub fn set_thread_count(&mut self, thread_count: u32) {
         self.thread_count = thread_count;
     }
 
+    /// Set for the number of threads like [`set_thread_count`](Archiver::set_thread_count),
+    /// but return [`ArchiveError::InvalidThreadCount`] if it is zero
+    /// or over 4 times the available parallelism of the machine.
+    pub fn try_set_thread_count(&mut self, thread_count: u32) -> Result<(), ArchiveError> {
+        if thread_count == 0 || thread_count > Archiver::max_thread_count() {
+            return Err(ArchiveError::InvalidThreadCount(thread_count));
+        }
+        self.thread_count = thread_count;
+        Ok(())
+    }
+
+    /// Get the number of worker threads which [`archive`](Archiver::archive) spawns
+    /// for the directories currently in the queue,
+    /// after the auto-tuning and the clamping to the number of directories and the maximum.
+    /// If the queue is empty after archiving, it is the number of the threads spawned by the last run.
+    /// If the staging and the compression run on separate threads, it is the sum of both.
+    ///
+    /// ```
+    /// use zip_archive::Archiver;
+    ///
+    /// let mut archiver = Archiver::new();
+    /// archiver.push("origin/dir1");
+    /// archiver.push("origin/dir2");
+    /// archiver.set_thread_count(8);
+    /// assert_eq!(archiver.effective_thread_count(), 2);
+    /// ```
+    pub fn effective_thread_count(&self) -> u32 {
+        let queue_len = self.queue_len();
+        if let (0, Some(count)) = (queue_len, *self.spawned_thread_count.lock().unwrap()) {
+            return count;
+        }
+        let pipelined = (self.io_thread_count.is_some() || self.compress_thread_count.is_some())
+            && get_pipeline(self.format.clone(), self.options.clone()).is_some();
+        match pipelined {
+            true => self.pipeline_thread_counts(queue_len).iter().sum(),
+            false => self.worker_count(queue_len),
+        }
+    }
+
+    /// Set the number of threads which stage the directories for the tar-based formats,
+    /// like [`Format::Xz`], by writing the intermediate tarballs.
+    ///
+    /// If this or [`set_compress_thread_count`](Archiver::set_compress_thread_count) is set,
+    /// the staging and the compression run on separate threads,
+    /// and the one not set uses the number of threads set by [`set_thread_count`](Archiver::set_thread_count).
+    /// The other formats ignore it.
+    pub fn set_io_thread_count(&mut self, io_thread_count: u32) {
+        self.io_thread_count = Some(io_thread_count);
+    }
+
+    /// Set the number of threads which compress the staged tarballs for the tar-based formats.
+    /// See [`set_io_thread_count`](Archiver::set_io_thread_count).
+    pub fn set_compress_thread_count(&mut self, compress_thread_count: u32) {
+        self.compress_thread_count = Some(compress_thread_count);
+    }
+
+    /// Set whether to archive the directories one at a time in the order of the queue,
+    /// so the messages and the results of the directories arrive in that order on every run,
+    /// like for the golden-file tests. The directories pushed come first, then the ones from the source.
+    /// It is disabled by default.
+    ///
+    /// It costs the parallelism across the directories: a single worker thread is spawned
+    /// whatever [`set_thread_count`](Archiver::set_thread_count) or the auto-tuning sets,
+    /// and a single staging and compression thread each for the tar-based formats.
+    /// The threads within an archive, like those of [`XzOptions`], are still used.
+    /// The heartbeat messages depend on the time, so they may vary.
+    pub fn set_ordered(&mut self, ordered: bool) {
+        self.ordered = ordered;
+    }
+
+    /// Set whether to tune the archiving for the machine and the input automatically.
+    /// It is disabled by default.
+    ///
+    /// When enabled, the `archive` function:
+    /// - uses as many threads as the available parallelism of the machine,
+    ///   but no more than the number of directories in the queue,
+    ///   instead of the number set by [`set_thread_count`](Archiver::set_thread_count).
+    /// - for [`Format::Xz`], lowers the compression level from 9 to 6
+    ///   if the total size of the files to archive is over 4 GiB, to keep the runtime reasonable.
+    ///   Computing the total size walks all directories in the queue before archiving.
+    ///
+    /// To override the heuristic, disable it and set the number of threads manually.
+    pub fn set_auto_tune(&mut self, auto_tune: bool) {
+        self.auto_tune = auto_tune;
+    }
+
+    /// Set whether to walk each directory once per job and cache its [`InputStats`],
+    /// which all features needing the size of the files then share:
+    /// the [auto-tuning](Archiver::set_auto_tune), the [empty policy](Archiver::set_empty_policy),
+    /// the compression ratio of the [report](Archiver::archive_report) and the 7z advisory warnings.
+    /// It is disabled by default, so each of them walks the directory by itself.
+    ///
+    /// The cached stats are given in the [`input_stats`](CompressionReport::input_stats) of the report.
+    pub fn set_cache_input_stats(&mut self, cache_input_stats: bool) {
+        self.options.input_stats_cache = match cache_input_stats {
+            true => Some(InputStatsCache::default()),
+            false => None,
+        };
+    }
+
     /// Set the [`std::sync::mpsc::Sender`] to send messages whether compressing processes complete.
     pub fn set_sender(&mut self, sender: Sender<String>) {
         self.sender = Some(sender);
     }
 
-    /// Set the format of the file to be compressed with [Format].
-    /// For more information, see [Format].
+    /// Create a new message channel, set its sender to the archiver and return the receiver.
+    /// Replaces the sender set by [`set_sender`](Archiver::set_sender).
+    ///
+    /// The archiver keeps the sender, so iterating the receiver ends only after the archiver is dropped.
+    ///
+    /// # Examples
     /// ```
-    /// use zip_archive::{Archiver, Format};
+    /// use zip_archive::Archiver;
+    ///
     /// let mut archiver = Archiver::new();
-    /// archiver.set_format(Format::_7z);
+    /// let receiver = archiver.message_channel();
+    /// archiver.push("origin/dir1");
+    /// archiver.set_destination("dest");
+    /// archiver.archive().ok();
+    /// drop(archiver);
+    ///
+    /// for message in receiver {
+    ///     println!("{}", message);
+    /// }
     /// ```
-    pub fn set_format(&mut self, comp_format: Format) {
-        self.format = comp_format;
+    pub fn message_channel(&mut self) -> Receiver<String> {
+        let (tx, rx) = channel();
+        self.sender = Some(tx);
+        rx
     }
 
-    /// Set the format of the file to be compressed with a string. 
+    /// Set whether to sync each archive file to the disk before reporting its completion.
+    /// It is enabled by default. Disabling it makes archiving faster,
+    /// but the archive files may not be durably stored when `archive` returns.
+    pub fn set_fsync(&mut self, fsync: bool) {
+        self.options.fsync = fsync;
+    }
+
+    /// Set the directory to write the intermediate tarballs of tar based formats like [`Format::Xz`].
+    /// By default, they are written to the destination directory.
+    /// If the directory does not exist, it will be created when the `archive` function is called.
+    pub fn set_temp_dir<T: AsRef<Path>>(&mut self, temp_dir: T) {
+        self.options.temp_dir = Some(temp_dir.as_ref().to_path_buf());
+    }
+
+    /// Set the directory which the relative source paths are resolved against,
+    /// instead of the current directory of the process, which may be changed by another thread.
+    /// The 7z executable runs in it too.
+    /// The destination and the temp directory are not affected.
+    pub fn set_working_dir<T: AsRef<Path>>(&mut self, working_dir: T) {
+        self.options.working_dir = Some(working_dir.as_ref().to_path_buf());
+    }
+
+    /// Set whether to keep the intermediate tarball of each directory for [`Format::Xz`],
+    /// like `dir1.tar` next to `dir1.tar.xz`, or in the temp directory if it is set.
+    /// It is disabled by default, and the tarballs are deleted after compressed.
+    /// A kept tarball must be removed before archiving the same directory again,
+    /// since the intermediate tarball is never overwritten.
+    ///
+    /// If archiving is interrupted by a panic of a worker, the tarballs being written or compressed
+    /// are removed before the panic reaches the caller, like the temporary files of
+    /// [`set_atomic_output`](Archiver::set_atomic_output), unless they are kept.
+    pub fn set_keep_intermediate_tar(&mut self, keep: bool) {
+        self.options.keep_intermediate_tar = keep;
+    }
+
+    /// Set whether to put the contents of each directory at the root of the tarball
+    /// for tar based formats like [`Format::Xz`], without the top directory.
+    /// It is disabled by default.
+    pub fn set_flat_tar(&mut self, flat_tar: bool) {
+        self.options.flat_tar = flat_tar;
+    }
+
+    /// Set the directory which the names of the entries in the zip and tar based archives are relative to.
+    /// By default, the entry names are relative to the parent of each archived directory,
+    /// so they start with the name of the directory.
+    ///
+    /// The archived directories must be under the base directory,
+    /// and given in the same way, both relative or both absolute. Otherwise archiving them fails.
+    ///
     /// ```
     /// use zip_archive::Archiver;
     /// let mut archiver = Archiver::new();
-    /// archiver.set_format_str("7z");
+    /// archiver.push("origin/project/dir1");
+    /// // The entries are stored like `project/dir1/file1.txt`.
+    /// archiver.set_entry_base("origin");
     /// ```
-    pub fn set_format_str<T: ToString>(&mut self, comp_format_str: T) {
-        self.format = Format::from(&comp_format_str.to_string());
+    pub fn set_entry_base<T: AsRef<Path>>(&mut self, entry_base: T) {
+        self.options.entry_base = Some(entry_base.as_ref().to_path_buf());
     }
 
-    /// Push all elements in givin iterator to the queue.
-    /// It iterate through all elements and push it to the queue.
+    /// Set the number of the leading components to drop from the names of the entries
+    /// in the zip and tar based archives, like `tar --strip-components`.
+    /// The entries with no component left, like the top directory itself, are left out of the archive.
+    /// It is 0 by default.
     ///
-    /// # Examples
     /// ```
     /// use zip_archive::Archiver;
-    ///
     /// let mut archiver = Archiver::new();
-    /// archiver.push_from_iter(vec!["origin/dir1", "origin/dir2", "origin/dir3"].into_iter());
+    /// archiver.push("origin/dir1");
+    /// // The entries are stored like `file1.txt` instead of `dir1/file1.txt`.
+    /// archiver.set_strip_components(1);
     /// ```
-    pub fn push_from_iter<I>(&mut self, iter: I)
-    where
-        I: Iterator,
-        I::Item: AsRef<Path>,
-    {
-        if let None = self.queue {
-            self.queue = Some(SegQueue::new());
-        }
-        for i in iter {
-            self.queue.as_ref().unwrap().push(i.as_ref().to_path_buf());
-        }
+    pub fn set_strip_components(&mut self, strip_components: u32) {
+        self.options.strip_components = strip_components;
     }
 
-    /// Push a single directory to the queue.
+    /// Set what to do when the 7z or tar.7z archive of a directory already exists in the destination.
+    /// A skipped directory is recorded in the [`skipped_files`](Archiver::skipped_files)
+    /// and reported as complete with the existing archive.
+    /// It is [`OverwritePolicy::Error`] by default.
+    ///
+    /// The zip, tar and xz archives are always overwritten.
+    pub fn set_overwrite_policy(&mut self, overwrite: OverwritePolicy) {
+        self.options.overwrite = overwrite;
+    }
+
+    /// Set what to do with a directory which has no files left after the filters,
+    /// like [`add_excluded_dir_name`](Archiver::add_excluded_dir_name).
+    /// A skipped directory is recorded in the [`skipped_files`](Archiver::skipped_files)
+    /// with [`SkipReason::NoFiles`], and no archive is written.
+    /// It is [`EmptyPolicy::Skip`] by default.
+    pub fn set_empty_policy(&mut self, empty_policy: EmptyPolicy) {
+        self.options.empty_policy = empty_policy;
+    }
+
+    /// Set the template of the archive file names, instead of the name of each directory
+    /// with the extension of the format.
+    ///
+    /// The placeholders are:
+    /// - `{name}`: the name of the directory or the file archived.
+    /// - `{ext}`: the extension of the archive without the leading dot, like `zip` or `tar.xz`.
+    ///   The parts of [`set_files_per_archive`](Archiver::set_files_per_archive) keep their numbers in it,
+    ///   like `part001.zip`.
+    /// - `{date}`: the UTC date when the archive is written, in the `YYYY-MM-DD` format.
+    /// - `{index}`: the number of the directory from 1, in the order they start being archived.
+    ///   With a single thread, it is the order they are pushed.
+    ///
+    /// The archive is written with the default name and renamed to the expanded one.
     ///
-    /// # Examples
     /// ```
     /// use zip_archive::Archiver;
     ///
     /// let mut archiver = Archiver::new();
-    /// archiver.push("origin/dir1");
-    /// archiver.push("origin/dir2");
-    /// archiver.push("origin/dir3");
+    /// archiver.set_name_template("{name}_{date}_{index}.{ext}");
     /// ```
-    pub fn push<T: AsRef<Path>>(&mut self, path: T) {
-        if let None = self.queue {
-            self.queue = Some(SegQueue::new());
-        }
-        self.queue
-            .as_ref()
-            .unwrap()
-            .push(path.as_ref().to_path_buf());
+    pub fn set_name_template(&mut self, template: &str) {
+        self.name_template = Some(NameTemplate::new(template));
     }
 
-    /// Compress directories in the queue with multithread.
+    /// Set the shell command to run after each archive is written, like to upload or sign it.
+    /// `{path}` in the template is replaced with the quoted path of the archive,
+    /// after it is renamed by [`set_name_template`](Archiver::set_name_template).
     ///
-    /// # Examples
+    /// The command runs on the worker thread, before the archive is reported as completed.
+    /// If it exits with an error, a warning with its standard error is sent,
+    /// unless [`set_fail_on_post_command`](Archiver::set_fail_on_post_command) fails the directory instead.
     ///
     /// ```
-    /// use std::path::PathBuf;
     /// use zip_archive::Archiver;
     ///
-    /// let origin = PathBuf::from("./origin");
-    /// let dest = PathBuf::from("./dest");
-    /// let thread_count = 4;
-    ///
     /// let mut archiver = Archiver::new();
-    /// archiver.push(origin);
-    /// archiver.set_destination(dest);
-    /// archiver.set_thread_count(thread_count);
-    ///
-    /// match archiver.archive(){
-    ///     Ok(_) => (),
-    ///     Err(e) => println!("Cannot archive the directory! {}", e),
-    /// };
+    /// archiver.set_post_command("gpg --detach-sign {path}");
     /// ```
+    pub fn set_post_command(&mut self, template: &str) {
+        self.post_command = Some(template.to_string());
+    }
+
+    /// Set whether the failure of the post command set by [`set_post_command`](Archiver::set_post_command)
+    /// fails the directory of the archive, which is then reported as an error and its source is kept.
+    /// The archive is left in the destination. It is disabled by default.
+    pub fn set_fail_on_post_command(&mut self, fail: bool) {
+        self.fail_on_post_command = fail;
+    }
+
+    /// Set whether to resume the interrupted run. It is disabled by default.
     ///
-    pub fn archive(&self) -> Result<(), Box<dyn Error>> {
-        self.verify_dest()?;
-        self.verigy_queue()?;
+    /// When enabled, each directory is recorded to the `.progress` file in the destination
+    /// as soon as its archives are written.
+    /// The directories recorded by the previous runs are skipped if all their archives are still present,
+    /// and recorded in the [`skipped_files`](Archiver::skipped_files) with [`SkipReason::Completed`].
+    /// Unlike comparing the modification times, the changes of the directories since then are not noticed.
+    pub fn set_resume(&mut self, resume: bool) {
+        self.resume = resume;
+    }
 
-        let queue = Arc::new(Archiver::copy_queue(self.queue.as_ref().unwrap()));
-        let dest = Arc::new(self.dest.clone().unwrap());
+    /// Set whether to add the entry of each archived directory itself to the zip archive,
+    /// with the mode and the modification time of the directory, so that they are restored on extraction.
+    /// It is disabled by default.
+    ///
+    /// The tar based formats always store the directory entry with its metadata,
+    /// unless [`set_flat_tar`](Archiver::set_flat_tar) is enabled.
+    pub fn set_preserve_root_dir(&mut self, preserve_root_dir: bool) {
+        self.options.preserve_root_dir = preserve_root_dir;
+    }
 
-        let mut handles = Vec::new();
-        for _ in 0..self.thread_count {
-            let arc_queue = Arc::clone(&queue);
-            let arc_dest = Arc::clone(&dest);
-            let format = self.format.clone();
-            let handle;
-            match self.sender {
-                Some(ref s) => {
-                    let new_sender = s.clone();
-                    handle = thread::spawn(move || {
-                        let compressor = get_compressor(format);
-                        compressor.process(arc_queue, arc_dest, Some(new_sender));
-                    });
-                }
-                None => {
-                    handle = thread::spawn(move || {
-                        let compressor = get_compressor(format);
-                        compressor.process(arc_queue, arc_dest, None);
-                    });
-                }
-            }
-            handles.push(handle);
-        }
-        for h in handles {
-            h.join().unwrap();
-        }
+    /// Set whether to store the extended attributes of each file, like macOS Finder tags or SELinux contexts,
+    /// in the extra field of its zip entry. It is disabled by default.
+    ///
+    /// All attributes readable by the process are stored, in a field with header ID `0x7861`.
+    /// The field holds each attribute as the name length, the name, the value length and the value,
+    /// where the lengths are 2 bytes little endian.
+    /// Attributes which do not fit in a single extra field (64 KiB) are left out.
+    ///
+    /// It is supported on Unix and applies only to [`Format::Zip`]. On other platforms it does nothing.
+    pub fn set_preserve_xattrs(&mut self, preserve_xattrs: bool) {
+        self.options.preserve_xattrs = preserve_xattrs;
+    }
 
-        try_send_message(&self.sender, "Archiving Complete!".to_string());
+    /// Set the order of the entries in each zip archive, which is the filesystem order by default.
+    /// [`EntryOrder::NameAsc`] makes the same order for the same files,
+    /// and grouping the similar files may help the compression of some data.
+    /// The files are listed and sorted before written, instead of being streamed.
+    /// It also applies to the archives of the files, like [`Archiver::archive_combined`].
+    pub fn set_entry_order(&mut self, entry_order: EntryOrder) {
+        self.options.entry_order = entry_order;
+    }
 
-        Ok(())
+    /// Set whether to record the owner of each file and directory in the tarball,
+    /// as the uid and the gid with the user and group names looked up in `/etc/passwd` and `/etc/group`,
+    /// so the ownership is restored when extracted as root, like for a system backup.
+    /// It is disabled by default, and then the entries are owned by the uid and the gid 0 without names.
+    ///
+    /// It is supported on Unix and applies only to [`Format::Tar`] and [`Format::Xz`].
+    /// On other platforms it does nothing.
+    pub fn set_preserve_ownership(&mut self, preserve_ownership: bool) {
+        self.options.preserve_ownership = preserve_ownership;
     }
 
-    fn verify_dest(&self) -> Result<(), Box<dyn Error>> {
-        match &self.dest {
-            Some(p) if !p.is_dir() => {
-                create_dir_all(p)?;
-                Ok(())
-            }
-            None => Err(Box::new(io::Error::new(
-                io::ErrorKind::NotFound,
-                "Destination directory is not set",
-            ))),
-            _ => Ok(()),
-        }
+    /// Add a name of subdirectories to exclude from the archives, like `node_modules` or `.git`.
+    /// Any subdirectory whose name matches is skipped at any depth.
+    /// For 7z format, files with the same name are also skipped.
+    ///
+    /// ```
+    /// use zip_archive::Archiver;
+    /// let mut archiver = Archiver::new();
+    /// archiver.add_excluded_dir_name("node_modules");
+    /// archiver.add_excluded_dir_name("target");
+    /// ```
+    pub fn add_excluded_dir_name<T: ToString>(&mut self, name: T) {
+        self.options.excluded_dir_names.push(name.to_string());
     }
 
-    fn verigy_queue(&self) -> Result<(), Box<dyn Error>> {
-        match &self.queue {
-            Some(q) => {
-                if self.queue.as_ref().unwrap().is_empty() {
-                    return Err(Box::new(io::Error::new(
-                        io::ErrorKind::NotFound,
-                        "The queue is empty",
-                    )));
-                }
-                try_send_message(
-                    &self.sender,
-                    format!("Total archive directory count: {}", q.len()),
-                );
-                Ok(())
-            }
-            None => {
-                try_send_message(
-                    &self.sender,
-                    "There are no files to archive in queue.".to_string(),
-                );
-                Err(Box::new(io::Error::new(
-                    io::ErrorKind::NotFound,
-                    "The queue is empty",
-                )))
-            }
-        }
+    /// Set whether to read the ignore file at the root of each archived directory, like `.gitignore`,
+    /// and exclude the files and subdirectories matching its glob patterns from the archive of that directory.
+    /// The ignore file itself is left out, and the excluded paths are recorded as [`SkipReason::Excluded`].
+    /// It is disabled by default.
+    ///
+    /// Each line is a pattern, where `*` matches any characters but `/`, `?` matches one of them,
+    /// and `**` matches any characters including `/`, but only as a whole path component.
+    /// A pattern with a `/` at the start or in the middle is matched against the path from the root,
+    /// and the others against the name at any depth. A pattern ending with `/` matches only the directories.
+    ///
+    /// ```
+    /// use zip_archive::Archiver;
+    /// let mut archiver = Archiver::new();
+    /// archiver.set_honor_ignore_files(true);
+    /// ```
+    pub fn set_honor_ignore_files(&mut self, honor_ignore_files: bool) {
+        self.options.honor_ignore_files = honor_ignore_files;
     }
 
-    fn copy_queue<T>(queue: &SegQueue<T>) -> SegQueue<T> {
-        let new_queue = SegQueue::new();
-        while !queue.is_empty() {
-            new_queue.push(queue.pop().unwrap());
-        }
-        new_queue
+    /// Set the name of the ignore file read by [`set_honor_ignore_files`](Archiver::set_honor_ignore_files).
+    /// It is `.archiveignore` by default.
+    pub fn set_ignore_file_name<T: ToString>(&mut self, name: T) {
+        self.options.ignore_file_name = name.to_string();
     }
-}
 
-#[cfg(test)]
-mod tests {
+    /// Set whether to leave the files with no bytes out of the archives,
+    /// recording them as [`SkipReason::EmptyFile`].
+    /// By default, they are kept as the empty entries in all formats.
+    /// A directory with only the empty files is then treated as having no files by the [`EmptyPolicy`].
+    pub fn set_skip_empty_files(&mut self, skip_empty_files: bool) {
+        self.options.skip_empty_files = skip_empty_files;
+    }
 
-    use function_name::named;
+    /// Set the base [`FileOptions`] of every zip entry, like the compression method and level.
+    /// The permissions and the large file flag of each file are layered on top of them.
+    /// When set, they supersede the default deflate compression of zip.
+    pub fn set_zip_file_options(&mut self, zip_file_options: FileOptions) {
+        self.options.zip_file_options = Some(zip_file_options);
+    }
 
-    use crate::core::test_util::{cleanup, setup, Dir};
+    /// Set the compression method and level of every zip entry, keeping the other zip file options.
+    /// The level is passed to the method as is, like `1..=9` for deflate or `-7..=22` for zstd,
+    /// and `None` is the default level of the method.
+    ///
+    /// [`CompressionMethod::Zstd`] compresses better than deflate while staying in the `.zip` container.
+    /// But many zip tools, like the one built into Windows, cannot extract the entries compressed with it,
+    /// so use it only when the archives are read by the tools supporting zstd, like 7-Zip or this crate.
+    ///
+    /// ```
+    /// use zip_archive::{Archiver, CompressionMethod};
+    /// let mut archiver = Archiver::new();
+    /// archiver.set_zip_compression(CompressionMethod::Zstd, Some(19));
+    /// ```
+    pub fn set_zip_compression(&mut self, method: CompressionMethod, level: Option<i32>) {
+        let file_options = self.options.zip_file_options.unwrap_or_default();
+        self.options.zip_file_options = Some(
+            file_options
+                .compression_method(method)
+                .compression_level(level),
+        );
+    }
 
-    use super::*;
-    use std::sync::mpsc;
+    /// Set the compression settings of the preset at once.
+    /// The setters called after it override the settings it sets.
+    ///
+    /// | Preset | zip | xz level | 7z level | stored extensions | other |
+    /// | --- | --- | --- | --- | --- | --- |
+    /// | [`Preset::Fastest`] | deflate, level 1 | 0 | 1 | media and archives | |
+    /// | [`Preset::Balanced`] | deflate, level 6 | 6 | 5 | media and archives | |
+    /// | [`Preset::Smallest`] | deflate, level 9 | 9 | 9 | none | |
+    /// | [`Preset::Backup`] | deflate, level 6 | 6 | 5 | media and archives | fsync, atomic output, dedup |
+    ///
+    /// The stored extensions are the already compressed ones, like `jpg`, `mp4` and `zip`,
+    /// and are used only by [`Format::Zip`]. See [`set_stored_extensions`](Archiver::set_stored_extensions).
+    ///
+    /// ```
+    /// use zip_archive::{Archiver, Preset};
+    ///
+    /// let mut archiver = Archiver::new();
+    /// archiver.set_preset(Preset::Fastest);
+    /// archiver.set_fsync(false);
+    /// ```
+    pub fn set_preset(&mut self, preset: Preset) {
+        preset.apply(&mut self.options);
+    }
 
-    #[test]
-    #[named]
-    fn archive_root_dir_test() {
-        let Dir { origin, dest } = setup(function_name!());
+    /// Set the compression level of [`Format::Xz`], from 0 to 9. It is 9 by default.
+    pub fn set_xz_level(&mut self, level: u32) {
+        self.options.xz_level = level.min(9);
+    }
 
-        let mut archiver = Archiver::new();
-        archiver.push_from_iter(get_dir_list(origin).unwrap().iter());
-        archiver.set_destination(&dest);
+    /// Set the options of [`Format::Xz`], validated by [`XzOptionsBuilder::build`].
+    pub fn set_xz_options(&mut self, xz_options: XzOptions) {
+        self.options.xz_level = xz_options.level;
+        self.options.xz_extreme = xz_options.extreme;
+        self.options.xz_threads = xz_options.threads;
+    }
 
-        archiver.archive().unwrap();
+    /// Set the size of the blocks which the multithreaded compression of [`Format::Xz`]
+    /// splits each tarball into, so a single large file is compressed by all threads
+    /// set by [`set_xz_options`](Archiver::set_xz_options).
+    /// The smaller blocks give more parallelism, but slightly larger archives.
+    ///
+    /// If `None`, it is 3 times the dictionary size of the level, like 192 MiB for the level 9.
+    /// It is ignored by the single-threaded compression.
+    pub fn set_xz_block_size(&mut self, block_size: Option<u64>) {
+        self.options.xz_block_size = block_size;
+    }
 
-        assert!(dest.join("dir1.zip").is_file());
-        assert!(dest.join("dir2.zip").is_file());
-        assert!(dest.join("dir3.zip").is_file());
+    /// Set the compression method and level of [`Format::Zip`],
+    /// validated by [`ZipOptionsBuilder::build`] unlike [`set_zip_compression`](Archiver::set_zip_compression).
+    pub fn set_zip_options(&mut self, zip_options: ZipOptions) {
+        self.set_zip_compression(zip_options.method, zip_options.level);
+    }
 
-        cleanup(function_name!());
+    /// Set whether to use the Zip64 extensions of [`Format::Zip`] where the archive needs them:
+    /// more than 65535 entries, a file of 4 GiB or more, or an archive of 4 GiB or more.
+    /// They are enabled only where needed, so the other archives stay readable by the old tools.
+    /// It is enabled by default.
+    ///
+    /// If disabled, the archive exceeding the limits fails with the error telling which one,
+    /// instead of being written invalid.
+    pub fn set_zip64(&mut self, zip64: bool) {
+        self.options.zip64 = zip64;
     }
 
-    #[test]
-    #[named]
-    fn archive_root_dir_with_sender_test() {
-        let Dir { origin, dest } = setup(function_name!());
+    /// Set whether to send the advisory warnings through the sender, even if not in the verbose mode.
+    /// They are sent in the verbose mode anyway. It is disabled by default.
+    ///
+    /// The warnings only suggest better settings, and never change the archiving, like:
+    /// - [`Format::_7z`] at the level 9 on a directory which is mostly the already compressed files,
+    ///   like videos, judged by their extensions.
+    pub fn set_advisory_warnings(&mut self, advisory_warnings: bool) {
+        self.options.advisory_warnings = advisory_warnings;
+    }
 
-        let (tx, tr) = mpsc::channel();
-        {
-            let mut archiver = Archiver::new();
-            archiver.push_from_iter(get_dir_list(origin).unwrap().iter());
-            archiver.set_destination(&dest);
-            archiver.set_sender(tx);
-            archiver.archive().unwrap();
-        }
-        let mut messages = vec![];
-        for re in tr {
-            messages.push(re);
-        }
-        let mut expected_messages = vec![
-            "Total archive directory count: 3",
-            "zip archiving complete: test_dest_archive_root_dir_with_sender_test/dir2.zip",
-            "zip archiving complete: test_dest_archive_root_dir_with_sender_test/dir3.zip",
-            "zip archiving complete: test_dest_archive_root_dir_with_sender_test/dir1.zip",
-            "Archiving Complete!",
-        ];
+    /// Set the compression level of [`Format::_7z`], from 0 to 9. It is 9 by default.
+    pub fn set_7z_level(&mut self, level: u32) {
+        self.options.level_7z = level.min(9);
+    }
+
+    /// Set the extensions of the files to store without compression in the zip archive,
+    /// like `png` or `mp4` which are already compressed. The case is ignored.
+    ///
+    /// Only [`Format::Zip`] compresses each file separately, so the other formats ignore it.
+    pub fn set_stored_extensions(&mut self, extensions: Vec<String>) {
+        self.options.stored_extensions = extensions;
+    }
+
+    /// Set whether to store the files which the symlinks point to in the zip archive,
+    /// and walk into the symlinks to the directories. It is enabled by default.
+    ///
+    /// When disabled, each symlink is stored as a symlink entry with the target path as its content,
+    /// like `zip --symlinks`, so extracting it on Unix recreates the symlink.
+    /// The symlinks to the directories are stored the same way, without their contents.
+    /// On Windows, the symlinks are stored the same way, but the tools which do not support them
+    /// may extract them as the regular files containing the target path.
+    ///
+    /// Only [`Format::Zip`] supports it. The other formats ignore it.
+    pub fn set_follow_symlinks(&mut self, follow_symlinks: bool) {
+        self.options.follow_symlinks = follow_symlinks;
+    }
+
+    /// Set whether to store the files with the same content as the hardlinks
+    /// to the first one in the tarball, which shrinks the archive of the duplicated files.
+    /// The content is compared by its hash, SHA-256 unless [`Archiver::set_dedup_hash_algorithm`] sets another.
+    /// It is disabled by default.
+    ///
+    /// Only the tar-based formats, like [`Format::Xz`], support it. The other formats ignore it.
+    pub fn set_dedup(&mut self, dedup: bool) {
+        self.options.dedup = dedup;
+    }
+
+    /// Set whether to store the sparse files, like the disk images of the virtual machines,
+    /// as the GNU sparse entries in the tarball, with only their data regions and not the holes.
+    /// It keeps the archive of the files which are mostly holes small,
+    /// and the holes are restored when the tarball is extracted by GNU tar or the `tar` crate.
+    /// It is disabled by default.
+    ///
+    /// The holes are found by `SEEK_DATA` and `SEEK_HOLE` on Linux, Android and FreeBSD.
+    /// On the other platforms, or on the file systems which cannot find them,
+    /// the files are stored with their holes read as zeros, like when it is disabled.
+    /// Only the tar-based formats, like [`Format::Tar`], support it. The other formats ignore it.
+    pub fn set_sparse(&mut self, sparse: bool) {
+        self.options.sparse = sparse;
+    }
+
+    /// Set the hash algorithm of both the dedup and the checksums. It is [`HashAlgorithm::Sha256`] by default.
+    pub fn set_hash_algorithm(&mut self, algorithm: HashAlgorithm) {
+        self.options.dedup_hash = algorithm;
+        self.options.checksum_hash = algorithm;
+    }
+
+    /// Set the hash algorithm comparing the contents of the files for the dedup only.
+    /// A fast one like [`HashAlgorithm::Crc32`] speeds up the dedup of a large tree,
+    /// at the risk of storing a different file as a hardlink when the hashes collide.
+    pub fn set_dedup_hash_algorithm(&mut self, algorithm: HashAlgorithm) {
+        self.options.dedup_hash = algorithm;
+    }
+
+    /// Set the hash algorithm of the checksums of the archives only.
+    pub fn set_checksum_hash_algorithm(&mut self, algorithm: HashAlgorithm) {
+        self.options.checksum_hash = algorithm;
+    }
+
+    /// Set the total bytes of the files which all threads may read into memory at once.
+    /// Each thread waits until the size of the file it reads fits in the budget,
+    /// so the large files are read one after another.
+    /// A file larger than the whole budget is read alone.
+    /// It is unlimited by default.
+    ///
+    /// ```
+    /// use zip_archive::Archiver;
+    /// let mut archiver = Archiver::new();
+    /// archiver.set_memory_budget(512 * 1024 * 1024);
+    /// ```
+    pub fn set_memory_budget(&mut self, bytes: u64) {
+        self.options.memory_budget = Some(MemoryBudget::new(bytes));
+    }
+
+    /// Set the number of the directories which may be archived at once in the format, at least 1,
+    /// even if more threads are free. It caps the memory hungry formats like [`Format::Xz`] at the high levels
+    /// in a batch mixing the formats by [`push_with_format`](Archiver::push_with_format),
+    /// while the other formats use all threads. It is unlimited by default.
+    ///
+    /// With [`set_io_thread_count`](Archiver::set_io_thread_count), it limits the compression of the staged files.
+    ///
+    /// ```
+    /// use zip_archive::{Archiver, Format};
+    /// let mut archiver = Archiver::new();
+    /// archiver.set_thread_count(8);
+    /// archiver.set_format_concurrency(Format::Xz, 2);
+    /// ```
+    pub fn set_format_concurrency(&mut self, format: Format, limit: usize) {
+        let slots = Semaphore::new(limit);
+        self.options
+            .format_concurrency
+            .retain(|(f, _)| *f != format);
+        self.options.format_concurrency.push((format, slots));
+    }
+
+    /// Set the observer which receives the start, the progress, the completion,
+    /// the skipped files and the errors of archiving. See [`ArchiveObserver`].
+    pub fn set_observer<O: ArchiveObserver + 'static>(&mut self, observer: O) {
+        self.options.observer = Some(Arc::new(observer));
+    }
+
+    /// Set the function which transforms the content of each file before it is archived,
+    /// like stripping the metadata of images. It gets the path and the content of the file.
+    /// If it fails, the file is left out of the archive and recorded in the
+    /// [`skipped_files`](Archiver::skipped_files) with [`SkipReason::TransformFailed`].
+    ///
+    /// Only the zip and tar based formats apply it.
+    /// Each file is read into memory as a whole to be transformed, instead of being streamed.
+    ///
+    /// ```
+    /// use zip_archive::Archiver;
+    /// let mut archiver = Archiver::new();
+    /// archiver.set_content_transform(|_path, content| Ok(content.to_ascii_uppercase()));
+    /// ```
+    pub fn set_content_transform<F>(&mut self, transform: F)
+    where
+        F: Fn(&Path, Vec<u8>) -> std::io::Result<Vec<u8>> + Send + Sync + 'static,
+    {
+        self.options.content_transform = Some(Arc::new(transform));
+    }
+
+    /// Set the number of files to put in each zip archive.
+    /// If it is set, each directory is split into the independent archives
+    /// like `dir1.part001.zip`, `dir1.part002.zip` and so on.
+    /// `None` or zero puts all files in a single archive, which is the default.
+    pub fn set_files_per_archive(&mut self, files_per_archive: Option<usize>) {
+        self.options.files_per_archive = files_per_archive.filter(|&n| n > 0);
+    }
+
+    /// Set whether to write each archive to a temporary file, like `dir1.zip.tmp`,
+    /// and rename it to the archive path only after it is successfully written.
+    /// So the incomplete archives are never seen at the archive path.
+    /// If the temp directory is set by [`Archiver::set_temp_dir`], the temporary files are written there,
+    /// and copied to the destination first if it is on another filesystem, like a network mount.
+    /// It is disabled by default.
+    pub fn set_atomic_output(&mut self, atomic_output: bool) {
+        self.options.atomic_output = atomic_output;
+    }
+
+    /// Set the interval to send a heartbeat message, like `Still working on dir1 (45s elapsed)`,
+    /// through the sender while each directory is being archived.
+    /// So a UI does not look frozen while a large file is compressed.
+    /// `None` disables it, which is the default.
+    pub fn set_heartbeat_interval(&mut self, heartbeat_interval: Option<Duration>) {
+        self.heartbeat_interval = heartbeat_interval;
+    }
+
+    /// Set whether to read the percentages printed by the 7z executable while it archives each directory,
+    /// and pass them to [`ArchiveObserver::on_percent`] of the observer.
+    /// The 7z executable is run with `-bsp1` to print them.
+    /// The output which is not a percentage is ignored, so nothing is reported by the builds printing none.
+    /// It is disabled by default.
+    pub fn set_7z_progress(&mut self, progress: bool) {
+        self.options.progress_7z = progress;
+    }
+
+    /// Set the name of a tarball in the destination to bundle the archives of the directories into,
+    /// after all of them are written by [`archive`](Archiver::archive),
+    /// [`archive_with`](Archiver::archive_with) or [`archive_report`](Archiver::archive_report).
+    /// Each archive is stored in the tarball under its file name, in the order of the names.
+    /// Set `None` to keep the separate archives only. It is `None` by default.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use zip_archive::Archiver;
+    ///
+    /// let mut archiver = Archiver::new();
+    /// archiver.push("origin/dir1");
+    /// archiver.push("origin/dir2");
+    /// archiver.set_destination("dest");
+    /// archiver.set_bundle_into_tar(Some("bundle.tar"));
+    /// archiver.set_delete_bundled_archives(true);
+    /// archiver.archive().unwrap();
+    /// ```
+    pub fn set_bundle_into_tar<P: AsRef<Path>>(&mut self, name: Option<P>) {
+        self.bundle_tar = name.map(|name| name.as_ref().to_path_buf());
+    }
+
+    /// Set whether to delete the archives of the directories after they are bundled
+    /// by [`set_bundle_into_tar`](Archiver::set_bundle_into_tar),
+    /// leaving only the tarball. It is disabled by default.
+    pub fn set_delete_bundled_archives(&mut self, delete: bool) {
+        self.delete_bundled = delete;
+    }
+
+    /// Set the path of the checksums file written after all archives are done, like `SHA256SUMS`,
+    /// relative to the destination unless it is absolute. It is `None` by default, which writes none.
+    ///
+    /// Each line is the digest of an archive in the [checksum algorithm](Archiver::set_checksum_hash_algorithm)
+    /// and its name relative to the destination, separated by two spaces like the `sha256sum` tool.
+    /// So `sha256sum -c SHA256SUMS` in the destination verifies the archives,
+    /// or `sha512sum -c` for SHA-512. CRC-32 has no such tool.
+    /// The [bundle](Archiver::set_bundle_into_tar) is listed instead of the archives it deleted.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use zip_archive::Archiver;
+    ///
+    /// let mut archiver = Archiver::new();
+    /// archiver.push("origin/dir1");
+    /// archiver.set_destination("dest");
+    /// archiver.set_checksum_file(Some("SHA256SUMS"));
+    /// archiver.archive().unwrap();
+    /// // Then `cd dest && sha256sum -c SHA256SUMS` checks dir1.zip.
+    /// ```
+    pub fn set_checksum_file<P: AsRef<Path>>(&mut self, path: Option<P>) {
+        self.checksum_file = path.map(|path| path.as_ref().to_path_buf());
+    }
+
+    /// Set whether to delete each source directory after it is archived, to free the space.
+    /// The directory is deleted only after its archives are written and read back successfully,
+    /// and a message is sent for the deletion. It is never deleted if archiving or the verification fails.
+    /// Verifying a 7z archive needs the 7z executable.
+    /// It is disabled by default.
+    pub fn set_delete_source_on_success(&mut self, delete_source: bool) {
+        self.delete_source = delete_source;
+    }
+
+    /// Set whether to compress each file in the queue into its own archive named after the file,
+    /// like `a.txt.zip` for [`Format::Zip`] or `a.txt.xz` for [`Format::Xz`], which is not a tarball.
+    /// The directories in the queue are archived as usual.
+    /// It is disabled by default, and then each file in the queue fails
+    /// with the error like `Expected a directory, got a file: a.txt`, instead of being archived.
+    pub fn set_per_file(&mut self, per_file: bool) {
+        self.options.per_file = per_file;
+    }
+
+    /// Set the environment variables of the 7z executable, like `TMPDIR` for its temporary files
+    /// or `LANG` for its locale. They are set on top of the environment inherited from this process.
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use zip_archive::Archiver;
+    ///
+    /// let mut archiver = Archiver::new();
+    /// archiver.set_7z_env(HashMap::from([("TMPDIR".to_string(), "/var/tmp".to_string())]));
+    /// ```
+    pub fn set_7z_env(&mut self, env: HashMap<String, String>) {
+        self.options.env_7z = env;
+    }
+
+    /// Set the function which makes the completion and the error message of each directory
+    /// sent through the sender, like for the translation or a log format.
+    /// The `Display` of [`ArchiveEvent`] is the default message.
+    ///
+    /// ```
+    /// use zip_archive::{ArchiveEvent, Archiver};
+    ///
+    /// let mut archiver = Archiver::new();
+    /// archiver.set_message_formatter(|event| match event {
+    ///     ArchiveEvent::Complete { archive, .. } => format!("done {}", archive.display()),
+    ///     _ => event.to_string(),
+    /// });
+    /// ```
+    pub fn set_message_formatter<F>(&mut self, formatter: F)
+    where
+        F: Fn(&ArchiveEvent) -> String + Send + Sync + 'static,
+    {
+        self.message_formatter = Some(Arc::new(formatter));
+    }
+
+    /// Set the progress of the job in bytes, to query the estimated time remaining
+    /// with [`JobProgress::eta`] from another thread while the job runs.
+    /// The total bytes are measured when the job starts, walking the pushed directories once more
+    /// unless their stats are [cached](Archiver::set_cache_input_stats).
+    /// The heartbeat messages also tell the estimated time remaining once it is known.
+    /// See [`JobProgress`] for an example.
+    pub fn set_job_progress(&mut self, job_progress: JobProgress) {
+        self.options.job_progress = Some(job_progress);
+    }
+
+    /// Set the token to cancel the job from another thread or a callback.
+    /// Once it is cancelled, the worker threads finish the directories they are archiving,
+    /// but pull no more from the queue. Then `archive` returns as usual,
+    /// and [`archive_report`](Archiver::archive_report) returns the report of the finished directories,
+    /// marked as [`cancelled`](CompressionReport::cancelled).
+    /// See [`CancellationToken`] for an example.
+    pub fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.cancellation_token = Some(token);
+    }
+
+    /// Set the flag to cancel the job like [`set_cancellation_token`](Archiver::set_cancellation_token),
+    /// which is cancelled once `true` is stored to it.
+    ///
+    /// ```no_run
+    /// use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
+    /// use zip_archive::Archiver;
+    ///
+    /// let cancel_flag = Arc::new(AtomicBool::new(false));
+    /// let mut archiver = Archiver::new();
+    /// archiver.push("origin/dir1");
+    /// archiver.set_destination("dest");
+    /// archiver.set_cancel_flag(Arc::clone(&cancel_flag));
+    ///
+    /// // On another thread, like the cancel button of a UI.
+    /// cancel_flag.store(true, Ordering::Relaxed);
+    /// ```
+    pub fn set_cancel_flag(&mut self, cancel_flag: Arc<AtomicBool>) {
+        self.cancellation_token = Some(CancellationToken::from(cancel_flag));
+    }
+
+    /// Check whether the job is cancelled by the cancellation token.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation_token
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+
+    /// Set the categories of the messages sent through the sender, finer than [`Archiver::set_verbose`].
+    /// The detailed messages of the verbose mode are sent only if their categories are set too.
+    /// All categories are sent by default.
+    ///
+    /// ```
+    /// use zip_archive::{Archiver, MessageCategory};
+    ///
+    /// let mut archiver = Archiver::new();
+    /// archiver.set_message_categories(MessageCategory::COMPLETION | MessageCategory::ERROR);
+    /// ```
+    pub fn set_message_categories(&mut self, categories: MessageCategory) {
+        self.message_categories = categories;
+    }
+
+    /// Set whether to send detailed messages, like the skipped files, the tarring and the compressing phases
+    /// of [`Format::Xz`], and the size of each archive in its completion message, through the sender.
+    /// It is disabled by default.
+    pub fn set_verbose(&mut self, verbose: bool) {
+        self.verbose = verbose;
+    }
+
+    /// Set whether [`archive`](Archiver::archive) succeeds doing nothing when there are no directories to archive,
+    /// instead of returning [`ArchiveError::QueueEmpty`].
+    /// It is disabled by default.
+    pub fn set_allow_empty_queue(&mut self, allow_empty_queue: bool) {
+        self.allow_empty_queue = allow_empty_queue;
+    }
+
+    /// Get the files and directories skipped by the last [`archive`](Archiver::archive) call,
+    /// with the reason of each.
+    /// The files which cannot be read are skipped with [`SkipReason::Unreadable`],
+    /// except in the 7z format, whose executable reads the files itself.
+    pub fn skipped_files(&self) -> Vec<(PathBuf, SkipReason)> {
+        self.options.skipped.to_vec()
+    }
+
+    /// Set the format of the file to be compressed with [Format].
+    /// For more information, see [Format].
+    /// ```
+    /// use zip_archive::{Archiver, Format};
+    /// let mut archiver = Archiver::new();
+    /// archiver.set_format(Format::_7z);
+    /// ```
+    pub fn set_format(&mut self, comp_format: Format) {
+        self.format = comp_format;
+    }
+
+    /// Set the format of the file to be compressed with a string. 
+    /// ```
+    /// use zip_archive::Archiver;
+    /// let mut archiver = Archiver::new();
+    /// archiver.set_format_str("7z");
+    /// ```
+    pub fn set_format_str<T: ToString>(&mut self, comp_format_str: T) {
+        self.format = Format::from(&comp_format_str.to_string());
+    }
+
+    /// Push all elements in givin iterator to the queue.
+    /// It iterate through all elements and push it to the queue.
+    ///
+    /// # Examples
+    /// ```
+    /// use zip_archive::Archiver;
+    ///
+    /// let mut archiver = Archiver::new();
+    /// archiver.push_from_iter(vec!["origin/dir1", "origin/dir2", "origin/dir3"].into_iter());
+    /// ```
+    pub fn push_from_iter<I>(&mut self, iter: I)
+    where
+        I: Iterator,
+        I::Item: AsRef<Path>,
+    {
+        if let None = self.queue {
+            self.queue = Some(SegQueue::new());
+        }
+        for i in iter {
+            self.queue
+                .as_ref()
+                .unwrap()
+                .push((i.as_ref().to_path_buf(), None, None));
+        }
+    }
+
+    /// Push a single directory to the queue.
+    ///
+    /// # Examples
+    /// ```
+    /// use zip_archive::Archiver;
+    ///
+    /// let mut archiver = Archiver::new();
+    /// archiver.push("origin/dir1");
+    /// archiver.push("origin/dir2");
+    /// archiver.push("origin/dir3");
+    /// ```
+    pub fn push<T: AsRef<Path>>(&mut self, path: T) {
+        if let None = self.queue {
+            self.queue = Some(SegQueue::new());
+        }
+        self.queue
+            .as_ref()
+            .unwrap()
+            .push((path.as_ref().to_path_buf(), None, None));
+    }
+
+    /// Push a single file to the queue, to be compressed into its own archive,
+    /// like `a.txt.zip` or `a.txt.xz`, in the per-file mode set by [`set_per_file`](Archiver::set_per_file).
+    /// The files are compressed in parallel like the directories.
+    ///
+    /// ```
+    /// use zip_archive::Archiver;
+    ///
+    /// let mut archiver = Archiver::new();
+    /// archiver.set_per_file(true);
+    /// archiver.push_file("logs/a.log");
+    /// archiver.push_file("logs/b.log");
+    /// ```
+    pub fn push_file<T: AsRef<Path>>(&mut self, path: T) {
+        self.push(path);
+    }
+
+    /// Push a single directory to the queue, to be archived in `format`
+    /// instead of the format set by [`set_format`](Archiver::set_format).
+    ///
+    /// # Examples
+    /// ```
+    /// use zip_archive::{Archiver, Format};
+    ///
+    /// let mut archiver = Archiver::new();
+    /// archiver.set_format(Format::Xz);
+    /// archiver.push("origin/source_code");
+    /// archiver.push_with_format("origin/rendered_media", Format::Zip);
+    /// ```
+    pub fn push_with_format<T: AsRef<Path>>(&mut self, path: T, format: Format) {
+        if let None = self.queue {
+            self.queue = Some(SegQueue::new());
+        }
+        self.queue
+            .as_ref()
+            .unwrap()
+            .push((path.as_ref().to_path_buf(), Some(format), None));
+    }
+
+    /// Set a lazy source of directories to archive.
+    /// The worker threads pull the directories from the source on demand,
+    /// after the directories pushed to the queue,
+    /// so discovering and archiving the directories overlap.
+    ///
+    /// The source is consumed by the next [`archive`](Archiver::archive) call.
+    /// With a source, the total count of directories is not sent,
+    /// and [auto-tuning](Archiver::set_auto_tune) considers only the pushed directories.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::path::PathBuf;
+    /// use zip_archive::Archiver;
+    ///
+    /// let mut archiver = Archiver::new();
+    /// archiver.set_source((1..=3).map(|i| PathBuf::from(format!("origin/dir{}", i))));
+    /// ```
+    pub fn set_source<I>(&mut self, source: I)
+    where
+        I: Iterator<Item = PathBuf> + Send + 'static,
+    {
+        self.source = Mutex::new(Some(Box::new(source)));
+    }
+
+    /// Check whether the environment is ready to archive, without compressing anything.
+    /// The report contains the availability of each format, the writability of the destination
+    /// and the number of directories in the queue.
+    ///
+    /// # Examples
+    /// ```
+    /// use zip_archive::Archiver;
+    ///
+    /// let mut archiver = Archiver::new();
+    /// archiver.set_destination("dest");
+    /// let report = archiver.preflight();
+    /// if report.seven_zip_path.is_none() {
+    ///     println!("7z format is not available.");
+    /// }
+    /// ```
+    pub fn preflight(&self) -> PreflightReport {
+        PreflightReport::new(
+            self.dest.as_deref(),
+            self.queue.as_ref().map_or(0, |q| q.len()),
+        )
+    }
+
+    /// Check the configuration of the archiver without archiving anything,
+    /// and get the first problem which would make [`archive`](Archiver::archive) fail:
+    /// - the destination is not set or not writable, nor the temp directory if it is set,
+    /// - the format is not available, like [`Format::_7z`] without its executable,
+    /// - the number of threads is zero or over 4 times the available parallelism of the machine,
+    /// - the name of the ignore file is not a file name,
+    ///   or an ignore file of a directory in the queue has an invalid pattern.
+    ///
+    /// Unlike [`preflight`](Archiver::preflight), which reports the environment,
+    /// it checks only what is used by the configuration.
+    /// The queue is read by taking out each directory and putting it back in its order,
+    /// so it takes the archiver mutably, not to be seen half empty by another thread.
+    ///
+    /// # Examples
+    /// ```
+    /// use zip_archive::{ArchiveError, Archiver};
+    ///
+    /// let mut archiver = Archiver::new();
+    /// assert!(matches!(archiver.validate(), Err(ArchiveError::DestinationNotSet)));
+    /// ```
+    pub fn validate(&mut self) -> Result<(), ArchiveError> {
+        let dest = self.dest.as_ref().ok_or(ArchiveError::DestinationNotSet)?;
+        for (name, dir) in [
+            ("destination", Some(dest)),
+            ("temp directory", self.options.temp_dir.as_ref()),
+        ] {
+            match dir {
+                Some(dir) if !preflight::is_writable(dir) => {
+                    return Err(ArchiveError::InvalidOption(format!(
+                        "the {} {} is not writable",
+                        name,
+                        dir.display()
+                    )))
+                }
+                _ => (),
+            }
+        }
+        if let Some(reason) = preflight::unavailable_reason(&self.format) {
+            return Err(ArchiveError::InvalidOption(format!(
+                "the {} format is not available: {}",
+                self.format.to_string(),
+                reason
+            )));
+        }
+        let thread_counts = [
+            Some(self.thread_count),
+            self.io_thread_count,
+            self.compress_thread_count,
+        ];
+        for count in thread_counts.into_iter().flatten() {
+            if count == 0 || count > Archiver::max_thread_count() {
+                return Err(ArchiveError::InvalidThreadCount(count));
+            }
+        }
+        if self.options.honor_ignore_files {
+            let name = Path::new(&self.options.ignore_file_name);
+            if name.file_name() != Some(name.as_os_str()) {
+                return Err(ArchiveError::InvalidOption(format!(
+                    "the ignore file name {:?} is not a file name",
+                    self.options.ignore_file_name
+                )));
+            }
+            if let Some(queue) = &self.queue {
+                let items: Vec<Item> = std::iter::from_fn(|| queue.pop()).collect();
+                let result = items.iter().try_for_each(|item| {
+                    IgnoreFile::for_root(&self.resolve(item.0.clone()), &self.options).map(drop)
+                });
+                for item in items {
+                    queue.push(item);
+                }
+                result?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the formats which can be used in the current environment, like for a format picker.
+    /// The built-in formats are always included, and [`Format::_7z`] only if its executable can be executed.
+    ///
+    /// # Examples
+    /// ```
+    /// use zip_archive::{Archiver, Format};
+    ///
+    /// assert!(Archiver::supported_formats().contains(&Format::Zip));
+    /// for (format, reason) in Archiver::unsupported_formats() {
+    ///     println!("{:?} is not available: {}", format, reason);
+    /// }
+    /// ```
+    pub fn supported_formats() -> Vec<Format> {
+        Format::all()
+            .iter()
+            .filter(|format| preflight::unavailable_reason(format).is_none())
+            .cloned()
+            .collect()
+    }
+
+    /// Get the formats which cannot be used in the current environment, with the reasons.
+    pub fn unsupported_formats() -> Vec<(Format, String)> {
+        Format::all()
+            .iter()
+            .filter_map(|format| preflight::unavailable_reason(format).map(|r| (format.clone(), r)))
+            .collect()
+    }
+
+    /// Compress the data read from `reader`, like a generated database dump,
+    /// into the zip archive named `zip_name` in the destination,
+    /// as a single entry named `entry_name`. No temporary file is written.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use std::io;
+    /// use zip_archive::Archiver;
+    ///
+    /// let mut archiver = Archiver::new();
+    /// archiver.set_destination("dest");
+    /// archiver
+    ///     .archive_reader(io::stdin(), "dump.sql", "dump.zip")
+    ///     .unwrap();
+    /// ```
+    pub fn archive_reader<R: Read>(
+        &self,
+        reader: R,
+        entry_name: &str,
+        zip_name: &str,
+    ) -> Result<PathBuf, ArchiveError> {
+        self.verify_dest()?;
+        let zip_path = self.dest.as_ref().unwrap().join(zip_name);
+        Ok(CompressZip::compress_reader(
+            reader,
+            entry_name,
+            zip_path,
+            &self.options,
+        )?)
+    }
+
+    /// Compress all directories in the queue into a single archive named `name` in the destination,
+    /// each under its own name, instead of an archive per directory.
+    /// The extension of the format is appended to `name`, like `all.7z`.
+    /// [`Format::_7z`] passes all directories to one execution of 7z,
+    /// so its solid blocks span them and compress better.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use zip_archive::{Archiver, Format};
+    ///
+    /// let mut archiver = Archiver::new();
+    /// archiver.push("origin/dir1");
+    /// archiver.push("origin/dir2");
+    /// archiver.set_destination("dest");
+    /// archiver.set_format(Format::_7z);
+    /// let archive_path = archiver.archive_combined("all").unwrap();
+    /// ```
+    pub fn archive_combined(&self, name: &str) -> Result<PathBuf, ArchiveError> {
+        self.combine(name)?.1
+    }
+
+    /// Archive all directories in the queue into a single archive like [`archive_combined`](Archiver::archive_combined),
+    /// and get the report with the single entry of the combined archive.
+    /// Its [`source`](ArchivedDir::source) is the deepest directory containing all the directories,
+    /// and its [`input_size`](ArchivedDir::input_size) sums the files of all of them.
+    /// If it fails, the report has the single failure of the same source instead.
+    pub fn archive_combined_report(&self, name: &str) -> Result<CompressionReport, ArchiveError> {
+        let start = Instant::now();
+        let (dirs, result) = self.combine(name)?;
+        let mut report = CompressionReport {
+            skipped: self.skipped_files(),
+            ..Default::default()
+        };
+        let source = common_base(&dirs);
+        match result {
+            Ok(archive) => {
+                let mut input_size = 0;
+                for dir in &dirs {
+                    input_size += extra::input_size(dir, &self.options)?;
+                }
+                report.archives.push(ArchivedDir {
+                    source,
+                    size: archive.metadata()?.len(),
+                    input_size: Some(input_size),
+                    archive,
+                    elapsed: start.elapsed(),
+                });
+            }
+            Err(e) => report.failures.push((source, e)),
+        }
+        report.elapsed = start.elapsed();
+        Ok(report)
+    }
+
+    /// Archive all directories in the queue into a single archive named `name`,
+    /// and get the directories with the result.
+    /// Each directory is reported as added to the archive, then the archive as completed.
+    fn combine(
+        &self,
+        name: &str,
+    ) -> Result<(Vec<PathBuf>, Result<PathBuf, ArchiveError>), ArchiveError> {
+        self.verify_dest()?;
+        self.verigy_queue()?;
+        let mut dirs = Vec::new();
+        while let Some((dir, _, _)) = self.queue.as_ref().and_then(|q| q.pop()) {
+            dirs.push(self.resolve(dir));
+        }
+        let archive_path =
+            self.dest
+                .as_ref()
+                .unwrap()
+                .join(format!("{}{}", name, self.format.extension()));
+        if self.verbose && self.format == Format::_7z {
+            if let Ok(command_line) =
+                Compress7z::combined_command_line(&dirs, &archive_path, &self.options)
+            {
+                self.send_message(
+                    MessageCategory::PROGRESS,
+                    format!("7z command: {}", command_line),
+                );
+            }
+        }
+        let reporter = self.reporter(&None);
+        let message = Message::new(self.format.clone());
+        let result = match compress_combined(&dirs, &archive_path, &self.format, &self.options) {
+            Ok(archive_path) => {
+                for dir in &dirs {
+                    reporter.send(
+                        MessageCategory::COMPLETION,
+                        format!(
+                            "Added {} to the combined archive: {}",
+                            dir.display(),
+                            archive_path.display()
+                        ),
+                    );
+                }
+                reporter.report(&message, Ok(archive_path.clone()));
+                Ok(archive_path)
+            }
+            Err(e) => {
+                reporter.report(&message, Err(io::Error::new(e.kind(), e.to_string())));
+                Err(ArchiveError::from(e))
+            }
+        };
+        self.finish();
+        Ok((dirs, result))
+    }
+
+    /// Compress directories in the queue with multithread.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::path::PathBuf;
+    /// use zip_archive::Archiver;
+    ///
+    /// let origin = PathBuf::from("./origin");
+    /// let dest = PathBuf::from("./dest");
+    /// let thread_count = 4;
+    ///
+    /// let mut archiver = Archiver::new();
+    /// archiver.push(origin);
+    /// archiver.set_destination(dest);
+    /// archiver.set_thread_count(thread_count);
+    ///
+    /// match archiver.archive(){
+    ///     Ok(_) => (),
+    ///     Err(e) => println!("Cannot archive the directory! {}", e),
+    /// };
+    /// ```
+    ///
+    pub fn archive(&self) -> Result<(), ArchiveError> {
+        self.archive_inner(None)
+    }
+
+    /// Find the directories under `root` at `depth`, and archive the ones `filter` accepts in `format`,
+    /// along with the directories already pushed.
+    ///
+    /// The archives are written directly into the destination set by
+    /// [`set_destination`](Archiver::set_destination), named after each directory,
+    /// not into the subdirectories mirroring the tree.
+    /// So the directories of the same name in different branches are handled
+    /// by [`set_overwrite_policy`](Archiver::set_overwrite_policy).
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use zip_archive::{Archiver, Format, TreeDepth};
+    ///
+    /// let mut archiver = Archiver::new();
+    /// archiver.set_destination("dest");
+    /// archiver
+    ///     .archive_tree("origin", TreeDepth::Leaves, Format::Zip, |dir| {
+    ///         !dir.ends_with("cache")
+    ///     })
+    ///     .unwrap();
+    /// ```
+    pub fn archive_tree<P, F>(
+        &mut self,
+        root: P,
+        depth: TreeDepth,
+        format: Format,
+        filter: F,
+    ) -> Result<(), ArchiveError>
+    where
+        P: AsRef<Path>,
+        F: Fn(&Path) -> bool,
+    {
+        self.verify_dest()?;
+        for dir in depth.dir_list(self.resolve(root.as_ref().to_path_buf()))? {
+            if filter(&dir) {
+                self.push_with_format(dir, format.clone());
+            }
+        }
+        self.archive()
+    }
+
+    /// Add a source root named `name`, whose directories at `depth` are archived in `format`
+    /// into the subtree `name` of the destination, mirroring their paths under the root.
+    /// So the roots of a job can go to their own subtrees in their own formats,
+    /// and the pushed directories still go directly into the destination.
+    ///
+    /// The directories are found when the root is added, and archived by the next [`archive`](Archiver::archive),
+    /// which creates the subtrees. The name must be a relative path, like `data` or `backup/data`.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use zip_archive::{Archiver, Format, TreeDepth};
+    ///
+    /// let mut archiver = Archiver::new();
+    /// archiver.set_destination("dest");
+    /// // Like dest/data/2024/logs.zip for /data/2024/logs.
+    /// archiver.add_root("data", "/data", TreeDepth::Leaves, Format::Zip).unwrap();
+    /// // Like dest/configs/nginx.tar.xz for /configs/nginx.
+    /// archiver.add_root("configs", "/configs", TreeDepth::Depth(1), Format::Xz).unwrap();
+    /// archiver.archive().unwrap();
+    /// ```
+    pub fn add_root<N, P>(
+        &mut self,
+        name: N,
+        root: P,
+        depth: TreeDepth,
+        format: Format,
+    ) -> Result<(), ArchiveError>
+    where
+        N: AsRef<Path>,
+        P: AsRef<Path>,
+    {
+        let name = name.as_ref();
+        if name.as_os_str().is_empty()
+            || !name.components().all(|c| matches!(c, Component::Normal(_)))
+        {
+            return Err(ArchiveError::InvalidOption(format!(
+                "the root name {:?} is not a relative path",
+                name
+            )));
+        }
+        let root = self.resolve(root.as_ref().to_path_buf());
+        let queue = self.queue.get_or_insert_with(SegQueue::new);
+        for dir in depth.dir_list(&root)? {
+            let subdir = match dir.parent().and_then(|p| p.strip_prefix(&root).ok()) {
+                Some(relative) => name.join(relative),
+                None => name.to_path_buf(),
+            };
+            queue.push((dir, Some(format.clone()), Some(subdir)));
+        }
+        Ok(())
+    }
+
+    /// Archive the directories like [`archive`](Archiver::archive),
+    /// passing the result of each directory to the callback as soon as it is done.
+    ///
+    /// The callback runs on the worker threads, so it can be called concurrently
+    /// and must be thread-safe. The messages are still sent through the sender.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use zip_archive::Archiver;
+    ///
+    /// let mut archiver = Archiver::new();
+    /// archiver.push("origin/dir1");
+    /// archiver.push("origin/dir2");
+    /// archiver.set_destination("dest");
+    /// archiver.set_thread_count(2);
+    ///
+    /// archiver
+    ///     .archive_with(|result| match result {
+    ///         Ok(path) => println!("Upload {:?}", path),
+    ///         Err(e) => println!("Cannot archive the directory! {}", e),
+    ///     })
+    ///     .unwrap();
+    /// ```
+    pub fn archive_with<F>(&self, callback: F) -> Result<(), ArchiveError>
+    where
+        F: Fn(Result<PathBuf, ArchiveError>) + Send + Sync + 'static,
+    {
+        self.archive_inner(Some(Arc::new(move |outcome: Outcome| {
+            callback(outcome.result)
+        })))
+    }
+
+    /// Archive the directories like [`archive`](Archiver::archive) in the background,
+    /// and get the iterator over the result of each directory as soon as it is done.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use zip_archive::Archiver;
+    ///
+    /// let mut archiver = Archiver::new();
+    /// archiver.push("origin/dir1");
+    /// archiver.push("origin/dir2");
+    /// archiver.set_destination("dest");
+    /// archiver.set_thread_count(2);
+    ///
+    /// for result in archiver.archive_iter().unwrap() {
+    ///     match result {
+    ///         Ok(path) => println!("Upload {:?}", path),
+    ///         Err(e) => println!("Cannot archive the directory! {}", e),
+    ///     }
+    /// }
+    /// ```
+    pub fn archive_iter(&self) -> Result<ArchiveResults<'_>, ArchiveError> {
+        let (sender, receiver) = channel();
+        let callback: ResultCallback = Arc::new(move |outcome: Outcome| {
+            sender.send(outcome.result).ok();
+        });
+        let handles = self.spawn(Some(callback))?;
+        Ok(ArchiveResults::new(self, receiver, handles))
+    }
+
+    /// Archive the directories like [`archive`](Archiver::archive),
+    /// and get the report of the produced archives, the failures and the skips of the whole job.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use zip_archive::Archiver;
+    ///
+    /// let mut archiver = Archiver::new();
+    /// archiver.push("origin/dir1");
+    /// archiver.set_destination("dest");
+    ///
+    /// let report = archiver.archive_report().unwrap();
+    /// for archived in &report.archives {
+    ///     println!("{:?}: {} bytes", archived.archive, archived.size);
+    /// }
+    /// for (dir, e) in &report.failures {
+    ///     println!("Cannot archive {:?}! {}", dir, e);
+    /// }
+    /// ```
+    pub fn archive_report(&self) -> Result<CompressionReport, ArchiveError> {
+        let start = Instant::now();
+        let outcomes = Arc::new(Mutex::new(Vec::new()));
+        let arc_outcomes = Arc::clone(&outcomes);
+        self.archive_inner(Some(Arc::new(move |outcome: Outcome| {
+            arc_outcomes.lock().unwrap().push(outcome);
+        })))?;
+        let outcomes = std::mem::take(&mut *outcomes.lock().unwrap());
+        let mut report = CompressionReport::new(
+            outcomes,
+            self.skipped_files(),
+            start.elapsed(),
+            self.is_cancelled(),
+        );
+        if let Some(cache) = &self.options.input_stats_cache {
+            report.input_stats = cache.to_map();
+        }
+        Ok(report)
+    }
+
+    fn archive_inner(&self, callback: Option<ResultCallback>) -> Result<(), ArchiveError> {
+        // Remove the intermediate files left if a worker panics.
+        let _scratch = self.options.scratch.guard();
+        let archives = Arc::new(Mutex::new(Vec::new()));
+        let collects_archives = self.bundle_tar.is_some() || self.checksum_file.is_some();
+        let callback = match collects_archives {
+            true => {
+                let archives = Arc::clone(&archives);
+                Some(Arc::new(move |outcome: Outcome| {
+                    if let Ok(archive) = &outcome.result {
+                        archives.lock().unwrap().push(archive.clone());
+                    }
+                    if let Some(callback) = &callback {
+                        callback(outcome);
+                    }
+                }) as ResultCallback)
+            }
+            false => callback,
+        };
+        let handles = match self.spawn(callback)? {
+            Some(handles) => handles,
+            None => return Ok(()),
+        };
+        for h in handles {
+            h.join().unwrap();
+        }
+        let mut archives = std::mem::take(&mut *archives.lock().unwrap());
+        archives.sort();
+        // The lifecycle message is sent even if a step after archiving fails.
+        let result = self.after_archiving(archives);
+        if let Err(e) = &result {
+            self.send_message(MessageCategory::ERROR, e.to_string());
+        }
+        self.finish();
+        result
+    }
+
+    /// Bundle the written `archives` and write their checksums, if they are set.
+    fn after_archiving(&self, mut archives: Vec<PathBuf>) -> Result<(), ArchiveError> {
+        if let Some(name) = &self.bundle_tar {
+            let bundle_path = self.bundle(name, &archives)?;
+            if self.delete_bundled {
+                archives.clear();
+            }
+            archives.push(bundle_path);
+        }
+        if let Some(path) = &self.checksum_file {
+            let dest = self.dest.as_ref().unwrap();
+            let path = dest.join(path);
+            write_checksum_file(&path, &archives, dest, self.options.checksum_hash)?;
+            self.send_message(
+                MessageCategory::COMPLETION,
+                format!(
+                    "Wrote the checksums of {} archives to {}",
+                    archives.len(),
+                    path.display()
+                ),
+            );
+        }
+        Ok(())
+    }
+
+    /// Bundle the archives into the tarball named `name` in the destination,
+    /// and delete them if it is set. Returns the path of the tarball.
+    fn bundle(&self, name: &Path, archives: &[PathBuf]) -> Result<PathBuf, ArchiveError> {
+        let bundle_path = self.dest.as_ref().unwrap().join(name);
+        write_bundle(&bundle_path, archives, &self.options)?;
+        if self.delete_bundled {
+            for archive in archives.iter() {
+                std::fs::remove_file(archive)?;
+            }
+        }
+        self.send_message(
+            MessageCategory::COMPLETION,
+            format!(
+                "Bundled {} archives into {}",
+                archives.len(),
+                bundle_path.display()
+            ),
+        );
+        Ok(bundle_path)
+    }
+
+    /// Spawn the worker threads to archive the directories.
+    /// Returns `None` if there is nothing to do and an empty queue is allowed.
+    fn spawn(
+        &self,
+        callback: Option<ResultCallback>,
+    ) -> Result<Option<Vec<JoinHandle<()>>>, ArchiveError> {
+        self.verify_dest()?;
+        self.verify_temp_dir()?;
+        match self.verigy_queue() {
+            Err(ArchiveError::QueueEmpty) if self.allow_empty_queue => return Ok(None),
+            result => result?,
+        }
+
+        let queue_len = self.queue_len();
+        let queue = self
+            .queue
+            .as_ref()
+            .map_or_else(SegQueue::new, Archiver::copy_queue);
+        let queue = self.resolve_queue(queue);
+        self.create_subtrees(&queue)?;
+        let source = self.source.lock().unwrap().take().map(|source| {
+            let working_dir = self.options.working_dir.clone();
+            Box::new(source.map(move |dir| match &working_dir {
+                Some(working_dir) => working_dir.join(dir),
+                None => dir,
+            })) as Source
+        });
+        let thread_count = self.worker_count(queue_len);
+        if let Some(cache) = &self.options.input_stats_cache {
+            cache.clear();
+        }
+        let mut options = self.tuned_options(&queue);
+        if let Some(job_progress) = &options.job_progress {
+            job_progress.start(Archiver::input_size(&queue, &options));
+        }
+        let dest = Arc::new(self.dest.clone().unwrap());
+        if self.resume {
+            options.resume = Some(ResumeLog::open(&dest)?);
+        }
+        let queue = Arc::new(
+            WorkQueue::new(queue, source).with_cancellation_token(self.cancellation_token.clone()),
+        );
+
+        self.options.skipped.clear();
+        if let Some(template) = &self.name_template {
+            template.reset();
+        }
+        let pipelined = self.io_thread_count.is_some() || self.compress_thread_count.is_some();
+        let compressors = Arc::new(Compressors::new(self.format.clone(), options, pipelined));
+        let handles = match compressors.is_pipelined() {
+            true => {
+                let [io_thread_count, compress_thread_count] =
+                    self.pipeline_thread_counts(queue_len);
+                self.spawn_pipeline(
+                    compressors,
+                    io_thread_count,
+                    compress_thread_count,
+                    queue,
+                    dest,
+                    callback,
+                )
+            }
+            false => self.spawn_workers(compressors, thread_count, queue, dest, callback),
+        };
+        *self.spawned_thread_count.lock().unwrap() = Some(handles.len() as u32);
+        Ok(Some(handles))
+    }
+
+    /// Send the messages after all worker threads are done.
+    fn finish(&self) {
+        if self.verbose {
+            for (path, reason) in self.skipped_files() {
+                self.send_message(
+                    MessageCategory::COMPLETION,
+                    format!("Skipped ({}): {}", reason, path.to_str().unwrap_or("")),
+                );
+            }
+        }
+        let message = match self.is_cancelled() {
+            true => "Archiving Cancelled!",
+            false => "Archiving Complete!",
+        };
+        self.send_message(MessageCategory::LIFECYCLE, message.to_string());
+    }
+
+    /// Send the message through the sender, if the category is enabled.
+    fn send_message(&self, category: MessageCategory, message: String) {
+        if self.message_categories.contains(category) {
+            try_send_message(&self.sender, message);
+        }
+    }
+
+    fn verify_dest(&self) -> Result<(), ArchiveError> {
+        match &self.dest {
+            Some(p) if !p.is_dir() => {
+                create_dir_all(p)?;
+                Ok(())
+            }
+            None => Err(ArchiveError::DestinationNotSet),
+            _ => Ok(()),
+        }
+    }
+
+    fn verify_temp_dir(&self) -> Result<(), ArchiveError> {
+        match &self.options.temp_dir {
+            Some(p) if !p.is_dir() => {
+                create_dir_all(p)?;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn verigy_queue(&self) -> Result<(), ArchiveError> {
+        if self.source.lock().unwrap().is_some() {
+            return Ok(());
+        }
+        match &self.queue {
+            Some(q) => {
+                if self.queue.as_ref().unwrap().is_empty() {
+                    return Err(ArchiveError::QueueEmpty);
+                }
+                self.send_message(
+                    MessageCategory::LIFECYCLE,
+                    format!("Total archive directory count: {}", q.len()),
+                );
+                Ok(())
+            }
+            None => {
+                self.send_message(
+                    MessageCategory::ERROR,
+                    "There are no files to archive in queue.".to_string(),
+                );
+                Err(ArchiveError::QueueEmpty)
+            }
+        }
+    }
+
+    /// Create the reporter of a worker thread.
+    fn reporter(&self, callback: &Option<ResultCallback>) -> Reporter {
+        Reporter::new(self.sender.clone(), callback.clone())
+            .with_heartbeat_interval(self.heartbeat_interval)
+            .with_job_progress(self.options.job_progress.clone())
+            .with_verbose(self.verbose)
+            .with_observer(self.options.observer.clone())
+            .with_delete_source(self.delete_source)
+            .with_formatter(self.message_formatter.clone())
+            .with_categories(self.message_categories)
+            .with_name_template(self.name_template.clone())
+            .with_post_command(
+                self.post_command
+                    .as_deref()
+                    .map(|template| PostCommand::new(template, self.fail_on_post_command)),
+            )
+    }
+
+    /// Spawn the worker threads which archive the directories with the compressors.
+    fn spawn_workers(
+        &self,
+        compressors: Arc<Compressors>,
+        thread_count: u32,
+        queue: Arc<WorkQueue>,
+        dest: Arc<PathBuf>,
+        callback: Option<ResultCallback>,
+    ) -> Vec<JoinHandle<()>> {
+        let mut handles = Vec::new();
+        for _ in 0..thread_count {
+            let arc_queue = Arc::clone(&queue);
+            let arc_dest = Arc::clone(&dest);
+            let arc_compressors = Arc::clone(&compressors);
+            let reporter = self.reporter(&callback);
+            let handle = thread::spawn(move || {
+                arc_compressors.process(&arc_queue, &arc_dest, &reporter);
+            });
+            handles.push(handle);
+        }
+        handles
+    }
+
+    /// Spawn the staging threads and the compression threads of the pipeline.
+    /// The number of the staged files waiting for the compression is bounded
+    /// by the number of the compression threads.
+    fn spawn_pipeline(
+        &self,
+        compressors: Arc<Compressors>,
+        io_thread_count: u32,
+        compress_thread_count: u32,
+        queue: Arc<WorkQueue>,
+        dest: Arc<PathBuf>,
+        callback: Option<ResultCallback>,
+    ) -> Vec<JoinHandle<()>> {
+        let (staged_sender, staged_receiver) = sync_channel(compress_thread_count as usize);
+        let staged_receiver = Arc::new(Mutex::new(staged_receiver));
+
+        let mut handles = Vec::new();
+        for _ in 0..io_thread_count {
+            let arc_queue = Arc::clone(&queue);
+            let arc_dest = Arc::clone(&dest);
+            let arc_compressors = Arc::clone(&compressors);
+            let staged_sender = staged_sender.clone();
+            let reporter = self.reporter(&callback);
+            let handle = thread::spawn(move || {
+                arc_compressors.stage(&arc_queue, &arc_dest, &staged_sender, &reporter);
+            });
+            handles.push(handle);
+        }
+        for _ in 0..compress_thread_count {
+            let arc_receiver = Arc::clone(&staged_receiver);
+            let arc_dest = Arc::clone(&dest);
+            let arc_compressors = Arc::clone(&compressors);
+            let handle = thread::spawn(move || {
+                arc_compressors.compress_staged(&arc_receiver, &arc_dest);
+            });
+            handles.push(handle);
+        }
+        handles
+    }
+
+    /// Get the number of the directories to archive, or `usize::MAX` if they come from a source.
+    fn queue_len(&self) -> usize {
+        if self.source.lock().unwrap().is_some() {
+            return usize::MAX;
+        }
+        self.queue.as_ref().map_or(0, |q| q.len())
+    }
+
+    /// Get the numbers of the staging and the compression threads to archive `queue_len` directories.
+    /// The one not set uses the number of the worker threads.
+    fn pipeline_thread_counts(&self, queue_len: usize) -> [u32; 2] {
+        let thread_count = self.worker_count(queue_len);
+        [self.io_thread_count, self.compress_thread_count].map(|count| match self.ordered {
+            true => 1,
+            false => count.map_or(thread_count, |c| {
+                Archiver::clamp_thread_count(c as usize, queue_len)
+            }),
+        })
+    }
+
+    /// Get the number of worker threads to archive `queue_len` directories.
+    fn worker_count(&self, queue_len: usize) -> u32 {
+        let count = if self.ordered {
+            1
+        } else if self.auto_tune {
+            thread::available_parallelism().map_or(1, |n| n.get())
+        } else {
+            self.thread_count as usize
+        };
+        Archiver::clamp_thread_count(count, queue_len)
+    }
+
+    /// Clamp the number of threads to the number of directories and the maximum, but at least 1.
+    fn clamp_thread_count(count: usize, queue_len: usize) -> u32 {
+        count
+            .min(queue_len)
+            .min(Archiver::max_thread_count() as usize)
+            .max(1) as u32
+    }
+
+    /// Get the maximum number of threads, 4 times the available parallelism of the machine.
+    fn max_thread_count() -> u32 {
+        let parallelism = thread::available_parallelism().map_or(1, |n| n.get());
+        (parallelism * MAX_THREADS_PER_PARALLELISM).min(u32::MAX as usize) as u32
+    }
+
+    /// Get the compress options tuned for the directories in `queue`.
+    fn tuned_options(&self, queue: &SegQueue<Item>) -> CompressOptions {
+        let mut options = self.options.clone();
+        if self.auto_tune
+            && self.format == Format::Xz
+            && Archiver::input_size(queue, &options) > AUTO_TUNE_LARGE_INPUT_SIZE
+        {
+            options.xz_level = options.xz_level.min(AUTO_TUNE_LARGE_INPUT_XZ_LEVEL);
+        }
+        options
+    }
+
+    /// Get the total size of the files in the directories of `queue`.
+    /// The directories which cannot be read are counted as empty.
+    fn input_size(queue: &SegQueue<Item>, options: &CompressOptions) -> u64 {
+        let mut size = 0;
+        for _ in 0..queue.len() {
+            let item = queue.pop().unwrap();
+            size += options.input_stats(&item.0).map_or(0, |s| s.size);
+            queue.push(item);
+        }
+        size
+    }
+
+    /// Create the subdirectories of the destination for the directories of the roots in `queue`.
+    fn create_subtrees(&self, queue: &SegQueue<Item>) -> Result<(), ArchiveError> {
+        let dest = self.dest.as_ref().unwrap();
+        for _ in 0..queue.len() {
+            let item = queue.pop().unwrap();
+            let result = match &item.2 {
+                Some(subdir) => create_dir_all(dest.join(subdir)),
+                None => Ok(()),
+            };
+            queue.push(item);
+            result?;
+        }
+        Ok(())
+    }
+
+    /// Resolve the relative path against the working directory if it is set.
+    fn resolve(&self, path: PathBuf) -> PathBuf {
+        match &self.options.working_dir {
+            Some(working_dir) => working_dir.join(path),
+            None => path,
+        }
+    }
+
+    /// Resolve the paths of the items in the queue against the working directory if it is set.
+    fn resolve_queue(&self, queue: SegQueue<Item>) -> SegQueue<Item> {
+        if self.options.working_dir.is_none() {
+            return queue;
+        }
+        let new_queue = SegQueue::new();
+        while let Some((dir, format, subdir)) = queue.pop() {
+            new_queue.push((self.resolve(dir), format, subdir));
+        }
+        new_queue
+    }
+
+    fn copy_queue<T>(queue: &SegQueue<T>) -> SegQueue<T> {
+        let new_queue = SegQueue::new();
+        while !queue.is_empty() {
+            new_queue.push(queue.pop().unwrap());
+        }
+        new_queue
+    }
+}
+
+impl Clone for Archiver {
+    /// Copy the configuration of the archiver.
+    /// The new archiver starts with an empty queue and no sender.
+    fn clone(&self) -> Self {
+        Archiver {
+            dest: self.dest.clone(),
+            thread_count: self.thread_count,
+            sender: None,
+            queue: None,
+            source: Mutex::new(None),
+            format: self.format.clone(),
+            options: CompressOptions {
+                skipped: SkipList::default(),
+                scratch: ScratchFiles::default(),
+                ..self.options.clone()
+            },
+            verbose: self.verbose,
+            auto_tune: self.auto_tune,
+            allow_empty_queue: self.allow_empty_queue,
+            io_thread_count: self.io_thread_count,
+            compress_thread_count: self.compress_thread_count,
+            heartbeat_interval: self.heartbeat_interval,
+            cancellation_token: self.cancellation_token.clone(),
+            delete_source: self.delete_source,
+            message_formatter: self.message_formatter.clone(),
+            message_categories: self.message_categories,
+            name_template: self
+                .name_template
+                .as_ref()
+                .map(|t| NameTemplate::new(t.as_str())),
+            post_command: self.post_command.clone(),
+            fail_on_post_command: self.fail_on_post_command,
+            resume: self.resume,
+            ordered: self.ordered,
+            bundle_tar: self.bundle_tar.clone(),
+            delete_bundled: self.delete_bundled,
+            checksum_file: self.checksum_file.clone(),
+            spawned_thread_count: Mutex::new(None),
+        }
+    }
+}
+
+impl fmt::Debug for Archiver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Archiver")
+            .field("dest", &self.dest)
+            .field("thread_count", &self.thread_count)
+            .field("format", &self.format)
+            .field("queue_len", &self.queue.as_ref().map_or(0, |q| q.len()))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use function_name::named;
+
+    use crate::core::test_util::{cleanup, setup, tar_entries, tar_xz_entries, zip_entries, Dir};
+    use crate::extra::{format_size, get_7z_executable_path};
+    use crate::process::message_test;
+
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    #[named]
+    fn archive_root_dir_test() {
+        let Dir { origin, dest } = setup(function_name!());
+
+        let mut archiver = Archiver::new();
+        archiver.push_from_iter(get_dir_list(origin).unwrap().iter());
+        archiver.set_destination(&dest);
+
+        archiver.archive().unwrap();
+
+        assert!(dest.join("dir1.zip").is_file());
+        assert!(dest.join("dir2.zip").is_file());
+        assert!(dest.join("dir3.zip").is_file());
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn archive_root_dir_with_sender_test() {
+        let Dir { origin, dest } = setup(function_name!());
+
+        let (tx, tr) = mpsc::channel();
+        {
+            let mut archiver = Archiver::new();
+            archiver.push_from_iter(get_dir_list(origin).unwrap().iter());
+            archiver.set_destination(&dest);
+            archiver.set_sender(tx);
+            archiver.archive().unwrap();
+        }
+        let mut messages = vec![];
+        for re in tr {
+            messages.push(re);
+        }
+        let mut expected_messages = vec![
+            "Total archive directory count: 3",
+            "zip archiving complete: test_dest_archive_root_dir_with_sender_test/dir2.zip",
+            "zip archiving complete: test_dest_archive_root_dir_with_sender_test/dir3.zip",
+            "zip archiving complete: test_dest_archive_root_dir_with_sender_test/dir1.zip",
+            "Archiving Complete!",
+        ];
+
+        messages.sort();
+        expected_messages.sort();
+
+        assert_eq!(expected_messages, messages);
+        assert!(dest.join("dir1.zip").is_file());
+        assert!(dest.join("dir2.zip").is_file());
+        assert!(dest.join("dir3.zip").is_file());
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn message_channel_test() {
+        let Dir { origin, dest } = setup(function_name!());
+
+        let receiver;
+        {
+            let mut archiver = Archiver::new();
+            receiver = archiver.message_channel();
+            archiver.push_from_iter(get_dir_list(origin).unwrap().iter());
+            archiver.set_destination(&dest);
+            archiver.archive().unwrap();
+        }
+        let mut messages = receiver.iter().collect::<Vec<_>>();
+        let mut expected_messages = vec![
+            "Total archive directory count: 3",
+            "zip archiving complete: test_dest_message_channel_test/dir1.zip",
+            "zip archiving complete: test_dest_message_channel_test/dir2.zip",
+            "zip archiving complete: test_dest_message_channel_test/dir3.zip",
+            "Archiving Complete!",
+        ];
 
         messages.sort();
         expected_messages.sort();
 
-        assert_eq!(expected_messages, messages);
-        assert!(dest.join("dir1.zip").is_file());
-        assert!(dest.join("dir2.zip").is_file());
-        assert!(dest.join("dir3.zip").is_file());
+        assert_eq!(expected_messages, messages);
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn fsync_test() {
+        let Dir { origin, dest } = setup(function_name!());
+
+        for fsync in [true, false] {
+            let mut archiver = Archiver::new();
+            archiver.push_from_iter(get_dir_list(&origin).unwrap().iter());
+            archiver.set_destination(&dest);
+            archiver.set_fsync(fsync);
+            archiver.set_format(if fsync { Format::Zip } else { Format::Xz });
+            archiver.archive().unwrap();
+        }
+
+        for name in ["dir1", "dir2", "dir3"] {
+            assert!(dest.join(format!("{}.zip", name)).metadata().unwrap().len() > 0);
+            assert!(
+                dest.join(format!("{}.tar.xz", name))
+                    .metadata()
+                    .unwrap()
+                    .len()
+                    > 0
+            );
+        }
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn excluded_dir_name_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let node_modules = origin.join("dir1").join("node_modules").join("package");
+        std::fs::create_dir_all(&node_modules).unwrap();
+        std::fs::write(node_modules.join("index.js"), "module.exports = {};").unwrap();
+
+        for format in [Format::Zip, Format::Xz] {
+            let mut archiver = Archiver::new();
+            archiver.push(origin.join("dir1"));
+            archiver.set_destination(&dest);
+            archiver.set_format(format);
+            archiver.add_excluded_dir_name("node_modules");
+            archiver.archive().unwrap();
+        }
+
+        let zip_entries = zip_entries(dest.join("dir1.zip"));
+        let tar_entries = tar_xz_entries(dest.join("dir1.tar.xz"));
+        assert!(zip_entries.contains(&"dir1/file3.png".to_string()));
+        assert!(tar_entries.contains(&"dir1/file3.png".to_string()));
+        assert!(zip_entries.iter().all(|e| !e.contains("node_modules")));
+        assert!(tar_entries.iter().all(|e| !e.contains("node_modules")));
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn skipped_files_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        std::fs::create_dir_all(origin.join("dir1").join("node_modules")).unwrap();
+        std::fs::create_dir_all(origin.join("dir2").join(".git")).unwrap();
+
+        let mut archiver = Archiver::new();
+        let receiver = archiver.message_channel();
+        archiver.push_from_iter(get_dir_list(&origin).unwrap().iter());
+        archiver.set_destination(&dest);
+        archiver.set_verbose(true);
+        archiver.add_excluded_dir_name("node_modules");
+        archiver.add_excluded_dir_name(".git");
+        archiver.archive().unwrap();
+
+        let mut skipped = archiver.skipped_files();
+        skipped.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            skipped,
+            vec![
+                (
+                    origin.join("dir1").join("node_modules"),
+                    SkipReason::Excluded
+                ),
+                (origin.join("dir2").join(".git"), SkipReason::Excluded),
+            ]
+        );
+
+        drop(archiver);
+        let messages = receiver.iter().collect::<Vec<_>>();
+        assert!(messages.contains(&format!(
+            "Skipped (excluded): {}",
+            origin.join("dir1").join("node_modules").to_str().unwrap()
+        )));
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn auto_tune_test() {
+        let Dir { origin, dest } = setup(function_name!());
+
+        let mut archiver = Archiver::new();
+        archiver.set_thread_count(2);
+        assert_eq!(archiver.worker_count(3), 2);
+
+        archiver.set_auto_tune(true);
+        let parallelism = thread::available_parallelism().unwrap().get() as u32;
+        assert_eq!(archiver.worker_count(3), parallelism.min(3));
+        assert_eq!(archiver.worker_count(1), 1);
+        assert_eq!(archiver.worker_count(0), 1);
+
+        archiver.push_from_iter(get_dir_list(&origin).unwrap().iter());
+        archiver.set_destination(&dest);
+        archiver.set_format(Format::Xz);
+        let queue = Archiver::copy_queue(archiver.queue.as_ref().unwrap());
+        assert_eq!(archiver.tuned_options(&queue).xz_level, 9);
+        assert_eq!(queue.len(), 3);
+
+        archiver.push_from_iter(get_dir_list(&origin).unwrap().iter());
+        archiver.archive().unwrap();
+        assert!(dest.join("dir1.tar.xz").is_file());
+        assert!(dest.join("dir2.tar.xz").is_file());
+        assert!(dest.join("dir3.tar.xz").is_file());
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    fn clone_and_debug_test() {
+        let (tx, _rx) = mpsc::channel();
+        let mut archiver = Archiver::new();
+        archiver.set_destination("dest");
+        archiver.set_thread_count(4);
+        archiver.set_format(Format::Xz);
+        archiver.set_sender(tx);
+        archiver.push("origin/dir1");
+        archiver.push("origin/dir2");
+
+        assert_eq!(
+            format!("{:?}", archiver),
+            r#"Archiver { dest: Some("dest"), thread_count: 4, format: Xz, queue_len: 2 }"#
+        );
+
+        let cloned = archiver.clone();
+        assert_eq!(
+            format!("{:?}", cloned),
+            r#"Archiver { dest: Some("dest"), thread_count: 4, format: Xz, queue_len: 0 }"#
+        );
+        assert!(cloned.sender.is_none());
+    }
+
+    #[test]
+    #[named]
+    fn source_test() {
+        let Dir { origin, dest } = setup(function_name!());
+
+        let mut archiver = Archiver::new();
+        let source_origin = origin.clone();
+        archiver.set_source((2..=3).map(move |i| source_origin.join(format!("dir{}", i))));
+        archiver.push(origin.join("dir1"));
+        archiver.set_destination(&dest);
+        archiver.set_thread_count(2);
+        archiver.archive().unwrap();
+
+        assert!(dest.join("dir1.zip").is_file());
+        assert!(dest.join("dir2.zip").is_file());
+        assert!(dest.join("dir3.zip").is_file());
+
+        // The source is consumed.
+        assert!(matches!(archiver.archive(), Err(ArchiveError::QueueEmpty)));
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn preflight_test() {
+        let Dir { origin, dest } = setup(function_name!());
+
+        let mut archiver = Archiver::new();
+        let report = archiver.preflight();
+        assert!(!report.destination_writable);
+        assert_eq!(report.queue_len, 0);
+
+        archiver.push_from_iter(get_dir_list(&origin).unwrap().iter());
+        archiver.set_destination(&dest);
+        let report = archiver.preflight();
+        assert!(report.destination_writable);
+        assert!(report.xz_available);
+        assert!(report.zip_available);
+        assert_eq!(report.queue_len, 3);
+        if report.seven_zip_path.is_none() {
+            assert!(report.seven_zip_version.is_none());
+        }
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    fn supported_formats_test() {
+        let formats = Archiver::supported_formats();
+        assert!(formats.contains(&Format::Zip));
+        assert!(formats.contains(&Format::Xz));
+        assert!(formats.contains(&Format::Tar));
+
+        let unsupported = Archiver::unsupported_formats();
+        assert_eq!(formats.len() + unsupported.len(), 5);
+        if !get_7z_executable_path().unwrap().is_file() {
+            assert!(!formats.contains(&Format::_7z));
+            assert_eq!(unsupported[0].0, Format::_7z);
+        }
+    }
+
+    #[test]
+    #[named]
+    fn entry_order_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let dir = origin.join("dir3");
+        let mut sizes = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| {
+                let e = e.unwrap();
+                (
+                    e.metadata().unwrap().len(),
+                    format!("dir3/{}", e.file_name().to_str().unwrap()),
+                )
+            })
+            .collect::<Vec<_>>();
+        sizes.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+
+        for (order, expected) in [
+            (
+                EntryOrder::NameAsc,
+                vec![
+                    "dir3/file1.png",
+                    "dir3/file2.jpg",
+                    "dir3/file4.jpg",
+                    "dir3/file7.txt",
+                ],
+            ),
+            (
+                EntryOrder::SizeDesc,
+                sizes.iter().map(|(_, name)| name.as_str()).collect(),
+            ),
+        ] {
+            let dest = dest.join(format!("{:?}", order));
+            let mut archiver = Archiver::new();
+            archiver.push(&dir);
+            archiver.set_destination(&dest);
+            archiver.set_entry_order(order);
+            archiver.archive().unwrap();
+
+            let mut archive =
+                zip::ZipArchive::new(std::fs::File::open(dest.join("dir3.zip")).unwrap()).unwrap();
+            let names = (0..archive.len())
+                .map(|i| archive.by_index(i).unwrap().name().to_string())
+                .collect::<Vec<_>>();
+            assert_eq!(names, expected);
+        }
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    fn copy_queue_test() {
+        let queue1 = SegQueue::new();
+        queue1.push("value1");
+        queue1.push("value2");
+        queue1.push("value3");
+        queue1.push("value4");
+        let queue2 = Archiver::copy_queue(&queue1);
+
+        let mut queue_vec = Vec::new();
+        while !queue2.is_empty() {
+            queue_vec.push(queue2.pop().unwrap());
+        }
+        let mut expected_vec = vec!["value1", "value2", "value3", "value4"];
+
+        queue_vec.sort();
+        expected_vec.sort();
+
+        assert_eq!(expected_vec, queue_vec);
+    }
+
+    #[test]
+    #[named]
+    fn add_queue_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let queue = SegQueue::new();
+        for dir in origin.read_dir().unwrap() {
+            queue.push(dir.unwrap().path().to_path_buf());
+        }
+        let mut archiver = Archiver::new();
+        archiver.push_from_iter(queue.into_iter());
+        archiver.set_destination(dest.to_path_buf());
+        archiver.archive().unwrap();
+        assert!(dest.join("dir1.zip").is_file());
+        assert!(dest.join("dir2.zip").is_file());
+        assert!(dest.join("dir3.zip").is_file());
+        cleanup(function_name!());
+
+        let Dir { origin, dest } = setup(function_name!());
+        let files = get_dir_list(origin).unwrap();
+        let mut archiver = Archiver::new();
+        archiver.push_from_iter(files.iter());
+        archiver.set_destination(dest.to_path_buf());
+        archiver.archive().unwrap();
+        assert!(dest.join("dir1.zip").is_file());
+        assert!(dest.join("dir2.zip").is_file());
+        assert!(dest.join("dir3.zip").is_file());
+        cleanup(function_name!());
+
+        let Dir { origin, dest } = setup(function_name!());
+        let mut archiver = Archiver::new();
+        archiver.push_from_iter(
+            vec![
+                origin.join("dir1").to_str().unwrap(),
+                origin.join("dir2").to_str().unwrap(),
+                origin.join("dir3").to_str().unwrap(),
+            ]
+            .into_iter(),
+        );
+        archiver.set_destination(dest.to_path_buf());
+        archiver.archive().unwrap();
+        assert!(dest.join("dir1.zip").is_file());
+        assert!(dest.join("dir2.zip").is_file());
+        assert!(dest.join("dir3.zip").is_file());
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn push_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let mut archiver = Archiver::new();
+        archiver.push(origin.join("dir1"));
+        archiver.push(origin.join("dir2"));
+        archiver.set_destination(&dest);
+        archiver.archive().unwrap();
+
+        assert!(dest.join("dir1.zip").is_file());
+        assert!(dest.join("dir2.zip").is_file());
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn format_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let mut archiver = Archiver::new();
+        archiver.push_from_iter(get_dir_list(&origin).unwrap().iter());
+        archiver.set_destination(&dest);
+        archiver.set_format(Format::Xz);
+        archiver.archive().unwrap();
+
+        assert!(dest.join("dir1.tar.xz").is_file());
+        assert!(dest.join("dir2.tar.xz").is_file());
+        assert!(dest.join("dir3.tar.xz").is_file());
+
+        archiver.push_from_iter(get_dir_list(&origin).unwrap().iter());
+        archiver.set_format_str("7z");
+        archiver.archive().unwrap();
+
+        assert!(dest.join("dir1.7z").is_file());
+        assert!(dest.join("dir2.7z").is_file());
+        assert!(dest.join("dir3.7z").is_file());
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn archive_error_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let mut archiver = Archiver::new();
+        archiver.push(origin.join("dir1"));
+        assert!(matches!(
+            archiver.archive(),
+            Err(ArchiveError::DestinationNotSet)
+        ));
+
+        let mut archiver = Archiver::new();
+        archiver.set_destination(&dest);
+        assert!(matches!(archiver.archive(), Err(ArchiveError::QueueEmpty)));
+
+        archiver.set_allow_empty_queue(true);
+        archiver.archive().unwrap();
+        archiver.push_from_iter(Vec::<PathBuf>::new().iter());
+        archiver.archive().unwrap();
+        assert_eq!(dest.read_dir().unwrap().count(), 0);
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn archive_with_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let mut archiver = Archiver::new();
+        archiver.push_from_iter(get_dir_list(&origin).unwrap().iter());
+        archiver.push(origin.join("missing"));
+        archiver.set_destination(&dest);
+        archiver.set_thread_count(2);
+
+        let results = Arc::new(Mutex::new(Vec::new()));
+        let arc_results = Arc::clone(&results);
+        archiver
+            .archive_with(move |result| arc_results.lock().unwrap().push(result))
+            .unwrap();
+
+        let results = results.lock().unwrap();
+        let mut paths = results
+            .iter()
+            .filter_map(|r| r.as_ref().ok().cloned())
+            .collect::<Vec<_>>();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                dest.join("dir1.zip"),
+                dest.join("dir2.zip"),
+                dest.join("dir3.zip")
+            ]
+        );
+        assert_eq!(results.iter().filter(|r| r.is_err()).count(), 1);
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn split_thread_count_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let mut archiver = Archiver::new();
+        archiver.push_from_iter(get_dir_list(&origin).unwrap().iter());
+        archiver.set_destination(&dest);
+        archiver.set_format(Format::Xz);
+        archiver.set_io_thread_count(3);
+        archiver.set_compress_thread_count(2);
+        let receiver = archiver.message_channel();
+        archiver.archive().unwrap();
+        drop(archiver);
+
+        let mut messages: Vec<String> = receiver.iter().collect();
+        assert_eq!(messages.pop().unwrap(), "Archiving Complete!");
+        messages.retain(|m| !m.starts_with("Total archive directory count"));
+        message_test::assert_messages(&dest, Format::Xz, messages);
+        for i in 1..=3 {
+            let entries = tar_xz_entries(dest.join(format!("dir{}.tar.xz", i)));
+            assert!(!entries.is_empty());
+        }
+        assert!(!dest.join("dir1.tar").exists());
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn compress_files_to_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let files = vec![
+            origin.join("dir1").join("file3.png"),
+            origin.join("dir3").join("file1.png"),
+            origin.join("dir3").join("file7.txt"),
+        ];
+        let expected = vec!["dir1/file3.png", "dir3/file1.png", "dir3/file7.txt"];
+
+        let zip_path = compress_files_to(&files, &origin, &dest.join("files.zip"), Format::Zip);
+        let mut entries = zip_entries(zip_path.unwrap());
+        entries.sort();
+        assert_eq!(entries, expected);
+
+        let xz_path = compress_files_to(&files, &origin, &dest.join("files.tar.xz"), Format::Xz);
+        let mut entries = tar_xz_entries(xz_path.unwrap());
+        entries.sort();
+        assert_eq!(entries, expected);
+        assert_eq!(dest.read_dir().unwrap().count(), 2);
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn thread_count_clamp_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let mut archiver = Archiver::new();
+        archiver.push_from_iter(get_dir_list(&origin).unwrap().iter());
+        archiver.set_destination(&dest);
+        archiver.set_thread_count(100);
+        assert_eq!(archiver.worker_count(3), 3);
+        assert_eq!(archiver.worker_count(0), 1);
+        assert!(archiver.worker_count(usize::MAX) <= Archiver::max_thread_count());
+        archiver.archive().unwrap();
+        assert_eq!(dest.read_dir().unwrap().count(), 3);
+
+        assert!(matches!(
+            archiver.try_set_thread_count(0),
+            Err(ArchiveError::InvalidThreadCount(0))
+        ));
+        let too_many = Archiver::max_thread_count() + 1;
+        assert!(archiver.try_set_thread_count(too_many).is_err());
+        archiver.try_set_thread_count(2).unwrap();
+        assert_eq!(archiver.thread_count, 2);
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn archive_iter_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let mut archiver = Archiver::new();
+        archiver.push_from_iter(get_dir_list(&origin).unwrap().iter());
+        archiver.set_destination(&dest);
+        archiver.set_thread_count(2);
+        let receiver = archiver.message_channel();
+
+        let mut paths = archiver
+            .archive_iter()
+            .unwrap()
+            .map(|result| result.unwrap())
+            .collect::<Vec<_>>();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                dest.join("dir1.zip"),
+                dest.join("dir2.zip"),
+                dest.join("dir3.zip")
+            ]
+        );
+        assert_eq!(receiver.try_iter().last().unwrap(), "Archiving Complete!");
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn push_with_format_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let mut archiver = Archiver::new();
+        archiver.set_format(Format::Xz);
+        archiver.push(origin.join("dir1"));
+        archiver.push_with_format(origin.join("dir2"), Format::Zip);
+        archiver.push_with_format(origin.join("dir3"), Format::Xz);
+        archiver.set_destination(&dest);
+        archiver.set_thread_count(2);
+        archiver.archive().unwrap();
+
+        let mut archives = dest
+            .read_dir()
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect::<Vec<_>>();
+        archives.sort();
+        assert_eq!(archives, vec!["dir1.tar.xz", "dir2.zip", "dir3.tar.xz"]);
+
+        // The directories of the other formats are archived directly by the staging threads.
+        std::fs::remove_dir_all(&dest).unwrap();
+        archiver.push(origin.join("dir1"));
+        archiver.push_with_format(origin.join("dir2"), Format::Zip);
+        archiver.set_io_thread_count(2);
+        archiver.archive().unwrap();
+        assert!(dest.join("dir1.tar.xz").is_file());
+        assert!(dest.join("dir2.zip").is_file());
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn memory_budget_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let mut archiver = Archiver::new();
+        archiver.push_from_iter(get_dir_list(&origin).unwrap().iter());
+        archiver.set_destination(&dest);
+        archiver.set_thread_count(4);
+        archiver.set_memory_budget(1);
+
+        let results = Arc::new(Mutex::new(Vec::new()));
+        let arc_results = Arc::clone(&results);
+        archiver
+            .archive_with(move |result| arc_results.lock().unwrap().push(result))
+            .unwrap();
+
+        let results = results.lock().unwrap();
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|r| r.is_ok()));
+        assert_eq!(
+            archiver.options.memory_budget.as_ref().unwrap().available(),
+            1
+        );
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn observer_test() {
+        #[derive(Default)]
+        struct Events(Mutex<Vec<String>>);
+
+        impl ArchiveObserver for Arc<Events> {
+            fn on_start(&self, dir: &Path) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("start {}", dir.display()));
+            }
+            fn on_progress(&self, file: &Path, _bytes: u64) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("progress {}", file.display()));
+            }
+            fn on_complete(&self, archive: &Path) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("complete {}", archive.display()));
+            }
+            fn on_skip(&self, path: &Path, _reason: &SkipReason) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("skip {}", path.display()));
+            }
+            fn on_error(&self, path: &Path, _error: &std::io::Error) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("error {}", path.display()));
+            }
+        }
+
+        let Dir { origin, dest } = setup(function_name!());
+        std::fs::create_dir_all(origin.join("dir1").join("node_modules")).unwrap();
+        let events = Arc::new(Events::default());
+
+        let mut archiver = Archiver::new();
+        archiver.push(origin.join("dir1"));
+        archiver.push(origin.join("missing"));
+        archiver.set_destination(&dest);
+        archiver.set_thread_count(1);
+        archiver.add_excluded_dir_name("node_modules");
+        archiver.set_observer(Arc::clone(&events));
+        archiver.archive().unwrap();
+
+        let mut events = events.0.lock().unwrap().clone();
+        events.sort();
+        let event = |kind: &str, path: PathBuf| format!("{} {}", kind, path.display());
+        assert_eq!(
+            events,
+            vec![
+                event("complete", dest.join("dir1.zip")),
+                event("error", origin.join("missing")),
+                event("progress", origin.join("dir1").join("file3.png")),
+                event("progress", origin.join("dir1").join("file5.webp")),
+                event("skip", origin.join("dir1").join("node_modules")),
+                event("start", origin.join("dir1")),
+                event("start", origin.join("missing")),
+            ]
+        );
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn strip_components_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        std::fs::create_dir_all(origin.join("dir1").join("sub")).unwrap();
+        std::fs::write(origin.join("dir1").join("sub").join("file.txt"), "sub").unwrap();
+
+        for format in [Format::Zip, Format::Xz] {
+            let mut archiver = Archiver::new();
+            archiver.push(origin.join("dir1"));
+            archiver.set_destination(&dest);
+            archiver.set_format(format);
+            archiver.set_strip_components(1);
+            archiver.archive().unwrap();
+        }
+
+        let mut zip_entries = zip_entries(dest.join("dir1.zip"));
+        zip_entries.sort();
+        assert_eq!(zip_entries, vec!["file3.png", "file5.webp", "sub/file.txt"]);
+        let mut tar_entries = tar_xz_entries(dest.join("dir1.tar.xz"));
+        tar_entries.sort();
+        assert_eq!(
+            tar_entries,
+            vec!["file3.png", "file5.webp", "sub", "sub/file.txt"]
+        );
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    #[cfg(unix)]
+    fn preserve_root_dir_test() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let Dir { origin, dest } = setup(function_name!());
+        let dir = origin.join("dir1");
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o750)).unwrap();
+
+        for format in [Format::Zip, Format::Xz] {
+            let mut archiver = Archiver::new();
+            archiver.push(&dir);
+            archiver.set_destination(&dest);
+            archiver.set_format(format);
+            archiver.set_preserve_root_dir(true);
+            archiver.archive().unwrap();
+        }
+
+        let mut zip =
+            zip::ZipArchive::new(std::fs::File::open(dest.join("dir1.zip")).unwrap()).unwrap();
+        let root = zip.by_name("dir1/").unwrap();
+        assert!(root.is_dir());
+        assert_eq!(root.unix_mode().unwrap() & 0o777, 0o750);
+        let modified = time::OffsetDateTime::from(dir.metadata().unwrap().modified().unwrap());
+        assert_eq!(root.last_modified().year(), modified.year() as u16);
+
+        let xz = std::fs::File::open(dest.join("dir1.tar.xz")).unwrap();
+        let mut tar = tar::Archive::new(xz2::read::XzDecoder::new(xz));
+        let root = tar
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap())
+            .find(|e| e.path().unwrap() == Path::new("dir1"))
+            .unwrap();
+        assert_eq!(root.header().mode().unwrap() & 0o777, 0o750);
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn content_transform_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let text = std::fs::read_to_string(origin.join("dir3").join("file7.txt")).unwrap();
+
+        for format in [Format::Zip, Format::Xz] {
+            let mut archiver = Archiver::new();
+            archiver.push(origin.join("dir3"));
+            archiver.set_destination(&dest);
+            archiver.set_format(format);
+            archiver.set_content_transform(|path, content| {
+                match path.extension().and_then(|e| e.to_str()) {
+                    Some("txt") => Ok(content.to_ascii_uppercase()),
+                    Some("jpg") => Err(std::io::Error::other("cannot strip")),
+                    _ => Ok(content),
+                }
+            });
+            archiver.archive().unwrap();
+            assert_eq!(
+                archiver.skipped_files().len(),
+                2,
+                "both jpg files are skipped"
+            );
+        }
+
+        let mut zip =
+            zip::ZipArchive::new(std::fs::File::open(dest.join("dir3.zip")).unwrap()).unwrap();
+        let mut content = String::new();
+        zip.by_name("dir3/file7.txt")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, text.to_uppercase());
+        assert!(zip.by_name("dir3/file2.jpg").is_err());
+
+        let xz = std::fs::File::open(dest.join("dir3.tar.xz")).unwrap();
+        let mut tar = tar::Archive::new(xz2::read::XzDecoder::new(xz));
+        let mut entries = tar.entries().unwrap().map(|e| e.unwrap());
+        let mut entry = entries
+            .find(|e| e.path().unwrap() == Path::new("dir3/file7.txt"))
+            .unwrap();
+        let mut content = String::new();
+        entry.read_to_string(&mut content).unwrap();
+        assert_eq!(content, text.to_uppercase());
+        drop(entry);
+        assert!(tar_xz_entries(dest.join("dir3.tar.xz"))
+            .iter()
+            .all(|e| !e.ends_with(".jpg")));
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn delete_source_on_success_test() {
+        let Dir { origin, dest } = setup(function_name!());
+
+        for (dir, format) in [("dir1", Format::Zip), ("dir2", Format::Xz)] {
+            let mut archiver = Archiver::new();
+            let receiver = archiver.message_channel();
+            archiver.push(origin.join(dir));
+            archiver.set_destination(&dest);
+            archiver.set_format(format.clone());
+            archiver.set_delete_source_on_success(true);
+            archiver.archive().unwrap();
+
+            drop(archiver);
+            assert!(dest
+                .join(format!("{}{}", dir, format.extension()))
+                .is_file());
+            assert!(!origin.join(dir).exists());
+            assert!(receiver
+                .iter()
+                .any(|m| m == format!("Deleted the source: {}", origin.join(dir).display())));
+        }
+
+        // The source is kept if archiving fails.
+        let mut archiver = Archiver::new();
+        archiver.push(origin.join("dir3"));
+        archiver.set_destination(&dest);
+        archiver.set_entry_base(origin.join("dir1"));
+        archiver.set_delete_source_on_success(true);
+        archiver.archive().unwrap();
+        assert!(origin.join("dir3").is_dir());
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn per_file_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let files = ["file1.png", "file2.jpg", "file7.txt"].map(|f| origin.join("dir3").join(f));
+
+        for format in [Format::Zip, Format::Xz] {
+            let mut archiver = Archiver::new();
+            for file in &files {
+                archiver.push_file(file);
+            }
+            archiver.set_destination(&dest);
+            archiver.set_format(format);
+            archiver.set_thread_count(2);
+            archiver.set_per_file(true);
+            archiver.archive().unwrap();
+        }
+
+        let mut archives = std::fs::read_dir(&dest)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        archives.sort();
+        assert_eq!(
+            archives,
+            vec![
+                "file1.png.xz",
+                "file1.png.zip",
+                "file2.jpg.xz",
+                "file2.jpg.zip",
+                "file7.txt.xz",
+                "file7.txt.zip"
+            ]
+        );
+        assert_eq!(zip_entries(dest.join("file7.txt.zip")), vec!["file7.txt"]);
+        let mut content = Vec::new();
+        xz2::read::XzDecoder::new(std::fs::File::open(dest.join("file7.txt.xz")).unwrap())
+            .read_to_end(&mut content)
+            .unwrap();
+        assert_eq!(content, std::fs::read(&files[2]).unwrap());
+
+        // The files fail without the per-file mode.
+        for pipelined in [false, true] {
+            let mut archiver = Archiver::new();
+            archiver.push_file(&files[2]);
+            archiver.push(origin.join("dir1"));
+            archiver.set_destination(dest.join(format!("pipelined_{}", pipelined)));
+            if pipelined {
+                archiver.set_io_thread_count(1);
+            }
+            let report = archiver.archive_report().unwrap();
+            assert_eq!(report.archives.len(), 1);
+            assert_eq!(report.failures.len(), 1);
+            assert_eq!(
+                report.failures[0].1.to_string(),
+                format!("Expected a directory, got a file: {}", files[2].display())
+            );
+        }
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn dropped_receiver_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let mut archiver = Archiver::new();
+        let receiver = archiver.message_channel();
+        archiver.push_from_iter(get_dir_list(&origin).unwrap().iter());
+        archiver.set_destination(&dest);
+        archiver.set_thread_count(2);
+        archiver.set_verbose(true);
+        drop(receiver);
+
+        archiver.archive().unwrap();
+        assert!(dest.join("dir1.zip").is_file());
+        assert!(dest.join("dir2.zip").is_file());
+        assert!(dest.join("dir3.zip").is_file());
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn archive_report_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        std::fs::create_dir_all(origin.join("dir1").join("node_modules")).unwrap();
+        let mut archiver = Archiver::new();
+        archiver.push_from_iter(get_dir_list(&origin).unwrap().iter());
+        archiver.push(origin.join("missing"));
+        archiver.set_destination(&dest);
+        archiver.set_thread_count(2);
+        archiver.add_excluded_dir_name("node_modules");
+
+        let report = archiver.archive_report().unwrap();
+        let mut archives = report.archives.clone();
+        archives.sort_by(|a, b| a.archive.cmp(&b.archive));
+        assert_eq!(archives.len(), 3);
+        for (archived, name) in archives.iter().zip(["dir1", "dir2", "dir3"]) {
+            assert_eq!(archived.source, origin.join(name));
+            assert_eq!(archived.archive, dest.join(format!("{}.zip", name)));
+            assert_eq!(
+                archived.size,
+                std::fs::metadata(&archived.archive).unwrap().len()
+            );
+        }
+        assert_eq!(report.total_size(), archives.iter().map(|a| a.size).sum());
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].0, origin.join("missing"));
+        assert!(!report.is_success());
+        assert_eq!(
+            report.skipped,
+            vec![(
+                origin.join("dir1").join("node_modules"),
+                SkipReason::Excluded
+            )]
+        );
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn archive_tree_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        std::fs::create_dir_all(origin.join("dir1").join("sub")).unwrap();
+        std::fs::write(origin.join("dir1").join("sub").join("a.txt"), b"a").unwrap();
+
+        let mut archiver = Archiver::new();
+        archiver.set_destination(&dest);
+        archiver
+            .archive_tree(&origin, TreeDepth::Leaves, Format::Zip, |dir| {
+                !dir.ends_with("dir2")
+            })
+            .unwrap();
+
+        let mut archives = std::fs::read_dir(&dest)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        archives.sort();
+        assert_eq!(archives, vec!["dir3.zip", "sub.zip"]);
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn empty_policy_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let empty = origin.join("dir4");
+        std::fs::create_dir_all(empty.join("node_modules")).unwrap();
+        std::fs::write(empty.join("node_modules").join("a.js"), b"a").unwrap();
+
+        let mut archiver = Archiver::new();
+        let receiver = archiver.message_channel();
+        archiver.push(&empty);
+        archiver.push(origin.join("dir1"));
+        archiver.set_destination(&dest);
+        archiver.add_excluded_dir_name("node_modules");
+        archiver.archive().unwrap();
+
+        assert!(!dest.join("dir4.zip").exists());
+        assert!(dest.join("dir1.zip").is_file());
+        assert!(archiver
+            .skipped_files()
+            .contains(&(empty.clone(), SkipReason::NoFiles)));
+        assert!(receiver
+            .try_iter()
+            .any(|m| m == format!("Skipped (no files matched filters): {}", empty.display())));
+
+        archiver.set_empty_policy(EmptyPolicy::Create);
+        archiver.push(&empty);
+        archiver.archive().unwrap();
+        assert!(dest.join("dir4.zip").is_file());
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn effective_thread_count_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let mut archiver = Archiver::new();
+        archiver.push_from_iter(get_dir_list(&origin).unwrap().iter());
+        archiver.set_destination(&dest);
+        archiver.set_thread_count(64);
+        assert_eq!(archiver.effective_thread_count(), 3);
+        archiver.archive().unwrap();
+        assert_eq!(archiver.effective_thread_count(), 3);
+
+        archiver.push_from_iter(get_dir_list(&origin).unwrap().iter());
+        archiver.set_format(Format::Xz);
+        archiver.set_io_thread_count(1);
+        assert_eq!(archiver.effective_thread_count(), 4);
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn name_template_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let mut archiver = Archiver::new();
+        archiver.push(origin.join("dir1"));
+        archiver.push_with_format(origin.join("dir2"), Format::Xz);
+        archiver.set_destination(&dest);
+        archiver.set_name_template("{name}_{date}_{index}.{ext}");
+        archiver.archive().unwrap();
+
+        let date = time::OffsetDateTime::now_utc().date();
+        let mut archives = std::fs::read_dir(&dest)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        archives.sort();
+        assert_eq!(
+            archives,
+            vec![
+                format!("dir1_{}_1.zip", date),
+                format!("dir2_{}_2.tar.xz", date)
+            ]
+        );
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn preset_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let text = (0..200_000)
+            .map(|i| format!("line {} of {}\n", i % 977, i % 131))
+            .collect::<String>();
+        std::fs::create_dir_all(origin.join("text")).unwrap();
+        std::fs::write(origin.join("text").join("a.txt"), text).unwrap();
+
+        let mut sizes = Vec::new();
+        for (preset, name) in [(Preset::Fastest, "fastest"), (Preset::Smallest, "smallest")] {
+            let mut archiver = Archiver::new();
+            archiver.push(origin.join("text"));
+            archiver.set_destination(dest.join(name));
+            archiver.set_preset(preset);
+            archiver.set_format(Format::Xz);
+            archiver.archive().unwrap();
+            archiver.push(origin.join("text"));
+            archiver.set_format(Format::Zip);
+            archiver.archive().unwrap();
+            let size = |file: &str| std::fs::metadata(dest.join(name).join(file)).unwrap().len();
+            sizes.push([size("text.zip"), size("text.tar.xz")]);
+        }
+        assert!(sizes[0][0] > sizes[1][0]);
+        assert!(sizes[0][1] > sizes[1][1]);
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn resume_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let dirs = get_dir_list(&origin).unwrap();
+        let mut archiver = Archiver::new();
+        archiver.push_from_iter(dirs.iter());
+        archiver.set_destination(&dest);
+        archiver.set_resume(true);
+        archiver.archive().unwrap();
+        assert!(dest.join(".progress").is_file());
+
+        // The run is interrupted before the archive of dir2 is written.
+        std::fs::remove_file(dest.join("dir2.zip")).unwrap();
+        archiver.push_from_iter(dirs.iter());
+        archiver.archive().unwrap();
+
+        assert!(dest.join("dir2.zip").is_file());
+        let mut skipped = archiver.skipped_files();
+        skipped.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            skipped,
+            vec![
+                (origin.join("dir1"), SkipReason::Completed),
+                (origin.join("dir3"), SkipReason::Completed)
+            ]
+        );
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn advisory_warnings_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let mut archiver = Archiver::new();
+        let receiver = archiver.message_channel();
+        archiver.push(origin.join("dir1"));
+        archiver.push(origin.join("dir3"));
+        archiver.set_destination(&dest);
+        archiver.set_format(Format::_7z);
+        archiver.set_advisory_warnings(true);
+        archiver.archive().unwrap();
+
+        let warnings = receiver
+            .try_iter()
+            .filter(|m| m.starts_with("Warning:"))
+            .collect::<Vec<_>>();
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings
+            .iter()
+            .any(|m| m.contains("100% of") && m.contains(origin.join("dir1").to_str().unwrap())));
+        assert!(warnings.iter().any(|m| m.contains("75% of")));
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn message_categories_test() {
+        let Dir { origin, dest } = setup(function_name!());
+
+        let receiver;
+        {
+            let mut archiver = Archiver::new();
+            receiver = archiver.message_channel();
+            archiver.push_from_iter(get_dir_list(&origin).unwrap().iter());
+            archiver.push(origin.join("missing"));
+            archiver.set_destination(&dest);
+            archiver.set_message_categories(MessageCategory::COMPLETION | MessageCategory::ERROR);
+            archiver.archive().unwrap();
+        }
+        let messages = receiver.iter().collect::<Vec<_>>();
+        assert_eq!(messages.len(), 4);
+        assert!(!messages
+            .iter()
+            .any(|m| m.starts_with("Total archive directory count") || m == "Archiving Complete!"));
+        assert_eq!(
+            messages
+                .iter()
+                .filter(|m| m.starts_with("zip archiving complete"))
+                .count(),
+            3
+        );
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn archive_combined_test() {
+        let Dir { origin, dest } = setup(function_name!());
+
+        let mut archiver = Archiver::new();
+        archiver.push_from_iter(get_dir_list(&origin).unwrap().iter());
+        archiver.set_destination(&dest);
+        let zip_path = archiver.archive_combined("all").unwrap();
+        assert_eq!(zip_path, dest.join("all.zip"));
+        assert!(!dest.join("dir1.zip").exists());
+
+        let mut entries = zip_entries(&zip_path);
+        entries.sort();
+        assert_eq!(entries.len(), 7);
+        assert!(entries.contains(&"dir1/file3.png".to_string()));
+        assert!(entries.contains(&"dir2/file6.webp".to_string()));
+        assert!(entries.contains(&"dir3/file7.txt".to_string()));
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn cancel_report_test() {
+        use std::sync::atomic::Ordering;
+
+        struct CancelOnComplete(Arc<AtomicBool>);
+
+        impl ArchiveObserver for CancelOnComplete {
+            fn on_complete(&self, _archive: &Path) {
+                self.0.store(true, Ordering::Relaxed);
+            }
+        }
+
+        let Dir { origin, dest } = setup(function_name!());
+        let cancel_flag = Arc::new(AtomicBool::new(false));
+        let mut archiver = Archiver::new();
+        archiver.push_from_iter(get_dir_list(&origin).unwrap().iter());
+        archiver.set_destination(&dest);
+        archiver.set_thread_count(1);
+        archiver.set_cancel_flag(Arc::clone(&cancel_flag));
+        archiver.set_observer(CancelOnComplete(Arc::clone(&cancel_flag)));
+        let report = archiver.archive_report().unwrap();
+
+        assert!(report.cancelled);
+        assert!(!report.is_success());
+        assert_eq!(report.archives.len(), 1);
+        assert!(report.failures.is_empty());
+        assert_eq!(dest.read_dir().unwrap().count(), 1);
 
         cleanup(function_name!());
     }
 
     #[test]
-    fn copy_queue_test() {
-        let queue1 = SegQueue::new();
-        queue1.push("value1");
-        queue1.push("value2");
-        queue1.push("value3");
-        queue1.push("value4");
-        let queue2 = Archiver::copy_queue(&queue1);
+    #[named]
+    fn cancellation_token_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let token = CancellationToken::new();
+        let mut archiver = Archiver::new();
+        let receiver = archiver.message_channel();
+        archiver.push_from_iter(get_dir_list(&origin).unwrap().iter());
+        archiver.set_destination(&dest);
+        archiver.set_cancellation_token(token.clone());
 
-        let mut queue_vec = Vec::new();
-        while !queue2.is_empty() {
-            queue_vec.push(queue2.pop().unwrap());
-        }
-        let mut expected_vec = vec!["value1", "value2", "value3", "value4"];
+        token.clone().cancel();
+        assert!(token.is_cancelled());
+        assert!(archiver.is_cancelled());
+        let report = archiver.archive_report().unwrap();
+        assert!(report.cancelled);
+        assert!(report.archives.is_empty());
+        assert_eq!(dest.read_dir().unwrap().count(), 0);
+        assert_eq!(receiver.try_iter().last().unwrap(), "Archiving Cancelled!");
 
-        queue_vec.sort();
-        expected_vec.sort();
+        cleanup(function_name!());
+    }
 
-        assert_eq!(expected_vec, queue_vec);
+    #[test]
+    #[named]
+    fn archive_combined_report_test() {
+        let Dir { origin, dest } = setup(function_name!());
+
+        let mut archiver = Archiver::new();
+        let receiver = archiver.message_channel();
+        archiver.push_from_iter(get_dir_list(&origin).unwrap().iter());
+        archiver.set_destination(&dest);
+        let report = archiver.archive_combined_report("all").unwrap();
+
+        assert!(report.is_success());
+        assert_eq!(report.archives.len(), 1);
+        let archived = &report.archives[0];
+        assert_eq!(archived.source, origin);
+        assert_eq!(archived.archive, dest.join("all.zip"));
+        assert_eq!(
+            archived.size,
+            dest.join("all.zip").metadata().unwrap().len()
+        );
+        let mut input_size = 0;
+        walk_files(&origin, |file| {
+            input_size += file.metadata()?.len();
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(archived.input_size, Some(input_size));
+
+        let messages = receiver.try_iter().collect::<Vec<_>>();
+        assert_eq!(
+            messages.iter().filter(|m| m.starts_with("Added ")).count(),
+            3
+        );
+        assert_eq!(
+            messages
+                .iter()
+                .filter(|m| m.starts_with("zip archiving complete:"))
+                .collect::<Vec<_>>(),
+            vec![&format!(
+                "zip archiving complete: {}",
+                dest.join("all.zip").display()
+            )]
+        );
+
+        cleanup(function_name!());
     }
 
     #[test]
     #[named]
-    fn add_queue_test() {
+    fn working_dir_test() {
         let Dir { origin, dest } = setup(function_name!());
-        let queue = SegQueue::new();
-        for dir in origin.read_dir().unwrap() {
-            queue.push(dir.unwrap().path().to_path_buf());
-        }
+
         let mut archiver = Archiver::new();
-        archiver.push_from_iter(queue.into_iter());
-        archiver.set_destination(dest.to_path_buf());
+        archiver.push("dir1");
+        archiver.push_with_format("dir2", Format::Xz);
+        archiver.set_destination(&dest);
+        archiver.set_working_dir(&origin);
         archiver.archive().unwrap();
-        assert!(dest.join("dir1.zip").is_file());
-        assert!(dest.join("dir2.zip").is_file());
-        assert!(dest.join("dir3.zip").is_file());
+
+        let mut entries = zip_entries(dest.join("dir1.zip"));
+        entries.sort();
+        assert_eq!(entries, vec!["dir1/file3.png", "dir1/file5.webp"]);
+        assert_eq!(tar_xz_entries(dest.join("dir2.tar.xz")).len(), 2);
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn compression_ratio_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        std::fs::create_dir(origin.join("empty")).unwrap();
+
+        let mut archiver = Archiver::new();
+        let receiver = archiver.message_channel();
+        archiver.push(origin.join("dir3"));
+        archiver.push(origin.join("empty"));
+        archiver.set_destination(&dest);
+        archiver.set_verbose(true);
+        archiver.set_empty_policy(EmptyPolicy::Create);
+        let report = archiver.archive_report().unwrap();
+
+        let dir3 = report.archives.iter().find(|a| a.source.ends_with("dir3"));
+        let dir3 = dir3.unwrap();
+        let input_size = extra::input_size(origin.join("dir3"), &CompressOptions::default());
+        assert_eq!(dir3.input_size, Some(input_size.unwrap()));
+        let ratio = dir3.size as f64 / dir3.input_size.unwrap() as f64;
+        assert!((dir3.ratio().unwrap() - ratio).abs() < 1e-9);
+        let expected = format!(
+            "({} from {}, {:.0}%)",
+            format_size(dir3.size),
+            format_size(dir3.input_size.unwrap()),
+            ratio * 100.0
+        );
+        assert!(receiver.try_iter().any(|m| m.ends_with(&expected)));
+
+        let empty = report.archives.iter().find(|a| a.source.ends_with("empty"));
+        assert_eq!(empty.unwrap().input_size, Some(0));
+        assert_eq!(empty.unwrap().ratio(), None);
+
         cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn format_concurrency_test() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
 
         let Dir { origin, dest } = setup(function_name!());
-        let files = get_dir_list(origin).unwrap();
+        let active = Arc::new(AtomicUsize::new(0));
+        let max_active = Arc::new(AtomicUsize::new(0));
+
         let mut archiver = Archiver::new();
-        archiver.push_from_iter(files.iter());
-        archiver.set_destination(dest.to_path_buf());
+        archiver.push_from_iter(get_dir_list(&origin).unwrap().iter());
+        archiver.set_destination(&dest);
+        archiver.set_thread_count(3);
+        archiver.set_format_concurrency(Format::Zip, 1);
+        let (arc_active, arc_max_active) = (Arc::clone(&active), Arc::clone(&max_active));
+        archiver.set_content_transform(move |_, content| {
+            // The files of a directory are read one by one, so the overlap is of the directories.
+            let now = arc_active.fetch_add(1, Ordering::SeqCst) + 1;
+            arc_max_active.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(20));
+            arc_active.fetch_sub(1, Ordering::SeqCst);
+            Ok(content)
+        });
         archiver.archive().unwrap();
-        assert!(dest.join("dir1.zip").is_file());
-        assert!(dest.join("dir2.zip").is_file());
-        assert!(dest.join("dir3.zip").is_file());
+
+        assert_eq!(max_active.load(Ordering::SeqCst), 1);
+        assert_eq!(dest.read_dir().unwrap().count(), 3);
+
         cleanup(function_name!());
+    }
 
+    #[test]
+    #[named]
+    fn cache_input_stats_test() {
         let Dir { origin, dest } = setup(function_name!());
+
         let mut archiver = Archiver::new();
-        archiver.push_from_iter(
-            vec![
-                origin.join("dir1").to_str().unwrap(),
-                origin.join("dir2").to_str().unwrap(),
-                origin.join("dir3").to_str().unwrap(),
-            ]
-            .into_iter(),
-        );
-        archiver.set_destination(dest.to_path_buf());
+        archiver.push_from_iter(get_dir_list(&origin).unwrap().iter());
+        archiver.set_destination(&dest);
+        archiver.set_format(Format::Xz);
+        archiver.set_auto_tune(true);
+        archiver.set_verbose(true);
+        archiver.set_empty_policy(EmptyPolicy::Skip);
+        archiver.set_cache_input_stats(true);
+        let report = archiver.archive_report().unwrap();
+
+        // The auto-tuning, the empty policy and the ratio share a single walk of each directory.
+        let cache = archiver.options.input_stats_cache.as_ref().unwrap();
+        assert_eq!(cache.walks(), 3);
+        assert_eq!(report.input_stats.len(), 3);
+        let dir1 = report.input_stats[&origin.join("dir1")];
+        assert_eq!(dir1.files, 2);
+        assert_eq!(dir1.incompressible_files, 2);
+        for archived in &report.archives {
+            let stats = report.input_stats[&archived.source];
+            assert_eq!(archived.input_size, Some(stats.size));
+        }
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn bundle_into_tar_test() {
+        let Dir { origin, dest } = setup(function_name!());
+
+        let mut archiver = Archiver::new();
+        archiver.push_from_iter(get_dir_list(&origin).unwrap().iter());
+        archiver.set_destination(&dest);
+        archiver.set_thread_count(2);
+        archiver.set_bundle_into_tar(Some("bundle.tar"));
+        archiver.set_delete_bundled_archives(true);
         archiver.archive().unwrap();
-        assert!(dest.join("dir1.zip").is_file());
-        assert!(dest.join("dir2.zip").is_file());
-        assert!(dest.join("dir3.zip").is_file());
+
+        assert_eq!(
+            tar_entries(dest.join("bundle.tar")),
+            vec!["dir1.zip", "dir2.zip", "dir3.zip"]
+        );
+        assert!(!dest.join("dir1.zip").exists());
+
         cleanup(function_name!());
     }
 
     #[test]
     #[named]
-    fn push_test() {
+    fn bundle_into_tar_error_test() {
         let Dir { origin, dest } = setup(function_name!());
+
         let mut archiver = Archiver::new();
+        let receiver = archiver.message_channel();
         archiver.push(origin.join("dir1"));
-        archiver.push(origin.join("dir2"));
         archiver.set_destination(&dest);
-        archiver.archive().unwrap();
+        archiver.set_bundle_into_tar(Some("missing/bundle.tar"));
+        let error = archiver.archive().unwrap_err();
+        drop(archiver);
+
+        // The error is sent, and the lifecycle message still ends the messages.
+        let messages = receiver.iter().collect::<Vec<_>>();
+        assert_eq!(
+            messages[messages.len() - 2..],
+            [error.to_string(), "Archiving Complete!".to_string()]
+        );
 
-        assert!(dest.join("dir1.zip").is_file());
-        assert!(dest.join("dir2.zip").is_file());
         cleanup(function_name!());
     }
 
     #[test]
     #[named]
-    fn format_test() {
+    fn checksum_hash_algorithm_test() {
         let Dir { origin, dest } = setup(function_name!());
+
         let mut archiver = Archiver::new();
         archiver.push_from_iter(get_dir_list(&origin).unwrap().iter());
         archiver.set_destination(&dest);
-        archiver.set_format(Format::Xz);
+        archiver.set_checksum_hash_algorithm(HashAlgorithm::Sha512);
+        // The dedup algorithm does not change the one of the checksums.
+        archiver.set_dedup_hash_algorithm(HashAlgorithm::Crc32);
+        archiver.set_checksum_file(Some("SHA512SUMS"));
         archiver.archive().unwrap();
 
-        assert!(dest.join("dir1.tar.xz").is_file());
-        assert!(dest.join("dir2.tar.xz").is_file());
-        assert!(dest.join("dir3.tar.xz").is_file());
+        let sums = std::fs::read_to_string(dest.join("SHA512SUMS")).unwrap();
+        let lines = sums.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        for (line, name) in lines.iter().zip(["dir1.zip", "dir2.zip", "dir3.zip"]) {
+            let (hash, file) = line.split_once("  ").unwrap();
+            assert_eq!(hash.len(), 128);
+            assert!(hash.chars().all(|c| c.is_ascii_hexdigit()));
+            assert_eq!(file, name);
+        }
+
+        // Both are set by set_hash_algorithm.
+        archiver.set_hash_algorithm(HashAlgorithm::Sha256);
+        archiver.push(origin.join("dir1"));
+        archiver.set_checksum_file(Some("SHA256SUMS"));
+        archiver.archive().unwrap();
+        let sums = std::fs::read_to_string(dest.join("SHA256SUMS")).unwrap();
+        assert_eq!(sums.split_once("  ").unwrap().0.len(), 64);
+        assert_eq!(archiver.options.dedup_hash, HashAlgorithm::Sha256);
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn validate_test() {
+        let Dir { origin, dest } = setup(function_name!());
+
+        let mut archiver = Archiver::new();
+        archiver.push(origin.join("dir1"));
+        assert!(matches!(
+            archiver.validate(),
+            Err(ArchiveError::DestinationNotSet)
+        ));
+        archiver.set_destination(&dest);
+        archiver.validate().unwrap();
+
+        archiver.set_thread_count(0);
+        assert!(matches!(
+            archiver.validate(),
+            Err(ArchiveError::InvalidThreadCount(0))
+        ));
+        archiver.set_thread_count(1);
+
+        std::fs::write(origin.join("dir1").join(".archiveignore"), "*.png\nfoo**\n").unwrap();
+        archiver.set_honor_ignore_files(true);
+        match archiver.validate() {
+            Err(ArchiveError::Io(e)) => {
+                assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+                assert!(e.to_string().contains("line 2"));
+            }
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        // The queue is kept for archiving.
+        assert_eq!(archiver.queue_len(), 1);
+        archiver.set_honor_ignore_files(false);
+
+        archiver.set_format(Format::_7z);
+        match preflight::unavailable_reason(&Format::_7z) {
+            Some(_) => assert!(matches!(
+                archiver.validate(),
+                Err(ArchiveError::InvalidOption(_))
+            )),
+            None => archiver.validate().unwrap(),
+        }
+
+        cleanup(function_name!());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[named]
+    fn post_command_test() {
+        let Dir { origin, dest } = setup(function_name!());
 
+        let mut archiver = Archiver::new();
         archiver.push_from_iter(get_dir_list(&origin).unwrap().iter());
-        archiver.set_format_str("7z");
+        archiver.set_destination(&dest);
+        archiver.set_post_command("touch {path}.done");
+        let report = archiver.archive_report().unwrap();
+        assert_eq!(report.archives.len(), 3);
+        for archived in &report.archives {
+            let mut marker = archived.archive.clone().into_os_string();
+            marker.push(".done");
+            assert!(PathBuf::from(marker).is_file());
+        }
+
+        archiver.push(origin.join("dir1"));
+        archiver.set_overwrite_policy(OverwritePolicy::Overwrite);
+        archiver.set_post_command("echo rejected >&2; exit 3");
+        assert!(archiver.archive_report().unwrap().is_success());
+        archiver.push(origin.join("dir1"));
+        archiver.set_fail_on_post_command(true);
+        let report = archiver.archive_report().unwrap();
+        assert_eq!(report.failures.len(), 1);
+        assert!(report.failures[0].1.to_string().contains("rejected"));
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn ordered_test() {
+        let Dir { origin, dest } = setup(function_name!());
+
+        let mut archiver = Archiver::new();
+        for name in ["dir3", "dir1", "dir2"] {
+            archiver.push(origin.join(name));
+        }
+        archiver.set_destination(&dest);
+        archiver.set_thread_count(3);
+        archiver.set_ordered(true);
+        assert_eq!(archiver.effective_thread_count(), 1);
+        let (tx, rx) = mpsc::channel();
+        archiver.set_sender(tx);
+        archiver.set_message_categories(MessageCategory::COMPLETION);
         archiver.archive().unwrap();
+        drop(archiver);
 
-        assert!(dest.join("dir1.7z").is_file());
-        assert!(dest.join("dir2.7z").is_file());
-        assert!(dest.join("dir3.7z").is_file());
+        let messages: Vec<String> = rx.iter().collect();
+        let message = Message::new(Format::Zip);
+        let expected: Vec<String> = ["dir3", "dir1", "dir2"]
+            .iter()
+            .map(|name| message.completion_message(dest.join(format!("{}.zip", name))))
+            .collect();
+        assert_eq!(messages, expected);
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn scratch_cleanup_test() {
+        let Dir { origin, dest } = setup(function_name!());
+
+        for (format, atomic_output) in [(Format::Xz, false), (Format::Zip, true)] {
+            let mut archiver = Archiver::new();
+            archiver.push(origin.join("dir3"));
+            archiver.set_destination(&dest);
+            archiver.set_format(format);
+            archiver.set_atomic_output(atomic_output);
+            // Interrupt the worker in the middle of the archive.
+            archiver.set_content_transform(|path: &Path, content: Vec<u8>| {
+                if path.ends_with("file7.txt") {
+                    panic!("Interrupted");
+                }
+                Ok(content)
+            });
+            let result =
+                std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| archiver.archive()));
+            assert!(result.is_err());
+            assert_eq!(dest.read_dir().unwrap().count(), 0);
+        }
+
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn job_progress_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let progress = JobProgress::new();
+        let mut archiver = Archiver::new();
+        archiver.push(origin.join("dir1"));
+        archiver.push(origin.join("dir3"));
+        archiver.set_destination(&dest);
+        archiver.set_job_progress(progress.clone());
+        archiver.archive().unwrap();
+
+        let mut total = 0;
+        for dir in ["dir1", "dir3"] {
+            walk_files(origin.join(dir), |file| {
+                total += file.metadata()?.len();
+                Ok(())
+            })
+            .unwrap();
+        }
+        assert_eq!(progress.total_bytes(), Some(total));
+        assert_eq!(progress.bytes_done(), total);
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn skip_empty_files_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let dir = origin.join("dir3");
+        std::fs::write(dir.join("empty.txt"), "").unwrap();
+
+        for format in Archiver::supported_formats() {
+            for skip in [false, true] {
+                let dest = dest.join(format!("{:?}-{}", format, skip));
+                let mut archiver = Archiver::new();
+                archiver.push(&dir);
+                archiver.set_destination(&dest);
+                archiver.set_format(format.clone());
+                archiver.set_skip_empty_files(skip);
+                archiver.archive().unwrap();
+
+                let mut entries = Vec::new();
+                let archive_path = dest.join(format!("dir3{}", format.extension()));
+                extract_each(&archive_path, |name, reader| {
+                    let mut content = Vec::new();
+                    reader.read_to_end(&mut content)?;
+                    entries.push((name.to_path_buf(), content.len()));
+                    Ok(())
+                })
+                .unwrap();
+                let empty = (PathBuf::from("dir3/empty.txt"), 0);
+                assert_eq!(entries.contains(&empty), !skip, "{:?} {}", format, skip);
+                assert_eq!(entries.len(), if skip { 4 } else { 5 }, "{:?}", format);
+                assert_eq!(
+                    archiver
+                        .skipped_files()
+                        .contains(&(dir.join("empty.txt"), SkipReason::EmptyFile)),
+                    skip
+                );
+            }
+        }
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    #[cfg(unix)]
+    fn unreadable_files_test() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let Dir { origin, dest } = setup(function_name!());
+        let file = origin.join("dir3").join("file7.txt");
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o000)).unwrap();
+        if std::fs::File::open(&file).is_ok() {
+            println!("Skipped: the file without the permissions is still readable, like by root");
+            cleanup(function_name!());
+            return;
+        }
+
+        for format in [Format::Zip, Format::Tar, Format::Xz] {
+            let dest = dest.join(format!("{:?}", format));
+            let mut archiver = Archiver::new();
+            archiver.push(origin.join("dir3"));
+            archiver.set_destination(&dest);
+            archiver.set_format(format.clone());
+            archiver.archive().unwrap();
+
+            let mut entries = Vec::new();
+            let archive_path = dest.join(format!("dir3{}", format.extension()));
+            extract_each(&archive_path, |name, _| {
+                entries.push(name.to_path_buf());
+                Ok(())
+            })
+            .unwrap();
+            assert_eq!(entries.len(), 3, "{:?}", format);
+            assert!(!entries.contains(&PathBuf::from("dir3/file7.txt")));
+            assert!(
+                archiver
+                    .skipped_files()
+                    .iter()
+                    .any(|(path, reason)| path == &file
+                        && matches!(reason, SkipReason::Unreadable(_)))
+            );
+        }
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn add_root_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        for (dir, file) in [
+            ("data/2024/logs", "a.txt"),
+            ("data/2025/logs", "b.txt"),
+            ("configs/app", "c.conf"),
+        ] {
+            std::fs::create_dir_all(origin.join(dir)).unwrap();
+            std::fs::write(origin.join(dir).join(file), file).unwrap();
+        }
+        let mut archiver = Archiver::new();
+        archiver.push(origin.join("dir1"));
+        archiver.set_destination(&dest);
+        archiver
+            .add_root("data", origin.join("data"), TreeDepth::Leaves, Format::Zip)
+            .unwrap();
+        archiver
+            .add_root(
+                "etc/configs",
+                origin.join("configs"),
+                TreeDepth::Depth(1),
+                Format::Xz,
+            )
+            .unwrap();
+        assert!(archiver
+            .add_root(
+                "../data",
+                origin.join("data"),
+                TreeDepth::Leaves,
+                Format::Zip
+            )
+            .is_err());
+        archiver.archive().unwrap();
+
+        assert!(dest.join("dir1.zip").is_file());
+        assert_eq!(
+            zip_entries(dest.join("data").join("2024").join("logs.zip")),
+            vec!["logs/a.txt"]
+        );
+        assert_eq!(
+            zip_entries(dest.join("data").join("2025").join("logs.zip")),
+            vec!["logs/b.txt"]
+        );
+        let mut entries = tar_xz_entries(dest.join("etc/configs/app.tar.xz"));
+        entries.retain(|e| !e.ends_with('/') && e != "app");
+        assert_eq!(entries, vec!["app/c.conf"]);
         cleanup(function_name!());
     }
 }
@@ -0,0 +1,77 @@
+use std::{io, path::Path, sync::mpsc::Sender};
+
+use crate::SkipReason;
+
+/// Receives the events of archiving, like a progress bar of a UI.
+///
+/// All methods do nothing by default, so implement only the ones you need.
+/// They are called on the worker threads, so they must be quick.
+///
+/// ```
+/// use std::path::Path;
+/// use zip_archive::{ArchiveObserver, Archiver};
+///
+/// struct Printer;
+///
+/// impl ArchiveObserver for Printer {
+///     fn on_complete(&self, archive: &Path) {
+///         println!("Done: {}", archive.display());
+///     }
+/// }
+///
+/// let mut archiver = Archiver::new();
+/// archiver.set_observer(Printer);
+/// ```
+pub trait ArchiveObserver: Send + Sync {
+    /// A directory starts being archived.
+    fn on_start(&self, _dir: &Path) {}
+
+    /// A file of `bytes` is added to an archive.
+    /// The 7z format does not report it, since the files are added by the external executable.
+    fn on_progress(&self, _file: &Path, _bytes: u64) {}
+
+    /// The 7z executable reports the percentage of `dir` done.
+    /// It is called only if [`Archiver::set_7z_progress`](crate::Archiver::set_7z_progress) is enabled.
+    fn on_percent(&self, _dir: &Path, _percent: u8) {}
+
+    /// An archive is written.
+    fn on_complete(&self, _archive: &Path) {}
+
+    /// A file or a directory is skipped from the archive.
+    fn on_skip(&self, _path: &Path, _reason: &SkipReason) {}
+
+    /// Archiving the directory at `path` failed.
+    fn on_error(&self, _path: &Path, _error: &io::Error) {}
+}
+
+/// Formats the events to strings and sends them, like the message channel.
+impl ArchiveObserver for Sender<String> {
+    fn on_start(&self, dir: &Path) {
+        self.send(format!("Archiving started: {}", dir.display()))
+            .ok();
+    }
+
+    fn on_percent(&self, dir: &Path, percent: u8) {
+        self.send(format!("Archiving {}: {}%", dir.display(), percent))
+            .ok();
+    }
+
+    fn on_complete(&self, archive: &Path) {
+        self.send(format!("Archiving complete: {}", archive.display()))
+            .ok();
+    }
+
+    fn on_skip(&self, path: &Path, reason: &SkipReason) {
+        self.send(format!("Skipped {}: {}", reason, path.display()))
+            .ok();
+    }
+
+    fn on_error(&self, path: &Path, error: &io::Error) {
+        self.send(format!(
+            "Archiving error occured!: {}: {}",
+            path.display(),
+            error
+        ))
+        .ok();
+    }
+}
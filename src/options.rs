@@ -0,0 +1,231 @@
+use std::ops::RangeInclusive;
+
+use zip::CompressionMethod;
+
+use crate::ArchiveError;
+
+/// Options of [`Format::Xz`](crate::Format::Xz), validated by [`XzOptionsBuilder::build`].
+///
+/// ```
+/// use zip_archive::{Archiver, XzOptions};
+///
+/// let xz_options = XzOptions::builder().level(9).extreme(true).threads(4).build().unwrap();
+/// let mut archiver = Archiver::new();
+/// archiver.set_xz_options(xz_options);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct XzOptions {
+    pub(crate) level: u32,
+    pub(crate) extreme: bool,
+    pub(crate) threads: u32,
+}
+
+impl XzOptions {
+    pub fn builder() -> XzOptionsBuilder {
+        XzOptionsBuilder::default()
+    }
+}
+
+/// Builder of [`XzOptions`].
+#[derive(Debug, Clone, Copy)]
+pub struct XzOptionsBuilder {
+    level: u32,
+    extreme: bool,
+    threads: u32,
+}
+
+impl Default for XzOptionsBuilder {
+    fn default() -> Self {
+        XzOptionsBuilder {
+            level: 9,
+            extreme: false,
+            threads: 1,
+        }
+    }
+}
+
+impl XzOptionsBuilder {
+    /// Set the compression level, from 0 to 9. It is 9 by default.
+    pub fn level(&mut self, level: u32) -> &mut Self {
+        self.level = level;
+        self
+    }
+
+    /// Set whether to use the extreme variant of the level,
+    /// which compresses slightly smaller but much slower. It is disabled by default.
+    pub fn extreme(&mut self, extreme: bool) -> &mut Self {
+        self.extreme = extreme;
+        self
+    }
+
+    /// Set the number of threads compressing each archive, at least 1. It is 1 by default.
+    pub fn threads(&mut self, threads: u32) -> &mut Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Get the options, or [`ArchiveError::InvalidOption`] if any of them is out of range.
+    pub fn build(&self) -> Result<XzOptions, ArchiveError> {
+        check_range("xz level", self.level as i64, 0..=9)?;
+        if self.threads == 0 {
+            return Err(ArchiveError::InvalidOption(
+                "xz threads must be at least 1".to_string(),
+            ));
+        }
+        Ok(XzOptions {
+            level: self.level,
+            extreme: self.extreme,
+            threads: self.threads,
+        })
+    }
+}
+
+/// Options of [`Format::Zip`](crate::Format::Zip), validated by [`ZipOptionsBuilder::build`].
+///
+/// ```
+/// use zip_archive::{Archiver, CompressionMethod, ZipOptions};
+///
+/// let zip_options = ZipOptions::builder()
+///     .method(CompressionMethod::Zstd)
+///     .level(19)
+///     .build()
+///     .unwrap();
+/// let mut archiver = Archiver::new();
+/// archiver.set_zip_options(zip_options);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ZipOptions {
+    pub(crate) method: CompressionMethod,
+    pub(crate) level: Option<i32>,
+}
+
+impl ZipOptions {
+    pub fn builder() -> ZipOptionsBuilder {
+        ZipOptionsBuilder::default()
+    }
+}
+
+/// Builder of [`ZipOptions`].
+#[derive(Debug, Clone, Copy)]
+pub struct ZipOptionsBuilder {
+    method: CompressionMethod,
+    level: Option<i32>,
+}
+
+impl Default for ZipOptionsBuilder {
+    fn default() -> Self {
+        ZipOptionsBuilder {
+            method: CompressionMethod::Deflated,
+            level: None,
+        }
+    }
+}
+
+impl ZipOptionsBuilder {
+    /// Set the compression method. It is [`CompressionMethod::Deflated`] by default.
+    pub fn method(&mut self, method: CompressionMethod) -> &mut Self {
+        self.method = method;
+        self
+    }
+
+    /// Set the compression level of the method.
+    /// If it is not set, the default level of the method is used.
+    pub fn level(&mut self, level: i32) -> &mut Self {
+        self.level = Some(level);
+        self
+    }
+
+    /// Get the options, or [`ArchiveError::InvalidOption`] if the method cannot be written,
+    /// or the level is out of its range: 0 to 9 for deflate, 1 to 9 for bzip2, and -7 to 22 for zstd.
+    /// The stored method takes no level.
+    #[allow(deprecated)]
+    pub fn build(&self) -> Result<ZipOptions, ArchiveError> {
+        let range = match self.method {
+            CompressionMethod::Stored => None,
+            CompressionMethod::Deflated => Some(0..=9),
+            CompressionMethod::Bzip2 => Some(1..=9),
+            CompressionMethod::Zstd => Some(-7..=22),
+            method => {
+                return Err(ArchiveError::InvalidOption(format!(
+                    "{} cannot be written",
+                    method
+                )))
+            }
+        };
+        match (range, self.level) {
+            (Some(range), Some(level)) => check_range("zip level", level as i64, range)?,
+            (None, Some(_)) => {
+                return Err(ArchiveError::InvalidOption(
+                    "the stored zip method takes no level".to_string(),
+                ))
+            }
+            _ => (),
+        }
+        Ok(ZipOptions {
+            method: self.method,
+            level: self.level,
+        })
+    }
+}
+
+/// Check that the option is in the range.
+fn check_range(name: &str, value: i64, range: RangeInclusive<i64>) -> Result<(), ArchiveError> {
+    if range.contains(&value) {
+        return Ok(());
+    }
+    Err(ArchiveError::InvalidOption(format!(
+        "{} {} is out of {} to {}",
+        name,
+        value,
+        range.start(),
+        range.end()
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use function_name::named;
+
+    use crate::core::test_util::{cleanup, setup, tar_xz_entries, Dir};
+    use crate::{Archiver, Format};
+
+    #[test]
+    #[named]
+    fn options_builder_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        assert!(matches!(
+            XzOptions::builder().level(10).build(),
+            Err(ArchiveError::InvalidOption(_))
+        ));
+        assert!(XzOptions::builder().threads(0).build().is_err());
+        assert!(ZipOptions::builder()
+            .method(CompressionMethod::Zstd)
+            .level(30)
+            .build()
+            .is_err());
+        assert!(ZipOptions::builder()
+            .method(CompressionMethod::Stored)
+            .level(1)
+            .build()
+            .is_err());
+        assert!(ZipOptions::builder().level(9).build().is_ok());
+
+        let mut archiver = Archiver::new();
+        archiver.push(origin.join("dir1"));
+        archiver.set_destination(&dest);
+        archiver.set_format(Format::Xz);
+        archiver.set_xz_options(
+            XzOptions::builder()
+                .level(6)
+                .extreme(true)
+                .threads(2)
+                .build()
+                .unwrap(),
+        );
+        archiver.archive().unwrap();
+        assert_eq!(tar_xz_entries(dest.join("dir1.tar.xz")).len(), 3);
+
+        cleanup(function_name!());
+    }
+}
@@ -0,0 +1,60 @@
+use std::io;
+use std::path::Path;
+
+use subprocess::{Exec, NullFile, Redirection};
+
+/// Shell command run after each archive is written.
+/// See [`Archiver::set_post_command`](crate::Archiver::set_post_command) for the placeholder.
+#[derive(Clone, Debug)]
+pub struct PostCommand {
+    template: String,
+    /// Fail the directory of the archive if the command fails, instead of only warning.
+    pub fails_dir: bool,
+}
+
+impl PostCommand {
+    pub fn new(template: &str, fails_dir: bool) -> Self {
+        PostCommand {
+            template: template.to_string(),
+            fails_dir,
+        }
+    }
+
+    /// Expand the placeholder with the quoted path of the archive.
+    pub fn expand(&self, archive: &Path) -> String {
+        self.template
+            .replace("{path}", &quote(&archive.to_string_lossy()))
+    }
+
+    /// Run the command for the archive through the shell, and fail if it exits with an error.
+    /// The error has the status and the standard error of the command.
+    pub fn run(&self, archive: &Path) -> io::Result<()> {
+        let command = self.expand(archive);
+        let capture = Exec::shell(&command)
+            .stdout(NullFile)
+            .stderr(Redirection::Pipe)
+            .capture()
+            .map_err(io::Error::other)?;
+        if capture.success() {
+            return Ok(());
+        }
+        Err(io::Error::other(format!(
+            "The post command `{}` failed with {:?}: {}",
+            command,
+            capture.exit_status,
+            capture.stderr_str().trim()
+        )))
+    }
+}
+
+/// Quote the argument for the shell, so it is passed as it is.
+#[cfg(unix)]
+fn quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// Quote the argument for the shell, so it is passed as it is.
+#[cfg(windows)]
+fn quote(arg: &str) -> String {
+    format!("\"{}\"", arg.replace('"', "\"\""))
+}
@@ -0,0 +1,110 @@
+use std::path::{Path, PathBuf};
+
+use subprocess::{Exec, NullFile};
+
+use crate::{extra::get_7z_executable_path, Format};
+
+/// Readiness of the environment to archive, made by [`Archiver::preflight`](crate::Archiver::preflight).
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreflightReport {
+    /// Path of the 7z executable, if it can be executed.
+    pub seven_zip_path: Option<PathBuf>,
+    /// Version of the 7z executable, if it can be read from its banner.
+    pub seven_zip_version: Option<String>,
+    /// Whether the xz format is available. It is always compiled in.
+    pub xz_available: bool,
+    /// Whether the zip format is available. It is always compiled in.
+    pub zip_available: bool,
+    /// Whether the destination is set and writable.
+    /// If the destination does not exist yet, its nearest existing ancestor is checked.
+    pub destination_writable: bool,
+    /// Number of the directories in the queue.
+    pub queue_len: usize,
+}
+
+impl PreflightReport {
+    pub(crate) fn new(dest: Option<&Path>, queue_len: usize) -> Self {
+        let seven_zip = get_7z_executable_path()
+            .ok()
+            .and_then(|path| probe_7z(&path).map(|version| (path, version)));
+        PreflightReport {
+            seven_zip_version: seven_zip.as_ref().and_then(|(_, v)| v.clone()),
+            seven_zip_path: seven_zip.map(|(p, _)| p),
+            xz_available: true,
+            zip_available: true,
+            destination_writable: dest.is_some_and(is_writable),
+            queue_len,
+        }
+    }
+}
+
+/// Execute the 7z executable without arguments.
+/// Returns `None` if it cannot be executed, or the version from its banner if it is found.
+fn probe_7z(path: &Path) -> Option<Option<String>> {
+    let capture = Exec::cmd(path).stderr(NullFile).capture().ok()?;
+    let banner = capture.stdout_str();
+    let version = banner
+        .lines()
+        .find(|line| line.starts_with("7-Zip"))
+        .and_then(|line| {
+            line.split_whitespace()
+                .find(|word| word.starts_with(|c: char| c.is_ascii_digit()))
+        })
+        .map(|version| version.to_string());
+    Some(version)
+}
+
+/// Get why the format cannot be used in the current environment, or `None` if it can be used.
+pub(crate) fn unavailable_reason(format: &Format) -> Option<String> {
+    match format {
+        Format::_7z | Format::Tar7z => match get_7z_executable_path() {
+            Ok(path) if probe_7z(&path).is_some() => None,
+            Ok(path) => Some(format!(
+                "the 7z executable {} cannot be executed",
+                path.display()
+            )),
+            Err(e) => Some(e.to_string()),
+        },
+        Format::Xz | Format::Zip | Format::Tar => None,
+    }
+}
+
+/// Check whether the directory, or its nearest existing ancestor, is writable.
+pub(crate) fn is_writable(dir: &Path) -> bool {
+    let existing = dir
+        .ancestors()
+        .find(|p| p.as_os_str().is_empty() || p.exists());
+    match existing {
+        Some(p) if p.as_os_str().is_empty() => is_writable(Path::new(".")),
+        Some(p) => p
+            .metadata()
+            .is_ok_and(|m| m.is_dir() && !m.permissions().readonly()),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn probe_missing_7z_test() {
+        assert_eq!(probe_7z(Path::new("./missing_7z_executable")), None);
+    }
+
+    #[test]
+    fn is_writable_test() {
+        fs::create_dir_all("writable_test").unwrap();
+
+        assert!(is_writable(Path::new("writable_test")));
+        assert!(is_writable(Path::new("writable_test/not/created/yet")));
+        assert!(is_writable(Path::new("relative_not_created_yet")));
+
+        fs::write("writable_test/file", "").unwrap();
+        assert!(!is_writable(Path::new("writable_test/file")));
+
+        fs::remove_dir_all("writable_test").unwrap();
+    }
+}
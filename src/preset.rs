@@ -0,0 +1,51 @@
+use zip::CompressionMethod;
+
+use crate::core::CompressOptions;
+
+/// Extensions of the already compressed files, stored without compression by the presets which allow it.
+pub(crate) const INCOMPRESSIBLE_EXTENSIONS: [&str; 14] = [
+    "jpg", "jpeg", "png", "gif", "webp", "mp3", "mp4", "mkv", "zip", "gz", "xz", "7z", "zst", "bz2",
+];
+
+/// Named sets of the compression settings, from the fastest to the smallest.
+/// See [`Archiver::set_preset`](crate::Archiver::set_preset) for what each one sets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// Compress as fast as possible, with the larger archives.
+    Fastest,
+    /// Compress with the default levels of each format.
+    Balanced,
+    /// Compress as small as possible, however long it takes.
+    Smallest,
+    /// Like [`Preset::Balanced`], but written safely for the long-term storage.
+    Backup,
+}
+
+impl Preset {
+    /// Set the compression settings of the preset to `options`.
+    pub(crate) fn apply(&self, options: &mut CompressOptions) {
+        let (zip_level, xz_level, level_7z) = match self {
+            Preset::Fastest => (1, 0, 1),
+            Preset::Balanced | Preset::Backup => (6, 6, 5),
+            Preset::Smallest => (9, 9, 9),
+        };
+        options.zip_file_options = Some(
+            options
+                .zip_file_options
+                .unwrap_or_default()
+                .compression_method(CompressionMethod::Deflated)
+                .compression_level(Some(zip_level)),
+        );
+        options.xz_level = xz_level;
+        options.level_7z = level_7z;
+        options.stored_extensions = match self {
+            Preset::Smallest => Vec::new(),
+            _ => INCOMPRESSIBLE_EXTENSIONS.map(String::from).to_vec(),
+        };
+        if *self == Preset::Backup {
+            options.fsync = true;
+            options.atomic_output = true;
+            options.dedup = true;
+        }
+    }
+}
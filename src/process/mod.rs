@@ -1,18 +1,40 @@
 use std::{
     error::Error,
-    path::Path,
-    sync::{mpsc::Sender, Arc},
+    fmt, fs, io,
+    ops::{BitOr, BitOrAssign},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender, SyncSender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
 use crossbeam_queue::SegQueue;
 
+use crate::{
+    core::{compress_file, verify_archive, CompressOptions, EmptyPolicy, SkipReason},
+    extra::{format_size, has_files},
+    post_command::PostCommand,
+    resume::ResumeLog,
+    template::NameTemplate,
+    ArchiveError, ArchiveObserver, CancellationToken, JobProgress,
+};
+
 mod p_7z;
+mod p_tar;
+mod p_tar_7z;
 mod p_xz;
 mod p_zip;
 
 /// The enum of formats that currently supported.  
 /// Using this enum, you can set the format of archiving method. 
-#[derive(PartialEq)]
+/// More formats may be added, so a `match` on it needs a wildcard arm,
+/// and [`Format::all`] lists the current ones.
+#[derive(PartialEq, Debug)]
+#[non_exhaustive]
 pub enum Format {
 
     /// .7z format. 
@@ -23,22 +45,60 @@ pub enum Format {
 
     /// .xz format.  
     /// Good compression level.  
+    /// The whole tarball is compressed as a single stream,
+    /// so files cannot be stored without compression one by one.
+    /// To store already compressed files, like media, use [`Format::Zip`]
+    /// with [`Archiver::set_stored_extensions`](crate::Archiver::set_stored_extensions).  
     Xz,
 
 
     /// Deflate archive which has .zip extension.  
     /// shortest time.  
     Zip,
+
+    /// Uncompressed tarball which has .tar extension.  
+    /// The files are stored as they are, so it shows the cost of the traversal and the IO
+    /// without the compression, for benchmarking.  
+    Tar,
+
+    /// Tarball compressed by 7z, which has .tar.7z extension.  
+    /// The tarball is streamed to the stdin of `7z a -si`, without the intermediate tarball on the disk,
+    /// so it needs a 7z build which supports `-si`, besides the requirments of [`Format::_7z`].  
+    Tar7z,
 }
 
 impl Format {
 
+    /// Get all formats, like for the choices of a UI.
+    /// Some of them may not be available in the current environment,
+    /// see [`Archiver::supported_formats`](crate::Archiver::supported_formats).
+    ///
+    /// # Examples
+    /// ```
+    /// use zip_archive::Format;
+    ///
+    /// for format in Format::all() {
+    ///     println!("{}", format.to_string());
+    /// }
+    /// ```
+    pub fn all() -> &'static [Format] {
+        &[
+            Format::_7z,
+            Format::Xz,
+            Format::Zip,
+            Format::Tar,
+            Format::Tar7z,
+        ]
+    }
+
     /// Get extension string of [`Format`].
     pub fn extension(&self) -> String {
         match self {
             Format::_7z => String::from(".7z"),
             Format::Xz => String::from(".tar.xz"),
             Format::Zip => String::from(".zip"),
+            Format::Tar => String::from(".tar"),
+            Format::Tar7z => String::from(".tar.7z"),
         }
     }
 
@@ -48,6 +108,8 @@ impl Format {
             "7z" => Format::_7z,
             "xz" => Format::Xz,
             "zip" => Format::Zip,
+            "tar" => Format::Tar,
+            "tar7z" => Format::Tar7z,
             _ => panic!("wrong format string!"),
         }
     }
@@ -59,6 +121,8 @@ impl Clone for Format {
             Format::_7z => Format::_7z,
             Format::Xz => Format::Xz,
             Format::Zip => Format::Zip,
+            Format::Tar => Format::Tar,
+            Format::Tar7z => Format::Tar7z,
         }
     }
 }
@@ -69,6 +133,8 @@ impl ToString for Format {
             Format::_7z => String::from("7z"),
             Format::Xz => String::from("xz"),
             Format::Zip => String::from("zip"),
+            Format::Tar => String::from("tar"),
+            Format::Tar7z => String::from("tar7z"),
         }
     }
 }
@@ -79,8 +145,817 @@ impl Default for Format {
     }
 }
 
-pub trait Process<T: AsRef<Path>, O: AsRef<Path>> {
-    fn process(&self, queue: Arc<SegQueue<T>>, dest: Arc<O>, sender: Option<Sender<String>>);
+/// Lazy source of the directories to archive.
+pub type Source = Box<dyn Iterator<Item = PathBuf> + Send>;
+
+/// A directory to archive, with its own format if it is not the default one,
+/// and its own subdirectory of the destination if it is from a root.
+pub type Item = (PathBuf, Option<Format>, Option<PathBuf>);
+
+/// Directories to archive, shared by all worker threads.
+/// The directories in the queue come first, then the ones pulled from the source on demand.
+pub struct WorkQueue {
+    queue: SegQueue<Item>,
+    source: Mutex<Option<Source>>,
+    cancellation_token: Option<CancellationToken>,
+}
+
+impl WorkQueue {
+    pub fn new(queue: SegQueue<Item>, source: Option<Source>) -> Self {
+        WorkQueue {
+            queue,
+            source: Mutex::new(source),
+            cancellation_token: None,
+        }
+    }
+
+    /// Stop giving the directories once the token is cancelled.
+    pub fn with_cancellation_token(mut self, token: Option<CancellationToken>) -> Self {
+        self.cancellation_token = token;
+        self
+    }
+
+    /// Get the next directory to archive, or `None` if it is cancelled.
+    pub fn pop(&self) -> Option<Item> {
+        if self
+            .cancellation_token
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+        {
+            return None;
+        }
+        match self.queue.pop() {
+            Some(item) => Some(item),
+            None => Some((self.source.lock().unwrap().as_mut()?.next()?, None, None)),
+        }
+    }
+}
+
+impl From<SegQueue<PathBuf>> for WorkQueue {
+    fn from(queue: SegQueue<PathBuf>) -> Self {
+        let items = SegQueue::new();
+        while let Some(dir) = queue.pop() {
+            items.push((dir, None, None));
+        }
+        WorkQueue::new(items, None)
+    }
+}
+
+/// Callback invoked on the worker threads with the result of each directory.
+pub type ResultCallback = Arc<dyn Fn(Outcome) + Send + Sync>;
+
+/// The result of a directory passed to the result callback.
+pub struct Outcome {
+    /// The archived directory, if known.
+    pub dir: Option<PathBuf>,
+    /// Time since the directory started being archived.
+    pub elapsed: Option<Duration>,
+    /// Total size of the files of the directory, if it is measured.
+    pub input_size: Option<u64>,
+    pub result: Result<PathBuf, ArchiveError>,
+}
+
+/// Reports the progress of a worker thread through the sender and the result callback.
+#[derive(Clone, Default)]
+pub struct Reporter {
+    sender: Option<Sender<String>>,
+    callback: Option<ResultCallback>,
+    heartbeat_interval: Option<Duration>,
+    job_progress: Option<JobProgress>,
+    verbose: bool,
+    observer: Option<Arc<dyn ArchiveObserver>>,
+    dir: Option<PathBuf>,
+    started: Option<Instant>,
+    input_size: Option<u64>,
+    delete_source: bool,
+    post_command: Option<PostCommand>,
+    formatter: Option<MessageFormatter>,
+    categories: MessageCategory,
+    name_template: Option<NameTemplate>,
+    index: usize,
+    resume: Option<ResumeLog>,
+    disconnected: Arc<AtomicBool>,
+}
+
+impl Reporter {
+    pub fn new(sender: Option<Sender<String>>, callback: Option<ResultCallback>) -> Self {
+        Reporter {
+            sender,
+            callback,
+            heartbeat_interval: None,
+            job_progress: None,
+            verbose: false,
+            observer: None,
+            dir: None,
+            started: None,
+            input_size: None,
+            delete_source: false,
+            post_command: None,
+            formatter: None,
+            categories: MessageCategory::ALL,
+            name_template: None,
+            index: 0,
+            resume: None,
+            disconnected: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Turn the completion and the error of each directory into the messages with the formatter,
+    /// instead of the default ones.
+    pub fn with_formatter(mut self, formatter: Option<MessageFormatter>) -> Self {
+        self.formatter = formatter;
+        self
+    }
+
+    /// Send only the messages in the categories.
+    pub fn with_categories(mut self, categories: MessageCategory) -> Self {
+        self.categories = categories;
+        self
+    }
+
+    /// Send the message of the event, made by the formatter if any.
+    fn send_event(&self, event: &ArchiveEvent) {
+        let category = match event {
+            ArchiveEvent::Complete { .. } => MessageCategory::COMPLETION,
+            ArchiveEvent::Error { .. } => MessageCategory::ERROR,
+        };
+        if !self.categories.contains(category) {
+            return;
+        }
+        match &self.formatter {
+            Some(formatter) => self.send(category, formatter(event)),
+            None => self.send(category, event.to_string()),
+        }
+    }
+
+    /// Record each directory to the resume log once its archives are written.
+    pub fn with_resume(mut self, resume: Option<ResumeLog>) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    /// Rename the archives of each directory with the template.
+    pub fn with_name_template(mut self, name_template: Option<NameTemplate>) -> Self {
+        self.name_template = name_template;
+        self
+    }
+
+    /// Rename the archives with the template, if any.
+    /// The extension is the part of the archive name after the name of the directory, like `tar.xz`.
+    fn rename(&self, archives: Vec<PathBuf>) -> io::Result<Vec<PathBuf>> {
+        let (template, dir) = match (&self.name_template, &self.dir) {
+            (Some(template), Some(dir)) => (template, dir),
+            _ => return Ok(archives),
+        };
+        let name = dir.file_name().unwrap_or_default().to_string_lossy();
+        let mut renamed = Vec::new();
+        for archive in archives {
+            let file_name = archive.file_name().unwrap_or_default().to_string_lossy();
+            let ext = match file_name
+                .strip_prefix(&*name)
+                .and_then(|e| e.strip_prefix('.'))
+            {
+                Some(ext) => ext.to_string(),
+                None => archive
+                    .extension()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+            };
+            let new_path = archive.with_file_name(template.expand(&name, &ext, self.index));
+            fs::rename(&archive, &new_path)?;
+            renamed.push(new_path);
+        }
+        Ok(renamed)
+    }
+
+    /// Delete each directory after its archives are written and verified.
+    pub fn with_delete_source(mut self, delete_source: bool) -> Self {
+        self.delete_source = delete_source;
+        self
+    }
+
+    /// Run the command after each archive is written.
+    pub fn with_post_command(mut self, post_command: Option<PostCommand>) -> Self {
+        self.post_command = post_command;
+        self
+    }
+
+    /// Run the post command for each archive, if any.
+    /// Its failure fails the directory if it is set so, or is sent as a warning.
+    fn run_post_command(&self, archives: Vec<PathBuf>) -> io::Result<Vec<PathBuf>> {
+        let post_command = match &self.post_command {
+            Some(post_command) => post_command,
+            None => return Ok(archives),
+        };
+        for archive in &archives {
+            match post_command.run(archive) {
+                Err(e) if post_command.fails_dir => return Err(e),
+                Err(e) => self.send(MessageCategory::WARNING, e.to_string()),
+                Ok(_) => (),
+            }
+        }
+        Ok(archives)
+    }
+
+    /// Pass the start, the completion and the errors of each directory to the observer.
+    pub fn with_observer(mut self, observer: Option<Arc<dyn ArchiveObserver>>) -> Self {
+        self.observer = observer;
+        self
+    }
+
+    /// Get the reporter of `dir`, which its errors are reported to the observer with.
+    pub fn for_dir(&self, dir: &Path) -> Reporter {
+        Reporter {
+            dir: Some(dir.to_path_buf()),
+            ..self.clone()
+        }
+    }
+
+    /// Tell the observer that `dir` starts being archived, and get the reporter of it.
+    pub fn start(&self, dir: &Path) -> Reporter {
+        if let Some(observer) = &self.observer {
+            observer.on_start(dir);
+        }
+        Reporter {
+            started: Some(Instant::now()),
+            index: self.name_template.as_ref().map_or(0, |t| t.next_index()),
+            ..self.for_dir(dir)
+        }
+    }
+
+    /// Set the total size of the files of the directory, which the compression ratio is reported with.
+    pub fn with_input_size(mut self, input_size: Option<u64>) -> Self {
+        self.input_size = input_size;
+        self
+    }
+
+    /// Whether the input size is reported, to the result callback or in the verbose messages.
+    pub fn takes_input_size(&self) -> bool {
+        self.verbose || self.callback.is_some()
+    }
+
+    /// Send the detailed messages of [`verbose`](Reporter::verbose).
+    pub fn with_verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Send the detailed message made by `message` only in the verbose mode.
+    pub fn verbose<F: FnOnce() -> Option<String>>(&self, category: MessageCategory, message: F) {
+        if self.verbose && self.categories.contains(category) {
+            if let Some(message) = message() {
+                self.send(category, message);
+            }
+        }
+    }
+
+    /// Send the heartbeat messages at the interval while a directory is being archived.
+    pub fn with_heartbeat_interval(mut self, heartbeat_interval: Option<Duration>) -> Self {
+        self.heartbeat_interval = heartbeat_interval;
+        self
+    }
+
+    /// Add the estimated time remaining of the job to the heartbeat messages, once it is known.
+    pub fn with_job_progress(mut self, job_progress: Option<JobProgress>) -> Self {
+        self.job_progress = job_progress;
+        self
+    }
+
+    /// Start sending the heartbeat messages for `path` until the returned [`Heartbeat`] is dropped.
+    pub fn heartbeat(&self, path: &Path) -> Heartbeat {
+        let (interval, sender) = match (self.heartbeat_interval, &self.sender) {
+            (Some(interval), Some(sender))
+                if self.categories.contains(MessageCategory::PROGRESS) =>
+            {
+                (interval, sender.clone())
+            }
+            _ => {
+                return Heartbeat {
+                    stop: None,
+                    handle: None,
+                }
+            }
+        };
+        let name = path
+            .file_name()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy()
+            .into_owned();
+        let job_progress = self.job_progress.clone();
+        let (stop, stopped) = channel::<()>();
+        let handle = thread::spawn(move || {
+            let start = Instant::now();
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let eta = match job_progress.as_ref().and_then(JobProgress::eta) {
+                    Some(eta) => format!(", about {}s left", eta.as_secs()),
+                    None => String::new(),
+                };
+                let message = format!(
+                    "Still working on {} ({}s elapsed{})",
+                    name,
+                    start.elapsed().as_secs(),
+                    eta
+                );
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+        Heartbeat {
+            stop: Some(stop),
+            handle: Some(handle),
+        }
+    }
+
+    /// Send the message through the sender, if any and the category is enabled.
+    /// Once the receiver is dropped, no more messages are sent by this reporter and its clones.
+    pub fn send(&self, category: MessageCategory, message: String) {
+        let sender = match &self.sender {
+            Some(sender)
+                if self.categories.contains(category)
+                    && !self.disconnected.load(Ordering::Relaxed) =>
+            {
+                sender
+            }
+            _ => return,
+        };
+        if sender.send(message).is_err() {
+            self.disconnected.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Send the completion or error message of a directory, and pass its result to the callback.
+    /// In the verbose mode, the completion message has the size of the archive.
+    pub fn report(&self, message: &Message, result: io::Result<PathBuf>) {
+        self.report_parts(message, result.map(|p| vec![p]));
+    }
+
+    /// Report the archives of a directory like [`report`](Reporter::report),
+    /// deleting the directory only once all of them are verified.
+    pub fn report_parts(&self, message: &Message, result: io::Result<Vec<PathBuf>>) {
+        let archives = match result
+            .and_then(|archives| self.rename(archives))
+            .and_then(|archives| self.run_post_command(archives))
+        {
+            Ok(archives) => archives,
+            Err(e) => {
+                self.send_event(&ArchiveEvent::Error {
+                    format: &message.format,
+                    error: &e,
+                });
+                return self.notify(Err(e));
+            }
+        };
+        // The ratio of each part of a split archive to the whole directory means nothing.
+        let reporter = match archives.len() {
+            1 => self.clone(),
+            _ => self.clone().with_input_size(None),
+        };
+        let input_size = reporter.input_size.filter(|_| self.verbose);
+        for archive in &archives {
+            let size = match archive.metadata() {
+                Ok(metadata) if self.verbose => Some(metadata.len()),
+                _ => None,
+            };
+            self.send_event(&ArchiveEvent::Complete {
+                format: &message.format,
+                archive,
+                size,
+                input_size,
+            });
+        }
+        if let (Some(resume), Some(dir)) = (&self.resume, &self.dir) {
+            if let Err(e) = resume.record(dir, &archives) {
+                self.send(
+                    MessageCategory::WARNING,
+                    format!("Cannot record the progress!: {}", e),
+                );
+            }
+        }
+        self.delete_source(message, &archives);
+        for archive in archives {
+            reporter.notify(Ok(archive));
+        }
+    }
+
+    /// Delete the directory if it is enabled and all its archives are verified.
+    /// The directory is kept if any of them fails the verification.
+    fn delete_source(&self, message: &Message, archives: &[PathBuf]) {
+        let dir = match &self.dir {
+            Some(dir) if self.delete_source => dir,
+            _ => return,
+        };
+        for archive in archives {
+            if let Err(e) = verify_archive(archive, &message.format) {
+                return self.send(
+                    MessageCategory::WARNING,
+                    format!(
+                        "Cannot verify {}, so the source is kept!: {}",
+                        archive.display(),
+                        e
+                    ),
+                );
+            }
+        }
+        let removed = if dir.is_file() {
+            fs::remove_file(dir)
+        } else {
+            fs::remove_dir_all(dir)
+        };
+        match removed {
+            Ok(_) => self.send(
+                MessageCategory::COMPLETION,
+                format!("Deleted the source: {}", dir.display()),
+            ),
+            Err(e) => self.send(
+                MessageCategory::WARNING,
+                format!("Cannot delete the source {}!: {}", dir.display(), e),
+            ),
+        }
+    }
+
+    /// Pass the result of a directory to the observer and the callback, if any.
+    pub fn notify(&self, result: io::Result<PathBuf>) {
+        if let Some(observer) = &self.observer {
+            match &result {
+                Ok(p) => observer.on_complete(p),
+                Err(e) => observer.on_error(self.dir.as_deref().unwrap_or(Path::new("")), e),
+            }
+        }
+        if let Some(callback) = &self.callback {
+            callback(Outcome {
+                dir: self.dir.clone(),
+                elapsed: self.started.map(|s| s.elapsed()),
+                input_size: self.input_size,
+                result: result.map_err(ArchiveError::from),
+            });
+        }
+    }
+}
+
+/// Sends the heartbeat messages until it is dropped.
+pub struct Heartbeat {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        // Disconnecting the channel wakes up the heartbeat thread immediately.
+        drop(self.stop.take());
+        if let Some(handle) = self.handle.take() {
+            handle.join().ok();
+        }
+    }
+}
+
+/// Share of the already compressed files over which 7z at the level 9 is warned against.
+const INCOMPRESSIBLE_SHARE_THRESHOLD: f64 = 0.5;
+
+/// A compressor shared by all worker threads.
+pub trait Process: Send + Sync {
+    /// Archive the directory into `dest`.
+    fn process_dir(&self, dir: &Path, dest: &Path, reporter: &Reporter);
+}
+
+/// A compressor split into the IO-bound staging and the CPU-bound compression,
+/// each run by its own worker threads.
+pub trait Pipeline: Send + Sync {
+    /// Stage the directory, like writing the intermediate tarball, and get the staged file.
+    fn stage(&self, dir: &Path, dest: &Path, reporter: &Reporter) -> Option<PathBuf>;
+
+    /// Compress the staged file into `dest`.
+    fn compress_staged(&self, staged: &Path, dest: &Path, reporter: &Reporter);
+}
+
+/// Compressors of all formats, which archive each directory in its own format or the default one.
+pub struct Compressors {
+    format: Format,
+    zip: Arc<dyn Process>,
+    xz: Arc<dyn Process>,
+    _7z: Arc<dyn Process>,
+    tar: Arc<dyn Process>,
+    tar_7z: Arc<dyn Process>,
+    pipeline: Option<Arc<dyn Pipeline>>,
+    options: CompressOptions,
+}
+
+impl Compressors {
+    /// Create the compressors with the default format.
+    /// If `pipelined`, the default format is staged and compressed by separate threads, if it has a staging step.
+    pub fn new(format: Format, options: CompressOptions, pipelined: bool) -> Self {
+        let pipeline = if pipelined {
+            get_pipeline(format.clone(), options.clone())
+        } else {
+            None
+        };
+        Compressors {
+            format,
+            zip: get_compressor(Format::Zip, options.clone()),
+            xz: get_compressor(Format::Xz, options.clone()),
+            _7z: get_compressor(Format::_7z, options.clone()),
+            tar: get_compressor(Format::Tar, options.clone()),
+            tar_7z: get_compressor(Format::Tar7z, options.clone()),
+            pipeline,
+            options,
+        }
+    }
+
+    /// Whether the default format is staged and compressed by separate threads.
+    pub fn is_pipelined(&self) -> bool {
+        self.pipeline.is_some()
+    }
+
+    /// Get the compressor of the format, or of the default format if `None`.
+    pub fn get(&self, format: Option<&Format>) -> &dyn Process {
+        match format.unwrap_or(&self.format) {
+            Format::Zip => self.zip.as_ref(),
+            Format::Xz => self.xz.as_ref(),
+            Format::_7z => self._7z.as_ref(),
+            Format::Tar => self.tar.as_ref(),
+            Format::Tar7z => self.tar_7z.as_ref(),
+        }
+    }
+
+    /// Archive the directory in its format, or in the per-file mode, the file into its own archive.
+    fn process_item(&self, dir: &Path, format: Option<&Format>, dest: &Path, reporter: &Reporter) {
+        if self.skip_dir(dir, reporter) {
+            return;
+        }
+        if matches!(format.unwrap_or(&self.format), Format::_7z | Format::Tar7z) {
+            self.advise_7z(dir, reporter);
+        }
+        let _permit = self.options.format_permit(format.unwrap_or(&self.format));
+        if dir.is_file() {
+            let format = format.unwrap_or(&self.format);
+            let result = match self.options.per_file {
+                true => compress_file(dir, dest, format, &self.options),
+                false => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Expected a directory, got a file: {}", dir.display()),
+                )),
+            };
+            return reporter.report(&Message::new(format.clone()), result);
+        }
+        self.get(format).process_dir(dir, dest, reporter);
+    }
+
+    /// Warn that 7z at the level 9 wastes time on the directory, if it is mostly the already compressed files.
+    /// It is sent if the advisory warnings are enabled or in the verbose mode, and changes nothing.
+    fn advise_7z(&self, dir: &Path, reporter: &Reporter) {
+        if self.options.level_7z < 9 {
+            return;
+        }
+        let advice = || {
+            let share = self.options.input_stats(dir).ok()?.incompressible_share();
+            (share > INCOMPRESSIBLE_SHARE_THRESHOLD).then(|| {
+                format!(
+                    "Warning: {:.0}% of {} is already compressed, \
+                     so 7z at the level 9 barely shrinks it. \
+                     Consider a lower level or Format::Zip with the stored extensions.",
+                    share * 100.0,
+                    dir.display()
+                )
+            })
+        };
+        if !self.options.advisory_warnings {
+            return reporter.verbose(MessageCategory::WARNING, advice);
+        }
+        if let Some(advice) = advice() {
+            reporter.send(MessageCategory::WARNING, advice);
+        }
+    }
+
+    /// Skip the directory if it is completed by a previous run in the resume mode,
+    /// or no files are left after the filters and the policy is to skip it.
+    fn skip_dir(&self, dir: &Path, reporter: &Reporter) -> bool {
+        let reason = match self.skip_reason(dir) {
+            Some(reason) => reason,
+            None => return false,
+        };
+        reporter.send(
+            MessageCategory::COMPLETION,
+            format!("Skipped ({}): {}", reason, dir.display()),
+        );
+        self.options.skip(dir, reason);
+        true
+    }
+
+    /// Check whether any file of the directory is left after the filters,
+    /// from the cached stats if they are enabled, or by a walk stopping at the first file otherwise.
+    fn has_files(&self, dir: &Path) -> io::Result<bool> {
+        match &self.options.input_stats_cache {
+            Some(_) => self.options.input_stats(dir).map(|s| s.files > 0),
+            None => has_files(dir, &self.options),
+        }
+    }
+
+    /// Get the reason to skip the directory, if any.
+    /// The directory is archived if it cannot be read, so the error is reported by the compressor.
+    fn skip_reason(&self, dir: &Path) -> Option<SkipReason> {
+        if self
+            .options
+            .resume
+            .as_ref()
+            .is_some_and(|r| r.is_completed(dir))
+        {
+            return Some(SkipReason::Completed);
+        }
+        if self.options.empty_policy == EmptyPolicy::Skip
+            && dir.is_dir()
+            && !self.has_files(dir).unwrap_or(true)
+        {
+            return Some(SkipReason::NoFiles);
+        }
+        None
+    }
+
+    /// Tell the reporter that `dir` starts being archived, measuring the size of its files
+    /// if the reporter takes it. The directories which cannot be read are not measured.
+    fn start(&self, dir: &Path, reporter: &Reporter) -> Reporter {
+        let reporter = reporter.start(dir);
+        let input_size = match reporter.takes_input_size() {
+            true => self.options.input_stats(dir).ok().map(|s| s.size),
+            false => None,
+        };
+        reporter.with_input_size(input_size)
+    }
+
+    /// Archive the directories from the queue in their formats until it is empty.
+    pub fn process(&self, queue: &WorkQueue, dest: &Path, reporter: &Reporter) {
+        let reporter = &reporter.clone().with_resume(self.options.resume.clone());
+        while let Some((dir, format, subdir)) = queue.pop() {
+            let _heartbeat = reporter.heartbeat(&dir);
+            let reporter = self.start(&dir, reporter);
+            match subdir {
+                Some(subdir) => {
+                    self.process_item(&dir, format.as_ref(), &dest.join(subdir), &reporter)
+                }
+                None => self.process_item(&dir, format.as_ref(), dest, &reporter),
+            }
+        }
+    }
+
+    /// Stage the directories of the default format from the queue and send them with their staged files
+    /// and reporters to the compression threads, until the queue is empty.
+    /// The directories of the other formats and the files are archived directly.
+    pub fn stage(
+        &self,
+        queue: &WorkQueue,
+        dest: &Path,
+        staged: &SyncSender<(PathBuf, PathBuf, Reporter)>,
+        reporter: &Reporter,
+    ) {
+        let pipeline = match &self.pipeline {
+            Some(pipeline) => pipeline,
+            None => return self.process(queue, dest, reporter),
+        };
+        let reporter = &reporter.clone().with_resume(self.options.resume.clone());
+        while let Some((dir, format, subdir)) = queue.pop() {
+            let heartbeat = reporter.heartbeat(&dir);
+            let reporter = self.start(&dir, reporter);
+            // The staged files are compressed into the common destination, so the roots are archived directly.
+            if let Some(subdir) = subdir {
+                self.process_item(&dir, format.as_ref(), &dest.join(subdir), &reporter);
+                continue;
+            }
+            if format.as_ref().is_some_and(|f| *f != self.format) || dir.is_file() {
+                self.process_item(&dir, format.as_ref(), dest, &reporter);
+                continue;
+            }
+            if self.skip_dir(&dir, &reporter) {
+                continue;
+            }
+            let staged_path = match pipeline.stage(&dir, dest, &reporter) {
+                Some(p) => p,
+                None => continue,
+            };
+            drop(heartbeat);
+            if let Err(e) = staged.send((dir, staged_path, reporter)) {
+                fs::remove_file((e.0).1).ok();
+                return;
+            }
+        }
+    }
+
+    /// Compress the staged files until all staging threads are done.
+    pub fn compress_staged(
+        &self,
+        staged: &Mutex<Receiver<(PathBuf, PathBuf, Reporter)>>,
+        dest: &Path,
+    ) {
+        let pipeline = match &self.pipeline {
+            Some(pipeline) => pipeline,
+            None => return,
+        };
+        loop {
+            let (dir, staged_path, reporter) = match staged.lock().unwrap().recv() {
+                Ok(p) => p,
+                Err(_) => break,
+            };
+            let _heartbeat = reporter.heartbeat(&dir);
+            let _permit = self.options.format_permit(&self.format);
+            pipeline.compress_staged(&staged_path, dest, &reporter);
+        }
+    }
+}
+
+/// An event of archiving a directory, which is turned into a message sent through the sender.
+/// Its `Display` is the default English message.
+#[derive(Debug)]
+pub enum ArchiveEvent<'a> {
+    /// The archive is written. The size of the archive is given in the verbose mode,
+    /// and the total size of the archived files too if the directory is archived into a single archive.
+    Complete {
+        format: &'a Format,
+        archive: &'a Path,
+        size: Option<u64>,
+        input_size: Option<u64>,
+    },
+    /// Archiving the directory failed.
+    Error {
+        format: &'a Format,
+        error: &'a io::Error,
+    },
+}
+
+impl fmt::Display for ArchiveEvent<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            ArchiveEvent::Complete {
+                format,
+                archive,
+                size,
+                input_size,
+            } => {
+                let message = Message::new((*format).clone());
+                match (size, input_size) {
+                    (Some(size), Some(input_size)) if *input_size > 0 => {
+                        message.completion_message_with_ratio(archive, *size, *input_size)
+                    }
+                    (Some(size), _) => message.completion_message_with_size(archive, *size),
+                    (None, _) => message.completion_message(archive),
+                }
+            }
+            ArchiveEvent::Error { format, error } => {
+                Message::new((*format).clone()).error_message(error)
+            }
+        };
+        write!(f, "{}", message)
+    }
+}
+
+/// Function which turns an [`ArchiveEvent`] into the message sent through the sender.
+pub type MessageFormatter = Arc<dyn Fn(&ArchiveEvent) -> String + Send + Sync>;
+
+/// Set of the categories of the messages sent through the sender,
+/// combined with `|`, like `MessageCategory::COMPLETION | MessageCategory::ERROR`.
+/// All categories are sent by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageCategory(u8);
+
+impl MessageCategory {
+    /// No messages.
+    pub const NONE: Self = Self(0);
+    /// The start and the end of the whole job, like `Total archive directory count: 3`
+    /// and `Archiving Complete!`.
+    pub const LIFECYCLE: Self = Self(1);
+    /// The completion of each directory, including the skipped and the deleted ones.
+    pub const COMPLETION: Self = Self(1 << 1);
+    /// The errors which fail a directory or the whole job.
+    pub const ERROR: Self = Self(1 << 2);
+    /// The warnings which fail nothing, like a tarball which cannot be deleted.
+    pub const WARNING: Self = Self(1 << 3);
+    /// The progress while a directory is archived, like the heartbeats and the 7z command lines.
+    pub const PROGRESS: Self = Self(1 << 4);
+    /// All messages.
+    pub const ALL: Self = Self(0b1_1111);
+
+    /// Check whether all categories of `other` are in the set.
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl Default for MessageCategory {
+    fn default() -> Self {
+        MessageCategory::ALL
+    }
+}
+
+impl BitOr for MessageCategory {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        MessageCategory(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for MessageCategory {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
 }
 
 pub struct Message {
@@ -103,6 +978,36 @@ impl Message {
         )
     }
 
+    /// Get the completion message followed by the size of the archive, like `dir1.zip (12.4 MiB)`.
+    pub fn completion_message_with_size<P: AsRef<Path>>(
+        &self,
+        target_path: P,
+        size: u64,
+    ) -> String {
+        format!(
+            "{} ({})",
+            self.completion_message(target_path),
+            format_size(size)
+        )
+    }
+
+    /// Get the completion message followed by the size of the archive, the size of the archived files
+    /// and the ratio of them, like `dir1.zip (12.4 MiB from 40.1 MiB, 31%)`.
+    pub fn completion_message_with_ratio<P: AsRef<Path>>(
+        &self,
+        target_path: P,
+        size: u64,
+        input_size: u64,
+    ) -> String {
+        format!(
+            "{} ({} from {}, {:.0}%)",
+            self.completion_message(target_path),
+            format_size(size),
+            format_size(input_size),
+            size as f64 / input_size as f64 * 100.0
+        )
+    }
+
     pub fn error_message<E: Error>(&self, error: E) -> String {
         format!(
             "{} archiving error occured!: {}",
@@ -112,14 +1017,24 @@ impl Message {
     }
 }
 
-pub fn get_compressor<T: AsRef<Path>, O: AsRef<Path>>(comp_t: Format) -> Box<dyn Process<T, O>> {
+pub fn get_compressor(comp_t: Format, options: CompressOptions) -> Arc<dyn Process> {
     return match comp_t {
-        Format::Xz => Box::new(p_xz::ProcessXz::default()),
-        Format::_7z => Box::new(p_7z::Process7z::default()),
-        Format::Zip => Box::new(p_zip::ProcessZip::default()),
+        Format::Xz => Arc::new(p_xz::ProcessXz::new(options)),
+        Format::_7z => Arc::new(p_7z::Process7z::new(options)),
+        Format::Zip => Arc::new(p_zip::ProcessZip::new(options)),
+        Format::Tar => Arc::new(p_tar::ProcessTar::new(options)),
+        Format::Tar7z => Arc::new(p_tar_7z::ProcessTar7z::new(options)),
     };
 }
 
+/// Get the pipeline of the format, if it has a staging step.
+pub fn get_pipeline(comp_t: Format, options: CompressOptions) -> Option<Arc<dyn Pipeline>> {
+    match comp_t {
+        Format::Xz => Some(Arc::new(p_xz::ProcessXz::new(options))),
+        Format::_7z | Format::Zip | Format::Tar | Format::Tar7z => None,
+    }
+}
+
 #[cfg(test)]
 pub mod message_test{
     use std::path::Path;
@@ -140,4 +1055,100 @@ pub mod message_test{
 
         assert_eq!(message, expected_messages);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heartbeat_test() {
+        let (tx, tr) = channel();
+        let reporter =
+            Reporter::new(Some(tx), None).with_heartbeat_interval(Some(Duration::from_millis(10)));
+
+        let heartbeat = reporter.heartbeat(Path::new("origin/dir1"));
+        // A slow compression.
+        thread::sleep(Duration::from_millis(100));
+        drop(heartbeat);
+
+        let messages: Vec<String> = tr.try_iter().collect();
+        assert!(!messages.is_empty());
+        assert!(messages
+            .iter()
+            .all(|m| m.starts_with("Still working on dir1 (")));
+
+        // The heartbeat stops when it is dropped.
+        thread::sleep(Duration::from_millis(50));
+        assert!(tr.try_recv().is_err());
+    }
+
+    #[test]
+    fn verbose_completion_size_test() {
+        let dest = PathBuf::from("verbose_completion_size_test");
+        fs::create_dir_all(&dest).unwrap();
+        let archive = dest.join("dir1.zip");
+        fs::write(&archive, vec![0; 2048]).unwrap();
+
+        let (tx, tr) = channel();
+        let message = Message::new(Format::Zip);
+        Reporter::new(Some(tx.clone()), None).report(&message, Ok(archive.clone()));
+        Reporter::new(Some(tx), None)
+            .with_verbose(true)
+            .report(&message, Ok(archive.clone()));
+
+        let complete = format!("zip archiving complete: {}", archive.display());
+        assert_eq!(tr.recv().unwrap(), complete);
+        assert_eq!(tr.recv().unwrap(), format!("{} (2.0 KiB)", complete));
+        fs::remove_dir_all(&dest).unwrap();
+    }
+
+    #[test]
+    fn message_formatter_test() {
+        let (tx, tr) = channel();
+        let formatter: MessageFormatter = Arc::new(|event: &ArchiveEvent| match event {
+            ArchiveEvent::Complete {
+                format, archive, ..
+            } => {
+                format!("[{}] done -> {}", format.to_string(), archive.display())
+            }
+            ArchiveEvent::Error { error, .. } => format!("failed -> {}", error),
+        });
+        let reporter = Reporter::new(Some(tx), None).with_formatter(Some(formatter));
+        let message = Message::new(Format::Zip);
+        reporter.report(&message, Ok(PathBuf::from("dest/dir1.zip")));
+        reporter.report(&message, Err(io::Error::other("broken")));
+
+        assert_eq!(tr.recv().unwrap(), "[zip] done -> dest/dir1.zip");
+        assert_eq!(tr.recv().unwrap(), "failed -> broken");
+    }
+
+    #[test]
+    fn dropped_receiver_test() {
+        let (tx, tr) = channel();
+        let reporter = Reporter::new(Some(tx), None);
+        let dir_reporter = reporter.for_dir(Path::new("origin/dir1"));
+        reporter.send(MessageCategory::LIFECYCLE, "first".to_string());
+        assert_eq!(tr.recv().unwrap(), "first");
+        drop(tr);
+
+        dir_reporter.send(MessageCategory::LIFECYCLE, "lost".to_string());
+        assert!(reporter.disconnected.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn format_all_test() {
+        let all = Format::all();
+        for format in [
+            Format::_7z,
+            Format::Xz,
+            Format::Zip,
+            Format::Tar,
+            Format::Tar7z,
+        ] {
+            assert!(all.contains(&format), "{:?}", format);
+            assert_eq!(Format::from(&format.to_string()), format);
+        }
+        assert_eq!(all.len(), 5);
+    }
+}
@@ -1,39 +1,38 @@
-use crossbeam_queue::SegQueue;
 use std::path::Path;
-use std::sync::mpsc::Sender;
-use std::sync::Arc;
 
-use crate::core::Compress;
-use crate::extra::try_send_message;
+use crate::core::{Compress, CompressOptions};
 use crate::{core::c_7z::Compress7z, Format};
 
-use super::{Message, Process};
+use super::{Message, MessageCategory, Process, Reporter};
 
 pub struct Process7z {
     message: Message,
+    options: CompressOptions,
 }
 
-impl Default for Process7z {
-    fn default() -> Self {
+impl Process7z {
+    pub fn new(options: CompressOptions) -> Self {
         Self {
             message: Message::new(Format::_7z),
+            options,
         }
     }
 }
 
-impl<T: AsRef<Path>, O: AsRef<Path>> Process<T, O> for Process7z {
-    fn process(&self, queue: Arc<SegQueue<T>>, dest: Arc<O>, sender: Option<Sender<String>>) {
-        let dest = &*dest;
-        while !queue.is_empty() {
-            let dir = match queue.pop() {
-                None => break,
-                Some(d) => d,
-            };
-            match Compress7z::compress(&dir, &dest) {
-                Ok(p) => try_send_message(&sender, self.message.completion_message(p)),
-                Err(e) => try_send_message(&sender, self.message.error_message(e)),
-            };
-        }
+impl Default for Process7z {
+    fn default() -> Self {
+        Self::new(CompressOptions::default())
+    }
+}
+
+impl Process for Process7z {
+    fn process_dir(&self, dir: &Path, dest: &Path, reporter: &Reporter) {
+        reporter.verbose(MessageCategory::PROGRESS, || {
+            let command_line = Compress7z::command_line(dir, dest, &self.options).ok()?;
+            Some(format!("7z command: {}", command_line))
+        });
+        let result = Compress7z::compress(dir, dest, &self.options);
+        reporter.report(&self.message, result);
     }
 }
 
@@ -42,11 +41,12 @@ mod tests {
 
     use super::*;
     use crate::core::test_util::{cleanup, setup, Dir};
-    use crate::extra::get_dir_list;
+    use crate::extra::{get_7z_executable_path, get_dir_list};
     use crate::process::message_test;
     use crossbeam_queue::SegQueue;
     use function_name::named;
     use std::sync::mpsc;
+    use std::sync::Arc;
     use std::thread;
 
     #[test]
@@ -63,7 +63,10 @@ mod tests {
         let arc_dest = Arc::new(dest.clone());
         thread::spawn(move || {
             let processor = Process7z::default();
-            processor.process(Arc::new(queue), arc_dest, Some(tx));
+            let reporter = Reporter::new(Some(tx), None);
+            while let Some(dir) = queue.pop() {
+                processor.process_dir(&dir, &arc_dest, &reporter);
+            }
         });
 
         let mut message = vec![];
@@ -74,4 +77,26 @@ mod tests {
         message_test::assert_messages(dest, Format::_7z, message);
         cleanup(function_name!());
     }
+
+    #[test]
+    #[named]
+    fn process_7z_verbose_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let (tx, tr) = mpsc::channel();
+
+        let reporter = Reporter::new(Some(tx), None).with_verbose(true);
+        Process7z::default().process_dir(&origin.join("dir1"), &dest, &reporter);
+
+        let expected = format!(
+            "7z command: {} a -mx=9 -t7z {} {}",
+            get_7z_executable_path().unwrap().display(),
+            dest.join("dir1.7z").display(),
+            std::env::current_dir()
+                .unwrap()
+                .join(origin.join("dir1"))
+                .display()
+        );
+        assert_eq!(tr.recv().unwrap(), expected);
+        cleanup(function_name!());
+    }
 }
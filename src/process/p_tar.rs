@@ -0,0 +1,68 @@
+use std::path::Path;
+
+use crate::{
+    core::{c_tar::write_tar_archive, CompressOptions},
+    Format,
+};
+
+use super::{Message, Process, Reporter};
+
+pub struct ProcessTar {
+    message: Message,
+    options: CompressOptions,
+}
+
+impl ProcessTar {
+    pub fn new(options: CompressOptions) -> Self {
+        Self {
+            message: Message::new(Format::Tar),
+            options,
+        }
+    }
+}
+
+impl Default for ProcessTar {
+    fn default() -> Self {
+        Self::new(CompressOptions::default())
+    }
+}
+
+impl Process for ProcessTar {
+    fn process_dir(&self, dir: &Path, dest: &Path, reporter: &Reporter) {
+        let mut tar_path = dest.join(dir.file_name().unwrap_or(dir.as_os_str()));
+        tar_path.set_extension("tar");
+        let result = write_tar_archive(&tar_path, dir, &self.options).map(|_| tar_path);
+        reporter.report(&self.message, result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::core::test_util::{cleanup, setup, tar_entries, Dir};
+    use crate::extra::get_dir_list;
+    use crate::process::message_test;
+    use function_name::named;
+    use std::sync::mpsc;
+
+    #[test]
+    #[named]
+    fn process_tar_test() {
+        let Dir { origin, dest } = setup(function_name!());
+
+        let (tx, tr) = mpsc::channel();
+        let processor = ProcessTar::default();
+        let reporter = Reporter::new(Some(tx), None);
+        for dir in get_dir_list(&origin).unwrap() {
+            processor.process_dir(&dir, &dest, &reporter);
+        }
+        drop(reporter);
+
+        message_test::assert_messages(&dest, Format::Tar, tr.iter().collect());
+        let mut entries = tar_entries(dest.join("dir1.tar"));
+        entries.sort();
+        assert_eq!(entries, vec!["dir1", "dir1/file3.png", "dir1/file5.webp"]);
+        cleanup(function_name!());
+    }
+}
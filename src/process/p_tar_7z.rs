@@ -0,0 +1,38 @@
+use std::path::Path;
+
+use crate::{
+    core::{c_tar_7z::write_tar_7z, CompressOptions},
+    Format,
+};
+
+use super::{Message, Process, Reporter};
+
+pub struct ProcessTar7z {
+    message: Message,
+    options: CompressOptions,
+}
+
+impl ProcessTar7z {
+    pub fn new(options: CompressOptions) -> Self {
+        Self {
+            message: Message::new(Format::Tar7z),
+            options,
+        }
+    }
+}
+
+impl Default for ProcessTar7z {
+    fn default() -> Self {
+        Self::new(CompressOptions::default())
+    }
+}
+
+impl Process for ProcessTar7z {
+    fn process_dir(&self, dir: &Path, dest: &Path, reporter: &Reporter) {
+        let mut name = dir.file_name().unwrap_or(dir.as_os_str()).to_os_string();
+        name.push(Format::Tar7z.extension());
+        let zip_path = dest.join(name);
+        let result = write_tar_7z(dir, &zip_path, &self.options).map(|_| zip_path);
+        reporter.report(&self.message, result);
+    }
+}
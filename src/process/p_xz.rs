@@ -1,69 +1,114 @@
 use std::{
     fs,
-    path::Path,
-    sync::{mpsc::Sender, Arc},
+    path::{Path, PathBuf},
 };
 
-use crossbeam_queue::SegQueue;
-
 use crate::{
-    core::{c_tar::CompressTar, c_xz::CompressXz, Compress},
-    extra::try_send_message,
+    core::{c_tar::CompressTar, c_xz::CompressXz, Compress, CompressOptions},
     Format,
 };
 
-use super::{Message, Process};
+use super::{Message, MessageCategory, Pipeline, Process, Reporter};
 
 pub struct ProcessXz {
     message: Message,
+    options: CompressOptions,
 }
 
-impl Default for ProcessXz {
-    fn default() -> Self {
+impl ProcessXz {
+    pub fn new(options: CompressOptions) -> Self {
         Self {
             message: Message::new(Format::Xz),
+            options,
+        }
+    }
+}
+
+impl ProcessXz {
+    /// Create the tarball of the directory in the temp directory or `dest`.
+    /// Unless it is kept, it is tracked until it is deleted, so an interrupted run removes it.
+    fn make_tar(&self, dir: &Path, dest: &Path, reporter: &Reporter) -> Option<PathBuf> {
+        let tar_dir = self.options.temp_dir.as_deref().unwrap_or(dest);
+        let tar_path = CompressTar::tar_path(dir, tar_dir);
+        if !self.options.keep_intermediate_tar {
+            self.options.scratch.register(&tar_path);
         }
+        reporter.verbose(MessageCategory::PROGRESS, || {
+            Some(format!("Tarring {}...", dir.display()))
+        });
+        match CompressTar::compress(dir, tar_dir, &self.options) {
+            Ok(p) => Some(p),
+            Err(e) => {
+                // It may be an existing tarball which is not written by this run.
+                self.options.scratch.release(&tar_path);
+                reporter.send(
+                    MessageCategory::ERROR,
+                    format!("Cannot create tarball!: {}", e),
+                );
+                reporter.notify(Err(e));
+                None
+            }
+        }
+    }
+
+    /// Compress the tarball into `dest` and delete it, unless it is kept.
+    fn compress_tar(&self, tar_path: &Path, dest: &Path, reporter: &Reporter) {
+        reporter.verbose(MessageCategory::PROGRESS, || {
+            Some(format!("Compressing {}...", tar_path.display()))
+        });
+        let result = CompressXz::compress(tar_path, dest, &self.options);
+        if self.options.keep_intermediate_tar {
+            return reporter.report(&self.message, result);
+        }
+        match fs::remove_file(tar_path) {
+            Ok(_) => self.options.scratch.release(tar_path),
+            Err(_) => reporter.send(
+                MessageCategory::WARNING,
+                "Cannot delete tarball!".to_string(),
+            ),
+        };
+        reporter.report(&self.message, result);
+    }
+}
+
+impl Default for ProcessXz {
+    fn default() -> Self {
+        Self::new(CompressOptions::default())
     }
 }
 
-impl<T: AsRef<Path>, O: AsRef<Path>> Process<T, O> for ProcessXz {
-    fn process(&self, queue: Arc<SegQueue<T>>, dest: Arc<O>, sender: Option<Sender<String>>) {
-        let dest = &*dest;
-        while !queue.is_empty() {
-            let dir = match queue.pop() {
-                None => break,
-                Some(d) => d,
-            };
-            let tar_path = match CompressTar::compress(&dir, &dest) {
-                Ok(p) => p,
-                Err(e) => {
-                    try_send_message(&sender, format!("Cannot create tarball!: {}", e));
-                    return;
-                }
-            };
-            match CompressXz::compress(&tar_path, &dest) {
-                Ok(p) => {
-                    match fs::remove_file(&tar_path) {
-                        Ok(_) => (),
-                        Err(_) => try_send_message(&sender, format!("Cannot delete tarball!")),
-                    };
-                    try_send_message(&sender, self.message.completion_message(p));
-                }
-                Err(e) => try_send_message(&sender, self.message.error_message(e)),
-            };
+impl Process for ProcessXz {
+    fn process_dir(&self, dir: &Path, dest: &Path, reporter: &Reporter) {
+        if let Some(tar_path) = self.make_tar(dir, dest, reporter) {
+            self.compress_tar(&tar_path, dest, reporter);
         }
     }
 }
 
+impl Pipeline for ProcessXz {
+    fn stage(&self, dir: &Path, dest: &Path, reporter: &Reporter) -> Option<PathBuf> {
+        self.make_tar(dir, dest, reporter)
+    }
+
+    fn compress_staged(&self, staged: &Path, dest: &Path, reporter: &Reporter) {
+        self.compress_tar(staged, dest, reporter);
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::{sync::mpsc, thread};
+    use std::{
+        sync::{mpsc, Arc},
+        thread,
+    };
 
+    use crossbeam_queue::SegQueue;
     use function_name::named;
 
     use crate::{
         core::test_util::{cleanup, setup, Dir},
-        get_dir_list, process::message_test,
+        get_dir_list,
+        process::message_test,
     };
 
     use super::*;
@@ -82,7 +127,10 @@ mod tests {
         let arc_dest = Arc::new(dest.clone());
         thread::spawn(move || {
             let processor = ProcessXz::default();
-            processor.process(Arc::new(queue), arc_dest, Some(tx));
+            let reporter = Reporter::new(Some(tx), None);
+            while let Some(dir) = queue.pop() {
+                processor.process_dir(&dir, &arc_dest, &reporter);
+            }
         });
 
         let mut message = vec![];
@@ -93,4 +141,92 @@ mod tests {
         message_test::assert_messages(dest, Format::Xz, message);
         cleanup(function_name!());
     }
+
+    #[test]
+    #[named]
+    fn process_xz_temp_dir_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let temp_dir = dest.join("temp");
+        let processor = ProcessXz::new(CompressOptions {
+            temp_dir: Some(temp_dir.clone()),
+            ..Default::default()
+        });
+        let (tx, tr) = mpsc::channel();
+
+        // The temp directory does not exist yet, so creating the tarball in it fails.
+        let reporter = Reporter::new(Some(tx), None);
+        processor.process_dir(&origin.join("dir1"), &dest, &reporter);
+        assert!(tr.try_recv().unwrap().starts_with("Cannot create tarball!"));
+
+        fs::create_dir(&temp_dir).unwrap();
+        processor.process_dir(&origin.join("dir1"), &dest, &reporter);
+
+        assert!(dest.join("dir1.tar.xz").is_file());
+        assert!(!dest.join("dir1.tar").exists());
+        assert_eq!(temp_dir.read_dir().unwrap().count(), 0);
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn pipeline_xz_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let processor = ProcessXz::default();
+        let (tx, tr) = mpsc::channel();
+        let reporter = Reporter::new(Some(tx), None);
+
+        let tar_path = processor
+            .stage(&origin.join("dir1"), &dest, &reporter)
+            .unwrap();
+        assert_eq!(tar_path, dest.join("dir1.tar"));
+        assert!(tar_path.is_file());
+
+        processor.compress_staged(&tar_path, &dest, &reporter);
+        assert!(dest.join("dir1.tar.xz").is_file());
+        assert!(!tar_path.exists());
+        assert!(tr.try_recv().unwrap().starts_with("xz archiving complete"));
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn phase_messages_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let processor = ProcessXz::default();
+        let (tx, tr) = mpsc::channel();
+        let reporter = Reporter::new(Some(tx), None).with_verbose(true);
+
+        processor.process_dir(&origin.join("dir1"), &dest, &reporter);
+        drop(reporter);
+        let messages: Vec<String> = tr.iter().collect();
+        assert_eq!(
+            messages[..2],
+            [
+                format!("Tarring {}...", origin.join("dir1").display()),
+                format!("Compressing {}...", dest.join("dir1.tar").display()),
+            ]
+        );
+        assert!(messages[2].starts_with("xz archiving complete"));
+        cleanup(function_name!());
+    }
+
+    #[test]
+    #[named]
+    fn keep_intermediate_tar_test() {
+        let Dir { origin, dest } = setup(function_name!());
+        let reporter = Reporter::new(None, None);
+        for keep in [true, false] {
+            let dest = dest.join(keep.to_string());
+            fs::create_dir(&dest).unwrap();
+            let processor = ProcessXz::new(CompressOptions {
+                keep_intermediate_tar: keep,
+                ..Default::default()
+            });
+            processor.process_dir(&origin.join("dir1"), &dest, &reporter);
+
+            assert!(dest.join("dir1.tar.xz").is_file());
+            assert_eq!(dest.join("dir1.tar").is_file(), keep);
+        }
+        cleanup(function_name!());
+    }
 }
@@ -1,44 +1,41 @@
 use std::path::Path;
 
 use crate::{
-    core::{c_zip::CompressZip, Compress},
-    extra::try_send_message,
+    core::{c_zip::CompressZip, Compress, CompressOptions},
     Format,
 };
 
-use super::{Message, Process};
+use super::{Message, Process, Reporter};
 
 pub struct ProcessZip {
     message: Message,
+    options: CompressOptions,
 }
 
-impl Default for ProcessZip {
-    fn default() -> Self {
+impl ProcessZip {
+    pub fn new(options: CompressOptions) -> Self {
         Self {
             message: Message::new(Format::Zip),
+            options,
         }
     }
 }
 
-impl<T: AsRef<Path>, O: AsRef<Path>> Process<T, O> for ProcessZip {
-    fn process(
-        &self,
-        queue: std::sync::Arc<crossbeam_queue::SegQueue<T>>,
-        dest: std::sync::Arc<O>,
-        sender: Option<std::sync::mpsc::Sender<String>>,
-    ) {
-        let dest = &*dest;
-        while !queue.is_empty() {
-            let dir = match queue.pop() {
-                None => break,
-                Some(d) => d,
-            };
+impl Default for ProcessZip {
+    fn default() -> Self {
+        Self::new(CompressOptions::default())
+    }
+}
 
-            match CompressZip::compress(dir, dest) {
-                Ok(p) => try_send_message(&sender, self.message.completion_message(p)),
-                Err(e) => try_send_message(&sender, self.message.error_message(e)),
-            }
+impl Process for ProcessZip {
+    fn process_dir(&self, dir: &Path, dest: &Path, reporter: &Reporter) {
+        if self.options.files_per_archive.is_none() {
+            let result = CompressZip::compress(dir, dest, &self.options);
+            reporter.report(&self.message, result);
+            return;
         }
+        let result = CompressZip::compress_parts(dir, dest, &self.options);
+        reporter.report_parts(&self.message, result);
     }
 }
 
@@ -51,7 +48,8 @@ mod tests {
     use crate::process::message_test;
     use crossbeam_queue::SegQueue;
     use function_name::named;
-    use std::sync::{mpsc, Arc};
+    use std::sync::mpsc;
+    use std::sync::Arc;
     use std::thread;
 
     #[test]
@@ -69,7 +67,10 @@ mod tests {
         let arc_dest = Arc::new(dest.clone());
         thread::spawn(move || {
             let processor = ProcessZip::default();
-            processor.process(Arc::new(queue), arc_dest, Some(tx));
+            let reporter = Reporter::new(Some(tx), None);
+            while let Some(dir) = queue.pop() {
+                processor.process_dir(&dir, &arc_dest, &reporter);
+            }
         });
         
         let mut message = vec![];
@@ -0,0 +1,144 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Shortest time between the samples of the throughput, so a burst of small files does not skew it.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+/// Weight of the latest sample in the smoothed throughput, from 0 to 1.
+const SMOOTHING: f64 = 0.2;
+
+/// Progress of a job in bytes, to estimate the time remaining, like a progress bar of a UI.
+/// The clones share the same state, so keep one and query it from another thread while the job runs.
+///
+/// The total is the size of the pushed directories, measured when the job starts,
+/// so the directories pulled from a [`source`](crate::Archiver::set_source) are not in it.
+/// The 7z format does not report the bytes done, like [`on_progress`](crate::ArchiveObserver::on_progress).
+///
+/// # Examples
+/// ```no_run
+/// use zip_archive::{Archiver, JobProgress};
+///
+/// let progress = JobProgress::new();
+/// let mut archiver = Archiver::new();
+/// archiver.push("origin/dir1");
+/// archiver.set_destination("dest");
+/// archiver.set_job_progress(progress.clone());
+///
+/// std::thread::spawn(move || loop {
+///     if let Some(eta) = progress.eta() {
+///         println!("About {}s left", eta.as_secs());
+///     }
+///     std::thread::sleep(std::time::Duration::from_secs(1));
+/// });
+/// archiver.archive().unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct JobProgress {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    total: Option<u64>,
+    done: u64,
+    /// Time and bytes done at the last sample of the throughput.
+    sample: Option<(Instant, u64)>,
+    /// Exponential moving average of the throughput in bytes per second.
+    rate: Option<f64>,
+}
+
+impl JobProgress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the bytes of the files added to the archives so far.
+    pub fn bytes_done(&self) -> u64 {
+        self.state.lock().unwrap().done
+    }
+
+    /// Get the total bytes of the job, or `None` if it is not started yet.
+    pub fn total_bytes(&self) -> Option<u64> {
+        self.state.lock().unwrap().total
+    }
+
+    /// Get the smoothed throughput in bytes per second,
+    /// or `None` until the first sample is taken, about half a second after the start.
+    pub fn throughput(&self) -> Option<f64> {
+        self.state.lock().unwrap().rate
+    }
+
+    /// Get the estimated time remaining from the bytes left and the smoothed throughput,
+    /// or `None` if the total or the throughput is not known yet.
+    pub fn eta(&self) -> Option<Duration> {
+        let state = self.state.lock().unwrap();
+        match (state.total, state.rate) {
+            (Some(total), Some(rate)) if rate > 0.0 => Some(Duration::from_secs_f64(
+                total.saturating_sub(state.done) as f64 / rate,
+            )),
+            _ => None,
+        }
+    }
+
+    /// Reset the progress for a job of `total` bytes starting now.
+    pub(crate) fn start(&self, total: u64) {
+        *self.state.lock().unwrap() = State {
+            total: Some(total),
+            sample: Some((Instant::now(), 0)),
+            ..Default::default()
+        };
+    }
+
+    /// Add the bytes of a file added to an archive.
+    pub(crate) fn add(&self, bytes: u64) {
+        self.add_at(bytes, Instant::now());
+    }
+
+    fn add_at(&self, bytes: u64, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        state.done += bytes;
+        let (time, done) = *state.sample.get_or_insert((now, 0));
+        let elapsed = now.saturating_duration_since(time);
+        if elapsed < SAMPLE_INTERVAL {
+            return;
+        }
+        let sample = (state.done - done) as f64 / elapsed.as_secs_f64();
+        state.rate = Some(match state.rate {
+            Some(rate) => SMOOTHING * sample + (1.0 - SMOOTHING) * rate,
+            None => sample,
+        });
+        state.sample = Some((now, state.done));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eta_test() {
+        const MB: u64 = 1 << 20;
+        let progress = JobProgress::new();
+        assert_eq!(progress.eta(), None);
+        progress.start(1000 * MB);
+        let start = Instant::now();
+        assert_eq!(progress.eta(), None);
+
+        // 20 MB/s on average, alternating between 16 and 24 MB/s every half a second.
+        let mut etas = Vec::new();
+        for i in 1..=50u32 {
+            let bytes = if i % 2 == 0 { 12 * MB } else { 8 * MB };
+            progress.add_at(bytes, start + SAMPLE_INTERVAL * i);
+            etas.extend(progress.eta());
+        }
+        assert_eq!(progress.bytes_done(), 500 * MB);
+        // 500 MB are left, which take 25 seconds.
+        let eta = progress.eta().unwrap().as_secs_f64();
+        assert!((22.0..28.0).contains(&eta), "{}", eta);
+        // The smoothed ETA changes by less than a tenth with each sample,
+        // while the throughput of each sample alone would swing it by a third.
+        assert!(etas
+            .windows(2)
+            .skip(10)
+            .all(|w| w[0].abs_diff(w[1]) < w[0] / 10));
+    }
+}
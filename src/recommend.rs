@@ -0,0 +1,147 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use crate::{
+    core::{compress_files, CompressOptions},
+    extra::{get_file_list, unique_temp_name},
+    Format,
+};
+
+/// Number of the files sampled by [`recommend_format`].
+const SAMPLE_FILE_COUNT: usize = 16;
+
+/// What [`recommend_format`] looks for in the candidate formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Objective {
+    /// The smallest archive, however long it takes.
+    #[default]
+    Smallest,
+    /// The best compression ratio per second, to balance the size and the time.
+    RatioPerSecond,
+}
+
+/// Result of the trial compression of the sample in a format.
+struct Trial {
+    format: Format,
+    size: u64,
+    seconds: f64,
+}
+
+/// Recommend the best of the `candidates` for the directory by the objective,
+/// by compressing a sample of its files with each of them and measuring the size and the time.
+///
+/// The first files of the directory, up to 16, are sampled and compressed
+/// in a temporary directory, which is removed afterward.
+/// The candidates which fail, like [`Format::_7z`] without its executable, are left out.
+/// Fails if the directory has no files or all candidates fail.
+///
+/// # Examples
+/// ```no_run
+/// use zip_archive::{recommend_format, Format, Objective};
+///
+/// let format =
+///     recommend_format("origin/dir1", &[Format::Xz, Format::Zip], Objective::Smallest).unwrap();
+/// println!("Use {:?}", format);
+/// ```
+pub fn recommend_format<P: AsRef<Path>>(
+    dir: P,
+    candidates: &[Format],
+    objective: Objective,
+) -> io::Result<Format> {
+    let dir = dir.as_ref();
+    let options = CompressOptions::default();
+    let sample = get_file_list(dir, &options)?
+        .into_iter()
+        .take(SAMPLE_FILE_COUNT)
+        .collect::<Vec<_>>();
+    if sample.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("No files to sample in {}!", dir.display()),
+        ));
+    }
+    let input_size = sample
+        .iter()
+        .map(|file| file.metadata().map(|m| m.len()))
+        .sum::<io::Result<u64>>()?;
+
+    let temp_dir = env::temp_dir().join(unique_temp_name(
+        "zip_archive_recommend",
+        dir.file_name().unwrap_or_default(),
+    ));
+    fs::create_dir_all(&temp_dir)?;
+    let trials = candidates
+        .iter()
+        .filter_map(|format| trial(&sample, dir, &temp_dir, format, &options).ok())
+        .collect::<Vec<_>>();
+    fs::remove_dir_all(&temp_dir)?;
+
+    let score = |trial: &Trial| match objective {
+        Objective::Smallest => -(trial.size as f64),
+        Objective::RatioPerSecond => {
+            input_size as f64 / trial.size.max(1) as f64 / trial.seconds.max(f64::EPSILON)
+        }
+    };
+    trials
+        .into_iter()
+        .max_by(|a, b| score(a).total_cmp(&score(b)))
+        .map(|trial| trial.format)
+        .ok_or_else(|| io::Error::other("All candidate formats failed to compress the sample!"))
+}
+
+/// Compress the sample into `temp_dir` in the format, and measure the size and the time.
+fn trial(
+    sample: &[PathBuf],
+    base: &Path,
+    temp_dir: &Path,
+    format: &Format,
+    options: &CompressOptions,
+) -> io::Result<Trial> {
+    let archive_path = temp_dir.join(format!("sample{}", format.extension()));
+    let start = Instant::now();
+    compress_files(sample, base, &archive_path, format, options)?;
+    let seconds = start.elapsed().as_secs_f64();
+    let size = archive_path.metadata()?.len();
+    fs::remove_file(&archive_path)?;
+    Ok(Trial {
+        format: format.clone(),
+        size,
+        seconds,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use function_name::named;
+
+    use super::*;
+    use crate::core::test_util::{cleanup, setup, Dir};
+
+    #[test]
+    #[named]
+    fn recommend_format_test() {
+        let Dir { origin, .. } = setup(function_name!());
+        let dir = origin.join("compressible");
+        fs::create_dir(&dir).unwrap();
+        for i in 0..4 {
+            fs::write(dir.join(format!("{}.txt", i)), "text ".repeat(10000)).unwrap();
+        }
+        let candidates = [Format::Xz, Format::Zip, Format::Tar];
+
+        for objective in [Objective::Smallest, Objective::RatioPerSecond] {
+            let format = recommend_format(&dir, &candidates, objective).unwrap();
+            assert!(candidates.contains(&format));
+        }
+        // The uncompressed tarball is never the smallest.
+        assert_ne!(
+            recommend_format(&dir, &candidates, Objective::Smallest).unwrap(),
+            Format::Tar
+        );
+        assert!(recommend_format(&dir, &[], Objective::Smallest).is_err());
+
+        cleanup(function_name!());
+    }
+}
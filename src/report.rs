@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::process::Outcome;
+use crate::{ArchiveError, InputStats, SkipReason};
+
+/// An archive written by [`Archiver::archive_report`](crate::Archiver::archive_report).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchivedDir {
+    /// The archived directory.
+    pub source: PathBuf,
+    /// The written archive.
+    pub archive: PathBuf,
+    /// Size of the archive in bytes.
+    pub size: u64,
+    /// Total size of the archived files in bytes, if it is measured.
+    /// It is not measured for each part of a split archive.
+    pub input_size: Option<u64>,
+    /// Time taken to archive the directory.
+    pub elapsed: Duration,
+}
+
+impl ArchivedDir {
+    /// Get the ratio of the size of the archive to the size of the archived files, like 0.31,
+    /// or `None` if the input size is not measured or zero, like for an empty directory.
+    pub fn ratio(&self) -> Option<f64> {
+        match self.input_size {
+            Some(input_size) if input_size > 0 => Some(self.size as f64 / input_size as f64),
+            _ => None,
+        }
+    }
+}
+
+/// Everything that happened in [`Archiver::archive_report`](crate::Archiver::archive_report).
+#[derive(Debug, Default)]
+pub struct CompressionReport {
+    /// The archives written, in the order they are done.
+    pub archives: Vec<ArchivedDir>,
+    /// The directories which failed to be archived, with the errors.
+    pub failures: Vec<(PathBuf, ArchiveError)>,
+    /// The skipped files and directories, with the reasons.
+    pub skipped: Vec<(PathBuf, SkipReason)>,
+    /// Time taken by the whole job.
+    pub elapsed: Duration,
+    /// Whether the job is cancelled by the cancel flag.
+    /// Then the report covers only the directories finished before it.
+    pub cancelled: bool,
+    /// Stats of the directories measured once for the job,
+    /// if they are cached by [`set_cache_input_stats`](crate::Archiver::set_cache_input_stats).
+    pub input_stats: HashMap<PathBuf, InputStats>,
+}
+
+impl CompressionReport {
+    pub(crate) fn new(
+        outcomes: Vec<Outcome>,
+        skipped: Vec<(PathBuf, SkipReason)>,
+        elapsed: Duration,
+        cancelled: bool,
+    ) -> Self {
+        let mut report = CompressionReport {
+            skipped,
+            elapsed,
+            cancelled,
+            ..Default::default()
+        };
+        for outcome in outcomes {
+            let source = outcome.dir.unwrap_or_default();
+            match outcome.result {
+                Ok(archive) => report.archives.push(ArchivedDir {
+                    source,
+                    size: archive.metadata().map_or(0, |m| m.len()),
+                    input_size: outcome.input_size,
+                    archive,
+                    elapsed: outcome.elapsed.unwrap_or_default(),
+                }),
+                Err(e) => report.failures.push((source, e)),
+            }
+        }
+        report
+    }
+
+    /// Whether all directories are archived without a failure or the cancellation.
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty() && !self.cancelled
+    }
+
+    /// Total size of the written archives in bytes.
+    pub fn total_size(&self) -> u64 {
+        self.archives.iter().map(|a| a.size).sum()
+    }
+}
@@ -0,0 +1,59 @@
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+use std::thread::JoinHandle;
+
+use crate::{ArchiveError, Archiver};
+
+/// Iterator over the result of each directory, made by [`Archiver::archive_iter`].
+///
+/// Each call of `next` blocks until a directory is done, while the others are archived in the background.
+/// The worker threads are joined when the iterator is exhausted or dropped.
+pub struct ArchiveResults<'a> {
+    archiver: &'a Archiver,
+    receiver: Receiver<Result<PathBuf, ArchiveError>>,
+    handles: Option<Vec<JoinHandle<()>>>,
+}
+
+impl<'a> ArchiveResults<'a> {
+    pub(crate) fn new(
+        archiver: &'a Archiver,
+        receiver: Receiver<Result<PathBuf, ArchiveError>>,
+        handles: Option<Vec<JoinHandle<()>>>,
+    ) -> Self {
+        ArchiveResults {
+            archiver,
+            receiver,
+            handles,
+        }
+    }
+
+    /// Join the worker threads and send the final messages, once.
+    fn join(&mut self) {
+        if let Some(handles) = self.handles.take() {
+            for h in handles {
+                h.join().unwrap();
+            }
+            self.archiver.finish();
+        }
+    }
+}
+
+impl Iterator for ArchiveResults<'_> {
+    type Item = Result<PathBuf, ArchiveError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.receiver.recv() {
+            Ok(result) => Some(result),
+            Err(_) => {
+                self.join();
+                None
+            }
+        }
+    }
+}
+
+impl Drop for ArchiveResults<'_> {
+    fn drop(&mut self) {
+        self.join();
+    }
+}
@@ -0,0 +1,62 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// Name of the file in the destination which the completed directories are recorded in.
+pub const PROGRESS_FILE_NAME: &str = ".progress";
+
+/// Record of the directories completed by the previous runs and the current one,
+/// kept in the progress file of the destination.
+///
+/// Each line of the file is a directory and one of its archives, separated by a tab.
+#[derive(Clone)]
+pub struct ResumeLog {
+    completed: Arc<HashMap<PathBuf, Vec<PathBuf>>>,
+    file: Arc<Mutex<File>>,
+}
+
+impl ResumeLog {
+    /// Read the progress file in `dest`, and open it to append the directories completed from now.
+    pub fn open(dest: &Path) -> io::Result<Self> {
+        let path = dest.join(PROGRESS_FILE_NAME);
+        let mut completed: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+        if path.is_file() {
+            for line in fs::read_to_string(&path)?.lines() {
+                if let Some((dir, archive)) = line.split_once('\t') {
+                    completed
+                        .entry(PathBuf::from(dir))
+                        .or_default()
+                        .push(PathBuf::from(archive));
+                }
+            }
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(ResumeLog {
+            completed: Arc::new(completed),
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    /// Check whether the directory is recorded by a previous run and all its archives are still present.
+    pub fn is_completed(&self, dir: &Path) -> bool {
+        self.completed
+            .get(dir)
+            .is_some_and(|archives| archives.iter().all(|a| a.is_file()))
+    }
+
+    /// Record the archives of the completed directory, and flush them to the disk at once,
+    /// so they survive a crash right after.
+    pub fn record(&self, dir: &Path, archives: &[PathBuf]) -> io::Result<()> {
+        let mut lines = String::new();
+        for archive in archives {
+            lines.push_str(&format!("{}\t{}\n", dir.display(), archive.display()));
+        }
+        let mut file = self.file.lock().unwrap();
+        file.write_all(lines.as_bytes())?;
+        file.sync_data()
+    }
+}
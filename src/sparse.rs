@@ -0,0 +1,102 @@
+use std::fs::File;
+use std::io::{self, Read};
+
+/// A region of a file which holds data, as its offset and length.
+pub type Region = (u64, u64);
+
+/// Get the data regions of the sparse file, found by `SEEK_DATA` and `SEEK_HOLE`.
+/// If the file ends with a hole, the last region is empty at the end of the file, like GNU tar.
+/// Returns `None` if the file has no hole, or the file system cannot find them.
+#[cfg(any(target_os = "android", target_os = "freebsd", target_os = "linux"))]
+pub fn data_regions(file: &File) -> io::Result<Option<Vec<Region>>> {
+    use std::os::unix::io::AsRawFd;
+
+    let size = file.metadata()?.len();
+    let seek = |offset: u64, whence| {
+        // SAFETY: lseek only moves the offset of the open descriptor, which is not read concurrently.
+        match unsafe { libc::lseek(file.as_raw_fd(), offset as libc::off_t, whence) } {
+            -1 => Err(io::Error::last_os_error()),
+            offset => Ok(offset as u64),
+        }
+    };
+    let mut regions = Vec::new();
+    let mut offset = 0;
+    while offset < size {
+        let data = match seek(offset, libc::SEEK_DATA) {
+            Ok(data) => data,
+            // No data after the offset, so the rest is a hole.
+            Err(e) if e.raw_os_error() == Some(libc::ENXIO) => break,
+            Err(_) => return Ok(None),
+        };
+        let hole = match seek(data, libc::SEEK_HOLE) {
+            Ok(hole) => hole.min(size),
+            Err(_) => return Ok(None),
+        };
+        regions.push((data, hole - data));
+        offset = hole;
+    }
+    seek(0, libc::SEEK_SET)?;
+    if regions.as_slice() == [(0, size)] || size == 0 {
+        return Ok(None);
+    }
+    if regions
+        .last()
+        .is_none_or(|(offset, len)| offset + len < size)
+    {
+        regions.push((size, 0));
+    }
+    Ok(Some(regions))
+}
+
+/// Get the data regions of the sparse file.
+/// The holes cannot be found on this platform, so every file is treated as non-sparse.
+#[cfg(not(any(target_os = "android", target_os = "freebsd", target_os = "linux")))]
+pub fn data_regions(_file: &File) -> io::Result<Option<Vec<Region>>> {
+    Ok(None)
+}
+
+/// Reader of the data regions of the file one after another, skipping the holes.
+pub struct RegionReader<'a> {
+    file: &'a File,
+    regions: std::slice::Iter<'a, Region>,
+    current: Region,
+}
+
+impl<'a> RegionReader<'a> {
+    pub fn new(file: &'a File, regions: &'a [Region]) -> Self {
+        RegionReader {
+            file,
+            regions: regions.iter(),
+            current: (0, 0),
+        }
+    }
+}
+
+impl Read for RegionReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.current.1 == 0 {
+            match self.regions.next() {
+                Some(region) => self.current = *region,
+                None => return Ok(0),
+            }
+        }
+        let (offset, len) = self.current;
+        let max = (buf.len() as u64).min(len) as usize;
+        let read = read_at(self.file, &mut buf[..max], offset)?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.current = (offset + read as u64, len - read as u64);
+        Ok(read)
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
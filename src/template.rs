@@ -0,0 +1,46 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use time::OffsetDateTime;
+
+/// Template of the archive file names, expanded per directory.
+/// See [`Archiver::set_name_template`](crate::Archiver::set_name_template) for the placeholders.
+#[derive(Clone, Debug)]
+pub struct NameTemplate {
+    template: String,
+    next_index: Arc<AtomicUsize>,
+}
+
+impl NameTemplate {
+    pub fn new(template: &str) -> Self {
+        NameTemplate {
+            template: template.to_string(),
+            next_index: Arc::new(AtomicUsize::new(1)),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.template
+    }
+
+    /// Take the index of the next directory, starting from 1.
+    pub fn next_index(&self) -> usize {
+        self.next_index.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Start the indices from 1 again, for a new run.
+    pub fn reset(&self) {
+        self.next_index.store(1, Ordering::Relaxed);
+    }
+
+    /// Expand the placeholders with the name of the directory, the extension and the index.
+    pub fn expand(&self, name: &str, ext: &str, index: usize) -> String {
+        self.template
+            .replace("{name}", name)
+            .replace("{ext}", ext)
+            .replace("{date}", &OffsetDateTime::now_utc().date().to_string())
+            .replace("{index}", &index.to_string())
+    }
+}
@@ -84,3 +84,48 @@ fn comp_test_all() {
     comp_test(Format::Xz);
     comp_test(Format::Zip);
 }
+
+#[test]
+#[named]
+fn compress_backend_test() {
+    use zip_archive::{Compress, CompressOptions, CompressTar, CompressXz, CompressZip};
+
+    let Dir { origin, dest, .. } = setup(function_name!());
+    let mut options = CompressOptions::default();
+    options.xz_level = 1;
+    options.excluded_dir_names = vec!["dir1".to_string()];
+
+    let zip_path = CompressZip::compress(&origin, &dest, &options).unwrap();
+    let zip = zip::ZipArchive::new(std::fs::File::open(&zip_path).unwrap()).unwrap();
+    assert_eq!(zip.len(), 5);
+    assert!(zip.file_names().all(|name| !name.contains("dir1/")));
+
+    let tar_path = CompressTar::compress(origin.join("dir3"), &dest, &options).unwrap();
+    let xz_path = CompressXz::compress(&tar_path, &dest, &options).unwrap();
+    assert_eq!(xz_path, dest.join("dir3.tar.xz"));
+    assert!(xz_path.is_file());
+
+    cleanup(function_name!());
+}
+
+#[test]
+#[named]
+fn dropped_receiver_test() {
+    let Dir {
+        origin,
+        dest,
+        tx,
+        tr,
+    } = setup(function_name!());
+
+    let archiver = get_archiver(&origin, &dest, tx, Format::Zip);
+    let receiver = std::thread::spawn(move || {
+        assert!(tr.recv().is_ok());
+    });
+    archiver.archive().unwrap();
+    receiver.join().unwrap();
+
+    assert_format(&dest, Format::Zip);
+
+    cleanup(function_name!());
+}